@@ -0,0 +1,59 @@
+extern crate criterion;
+extern crate diddir;
+extern crate tempfile;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use diddir::{Config, DIDDir};
+use std::fs;
+use std::path::Path;
+use tempfile::{tempdir, TempDir};
+
+static IDENTITIES: usize = 10_000;
+
+fn create_diddir() -> (TempDir, Config) {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    DIDDir::init(&config).unwrap();
+
+    // write the files directly, going through the API would take forever
+    for i in 0..IDENTITIES {
+        let pkid = format!("{:064x}", i);
+        write_file(&config.root_dir().join(&pkid), &format!("{{\"name\": \"Identity {}\"}}", i));
+        write_file(&config.aliases_dir().join(format!("identity-{}", i)), &pkid);
+    }
+    (dir, config)
+}
+
+fn write_file(path: &Path, data: &str) {
+    fs::write(path, data).unwrap();
+
+    // opening checks that nobody else can read the files
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap();
+    }
+}
+
+fn bench_diddir(c: &mut Criterion) {
+    let (_dir, config) = create_diddir();
+    let mut diddir = DIDDir::open(&config).unwrap();
+    let pkid = format!("{:064x}", 42);
+    let mut n = 0;
+
+    c.bench_function("open 10k", |b| b.iter(|| DIDDir::open(&config).unwrap()));
+    c.bench_function("refresh 10k unchanged", |b| b.iter(|| diddir.refresh().unwrap()));
+    c.bench_function("get_identity 10k", |b| b.iter(|| diddir.get_identity(&pkid).unwrap()));
+    c.bench_function("get_aliases 10k", |b| b.iter(|| diddir.get_aliases(&pkid).unwrap()));
+    c.bench_function("save_identity 10k", |b| b.iter(|| {
+        n += 1;
+        diddir.save_identity(&pkid, &format!("{{\"name\": \"Identity {}\"}}", n)).unwrap()
+    }));
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_diddir
+}
+criterion_main!(benches);
@@ -0,0 +1,244 @@
+use crate::hash::sha256_hex;
+use crate::storage::Storage;
+use crate::sync::{Entry, ALIAS_TOMBSTONES, IDENTITY_TOMBSTONES};
+use crate::{Alias, Pkid, PkidDerivation};
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// The archive format written by `DIDDir::export`.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// The top level key DIDDir documents keep private material under.
+static SECRETS: &str = "secrets";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExportOptions {
+    /// Keep the `secrets` of each document. Off by default so an archive
+    /// can be handed around without leaking private keys. A document that
+    /// had its secrets taken out is written anew, so it is a different
+    /// document with a different hash; documents without secrets are
+    /// exported as they are.
+    pub include_secrets: bool
+}
+
+/// What `DIDDir::import` does with entries that differ from what the
+/// DIDDir already has.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ConflictPolicy {
+    /// Import nothing if anything conflicts.
+    #[default]
+    Fail,
+    /// Keep what the DIDDir has.
+    Skip,
+    /// Replace it with what the archive has.
+    Overwrite
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Conflict {
+    /// The DIDDir has a different document under this pkid.
+    Identity(String),
+    /// The DIDDir has this alias pointing somewhere else.
+    Alias { alias: String, existing: String, incoming: String }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// Pkids written to the DIDDir.
+    pub identities: Vec<String>,
+    /// Aliases written to the DIDDir.
+    pub aliases: Vec<String>,
+    /// Everything that differed, whether skipped or overwritten.
+    pub conflicts: Vec<Conflict>
+}
+
+/// Describes an import that failed on conflicts.
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<String> = self.conflicts.iter().map(|c| match c {
+            Conflict::Identity(pkid) => pkid.to_owned(),
+            Conflict::Alias { alias, .. } => format!("alias {}", alias)
+        }).collect();
+        write!(f, "Archive conflicts with: {}", names.join(", "))
+    }
+}
+
+impl Error for ImportReport {}
+
+/// A whole keyring as a single JSON document. The manifest maps
+/// `identities/<pkid>` and `aliases/<alias>` to the SHA-256 of the entry so
+/// damage in transit is caught before anything is imported. Tombstones map
+/// the same keys to the hash of removed entries, for syncing.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Archive {
+    pub version: u32,
+    pub identities: BTreeMap<String, String>,
+    pub aliases: BTreeMap<String, String>,
+    pub manifest: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tombstones: BTreeMap<String, String>
+}
+
+impl Archive {
+
+    /// Reads everything in `storage` into an archive.
+    pub fn from_storage(storage: &dyn Storage, options: &ExportOptions) -> io::Result<Self> {
+        let mut archive = Archive {
+            version: ARCHIVE_VERSION,
+            ..Default::default()
+        };
+
+        for pkid in storage.identities()? {
+            let mut data = storage.read_identity(&pkid)?;
+            if !options.include_secrets {
+                data = strip_secrets(data);
+            }
+            archive.manifest.insert(identity_key(&pkid), sha256_hex(data.as_bytes()));
+            archive.identities.insert(pkid, data);
+        }
+        for (alias, pkid) in storage.aliases()? {
+            archive.manifest.insert(alias_key(&alias), sha256_hex(pkid.as_bytes()));
+            archive.aliases.insert(alias, pkid);
+        }
+        for pkid in storage.records(IDENTITY_TOMBSTONES)? {
+            let hash = storage.read_record(IDENTITY_TOMBSTONES, &pkid)?;
+            archive.tombstones.insert(identity_key(&pkid), hash);
+        }
+        for alias in storage.records(ALIAS_TOMBSTONES)? {
+            let hash = storage.read_record(ALIAS_TOMBSTONES, &alias)?;
+            archive.tombstones.insert(alias_key(&alias), hash);
+        }
+
+        Ok(archive)
+    }
+
+    /// Parses and validates an archive.
+    pub fn read<R: io::Read>(reader: R) -> io::Result<Self> {
+        let archive: Archive = serde_json::from_reader(reader).map_err(|e|
+            invalid(format!("Invalid DIDDir archive: {}", e)))?;
+        archive.validate()?;
+        Ok(archive)
+    }
+
+    pub fn write<W: io::Write>(&self, writer: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::other)
+    }
+
+    /// Checks the version, that every entry has a good name and matches
+    /// the manifest, and that every alias points at an identity.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.version != ARCHIVE_VERSION {
+            return Err(invalid(format!("Unsupported DIDDir archive version: {}", self.version)));
+        }
+
+        let mut expected = BTreeMap::new();
+        for (pkid, data) in self.identities.iter() {
+            Pkid::new(pkid)?;
+            expected.insert(identity_key(pkid), sha256_hex(data.as_bytes()));
+        }
+        for (alias, pkid) in self.aliases.iter() {
+            Alias::new(alias)?;
+            Pkid::new(pkid)?;
+            expected.insert(alias_key(alias), sha256_hex(pkid.as_bytes()));
+        }
+
+        for (key, hash) in expected.iter() {
+            match self.manifest.get(key) {
+                Some(h) if h == hash => {},
+                Some(_) => return Err(invalid(format!("Archive entry doesn't match manifest: {}", key))),
+                None => return Err(invalid(format!("Archive entry missing from manifest: {}", key)))
+            }
+        }
+        if let Some(key) = self.manifest.keys().find(|k| !expected.contains_key(*k)) {
+            return Err(invalid(format!("Archive manifest lists missing entry: {}", key)));
+        }
+        for key in self.tombstones.keys() {
+            match Entry::from_key(key) {
+                Some(Entry::Identity(pkid)) => { Pkid::new(&pkid)?; },
+                Some(Entry::Alias(alias)) => { Alias::new(&alias)?; },
+                None => return Err(invalid(format!("Archive has a tombstone for an unknown entry: {}", key)))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Works out what importing into `storage` would write, checking
+    /// everything up front so errors and `ConflictPolicy::Fail` leave
+    /// `storage` as it was. The report lists what to write.
+    pub(crate) fn plan_import(&self, storage: &dyn Storage, derivation: PkidDerivation,
+                              policy: ConflictPolicy) -> io::Result<ImportReport> {
+        self.validate()?;
+
+        let mut report = ImportReport::default();
+
+        for (pkid, data) in self.identities.iter() {
+            derivation.check(pkid, data)?;
+            if storage.has_identity(pkid)? {
+                if storage.read_identity(pkid)? == *data {
+                    continue;
+                }
+                report.conflicts.push(Conflict::Identity(pkid.to_owned()));
+                if policy != ConflictPolicy::Overwrite {
+                    continue;
+                }
+            }
+            report.identities.push(pkid.to_owned());
+        }
+
+        for (alias, pkid) in self.aliases.iter() {
+            if !self.identities.contains_key(pkid) && !storage.has_identity(pkid)? {
+                return Err(invalid(format!("Alias {} points at unknown identity: {}", alias, pkid)));
+            }
+            if let Ok(existing) = storage.read_alias(alias) {
+                if existing == *pkid {
+                    continue;
+                }
+                report.conflicts.push(Conflict::Alias {
+                    alias: alias.to_owned(),
+                    existing,
+                    incoming: pkid.to_owned()
+                });
+                if policy != ConflictPolicy::Overwrite {
+                    continue;
+                }
+            }
+            report.aliases.push(alias.to_owned());
+        }
+
+        if policy == ConflictPolicy::Fail && !report.conflicts.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, report));
+        }
+
+        Ok(report)
+    }
+}
+
+/// Drops the top level `secrets` of a JSON document. A document without
+/// any comes back as it was, byte for byte.
+fn strip_secrets(data: String) -> String {
+    let mut doc = match serde_json::from_str::<Value>(&data) {
+        Ok(Value::Object(doc)) => doc,
+        _ => return data
+    };
+    match doc.remove(SECRETS) {
+        Some(_) => serde_json::to_string_pretty(&doc).unwrap_or(data),
+        None => data
+    }
+}
+
+fn identity_key(pkid: &str) -> String {
+    Entry::Identity(pkid.to_owned()).key()
+}
+
+fn alias_key(alias: &str) -> String {
+    Entry::Alias(alias.to_owned()).key()
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,210 @@
+use crate::hash::sha256_hex;
+use crate::storage::Storage;
+use serde_derive::{Serialize, Deserialize};
+use std::env;
+use std::io;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Record kinds for the entries, keyed by zero padded sequence number, and
+/// for the head of the chain.
+pub(crate) static ENTRIES: &str = "audit/entries";
+pub(crate) static HEAD: &str = "audit";
+static HEAD_KEY: &str = "head";
+
+/// What the first entry chains onto.
+static GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditOp {
+    SaveIdentity,
+    RemoveIdentity,
+    SaveAlias,
+    RemoveAlias,
+    UpdateMetadata,
+    Attest,
+    Revoke,
+    RemoveNamespace
+}
+
+/// One change to the keyring. `before` and `after` are SHA-256 hashes of
+/// the document, of the pkid for aliases or of the metadata, with `None`
+/// meaning there wasn't one. For attestations `after` is the id of the
+/// attestation and `pkid` its subject.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub seq: u64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub op: AuditOp,
+    pub pkid: Option<String>,
+    pub alias: Option<String>,
+    // left out when unset so entries from before namespaces hash the same
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub pid: u32,
+    pub user: String,
+    /// `hash` of the entry before this one.
+    pub prev: String,
+    /// SHA-256 over the entry with this field left empty.
+    pub hash: String
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> io::Result<String> {
+        let mut entry = self.clone();
+        entry.hash = String::new();
+        let json = serde_json::to_string(&entry).map_err(io::Error::other)?;
+        Ok(sha256_hex(json.as_bytes()))
+    }
+}
+
+/// The end of the chain. Keeping a copy somewhere else lets `verify` also
+/// catch the log being cut back together with its head.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditHead {
+    pub seq: u64,
+    pub hash: String
+}
+
+/// Picks entries out of the audit log. Unset fields match anything.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuditQuery {
+    pub op: Option<AuditOp>,
+    pub pkid: Option<String>,
+    pub alias: Option<String>,
+    pub namespace: Option<String>,
+    /// Entries at or after this Unix timestamp.
+    pub since: Option<u64>,
+    /// Entries before this Unix timestamp.
+    pub until: Option<u64>
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.op.map(|op| op == entry.op).unwrap_or(true) &&
+            self.pkid.as_ref().map(|p| entry.pkid.as_ref() == Some(p)).unwrap_or(true) &&
+            self.alias.as_ref().map(|a| entry.alias.as_ref() == Some(a)).unwrap_or(true) &&
+            self.namespace.as_ref().map(|n| entry.namespace.as_ref() == Some(n)).unwrap_or(true) &&
+            self.since.map(|t| entry.timestamp >= t).unwrap_or(true) &&
+            self.until.map(|t| entry.timestamp < t).unwrap_or(true)
+    }
+}
+
+/// Whether the keyring has an audit log. Once it has, every change goes in.
+pub(crate) fn started(storage: &dyn Storage) -> io::Result<bool> {
+    Ok(head(storage)?.is_some())
+}
+
+/// Adds an entry to the end of the chain.
+pub(crate) fn append(storage: &mut dyn Storage, op: AuditOp, pkid: Option<&str>, alias: Option<&str>,
+                     namespace: Option<&str>, before: Option<String>, after: Option<String>) -> io::Result<()> {
+    let head = head(storage)?.unwrap_or(AuditHead {
+        seq: 0,
+        hash: GENESIS.to_owned()
+    });
+
+    let mut entry = AuditEntry {
+        seq: head.seq + 1,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs(),
+        op,
+        pkid: pkid.map(|p| p.to_owned()),
+        alias: alias.map(|a| a.to_owned()),
+        namespace: namespace.map(|n| n.to_owned()),
+        before,
+        after,
+        pid: process::id(),
+        user: user(),
+        prev: head.hash,
+        hash: String::new()
+    };
+    entry.hash = entry.compute_hash()?;
+
+    let json = serde_json::to_string(&entry).map_err(io::Error::other)?;
+    storage.write_record(ENTRIES, &entry_key(entry.seq), &json)?;
+
+    let head = AuditHead { seq: entry.seq, hash: entry.hash };
+    storage.write_record(HEAD, HEAD_KEY, &serde_json::to_string(&head).map_err(io::Error::other)?)
+}
+
+pub(crate) fn query(storage: &dyn Storage, query: &AuditQuery) -> io::Result<Vec<AuditEntry>> {
+    let mut entries = Vec::new();
+    for seq in 1..=head(storage)?.map(|h| h.seq).unwrap_or(0) {
+        let entry = read(storage, seq)?;
+        if query.matches(&entry) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Walks the whole chain, checking every entry is there, unchanged and
+/// linked to the one before, and that nothing follows the head.
+pub(crate) fn verify(storage: &dyn Storage) -> io::Result<Option<AuditHead>> {
+    let head = head(storage)?;
+    let last = head.as_ref().map(|h| h.seq).unwrap_or(0);
+
+    let mut prev = GENESIS.to_owned();
+    for seq in 1..=last {
+        let entry = read(storage, seq)?;
+        if entry.seq != seq {
+            return Err(broken(seq, "is out of place"));
+        }
+        if entry.prev != prev {
+            return Err(broken(seq, "doesn't follow the entry before it"));
+        }
+        if entry.compute_hash()? != entry.hash {
+            return Err(broken(seq, "has been modified"));
+        }
+        prev = entry.hash;
+    }
+
+    if let Some(ref head) = head {
+        if head.hash != prev {
+            return Err(broken(last, "doesn't match the head of the log"));
+        }
+    }
+    let extra = storage.records(ENTRIES)?.into_iter()
+        .filter_map(|key| key.parse::<u64>().ok())
+        .find(|seq| *seq > last);
+    if let Some(seq) = extra {
+        return Err(broken(seq, "follows the head of the log"));
+    }
+
+    Ok(head)
+}
+
+fn head(storage: &dyn Storage) -> io::Result<Option<AuditHead>> {
+    match storage.read_record(HEAD, HEAD_KEY) {
+        Ok(head) => serde_json::from_str(&head).map(Some).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData, format!("Corrupt audit log head: {}", e))),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+fn read(storage: &dyn Storage, seq: u64) -> io::Result<AuditEntry> {
+    let json = match storage.read_record(ENTRIES, &entry_key(seq)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(broken(seq, "is missing")),
+        result => result?
+    };
+    serde_json::from_str(&json).map_err(|_| broken(seq, "can't be read"))
+}
+
+fn broken(seq: u64, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+        format!("Audit log entry {} {}", seq, reason))
+}
+
+fn entry_key(seq: u64) -> String {
+    format!("{:020}", seq)
+}
+
+fn user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
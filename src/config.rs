@@ -1,58 +1,344 @@
 extern crate directories;
 
+use crate::dir::DIDDirSys;
+use crate::doc::Document;
+use crate::hash::sha256_hex;
+use crate::name::{Pkid, Profile};
+use crate::storage::fs::is_shard;
+use crate::sync::Resolver;
 use directories::ProjectDirs;
+use serde_derive::{Serialize, Deserialize};
 use std::default::Default;
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-static QUALIFIER: &'static str = "org";
-static ORGANIZATION: &'static str = "linuxfoundation";
-static APPLICATION: &'static str = "diddir";
-static ALIASES: &'static str = "aliases";
-static TMP: &'static str = "tmp";
+mod file;
+use self::file::ConfigFile;
 
-#[derive(Debug, PartialEq)]
+static QUALIFIER: &str = "org";
+static ORGANIZATION: &str = "linuxfoundation";
+static APPLICATION: &str = "diddir";
+pub(crate) static ALIASES: &str = "aliases";
+pub(crate) static TMP: &str = "tmp";
+static LOCK: &str = ".lock";
+pub(crate) static PROFILES: &str = ".profiles";
+pub(crate) static DEFAULT_PROFILE: &str = ".profile";
+static SQLITE_DB: &str = "diddir.sqlite3";
+static LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a pkid follows from the document stored under it. Anything but
+/// `None` stops a document being filed under some other identity's pkid,
+/// which would hijack that identity's aliases.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PkidDerivation {
+    /// Any pkid goes, documents don't even have to be DID documents.
+    #[default]
+    None,
+    /// SHA-256 of the document's DID.
+    Did,
+    /// `PublicKey::fingerprint` of the document's `Document::primary_key`.
+    KeyFingerprint
+}
+
+impl PkidDerivation {
+    /// The pkid `doc` has to be stored under, if there is a rule.
+    pub fn derive(&self, doc: &Document) -> Option<String> {
+        match self {
+            PkidDerivation::None => None,
+            PkidDerivation::Did => Some(sha256_hex(doc.id.as_str().as_bytes())),
+            PkidDerivation::KeyFingerprint => doc.primary_key().map(|k| k.fingerprint())
+        }
+    }
+
+    /// Checks `data` is a document that belongs under `pkid`.
+    pub fn check(&self, pkid: &str, data: &str) -> io::Result<()> {
+        if *self == PkidDerivation::None {
+            return Ok(());
+        }
+
+        let doc: Document = serde_json::from_str(data).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData,
+                format!("Identity {} is not a DID document: {}", pkid, e)))?;
+
+        match self.derive(&doc) {
+            Some(ref expected) if expected == pkid => Ok(()),
+            Some(expected) => Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("Identity {} doesn't match its document, expected pkid {}", pkid, expected))),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("Identity {} has no primary key to derive a pkid from", pkid)))
+        }
+    }
+}
+
+/// How many old versions of each identity to keep.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Retention {
+    /// Keep no history at all.
+    Off,
+    #[default]
+    KeepAll,
+    /// Keep the newest versions, including the current one.
+    KeepLast(usize),
+    /// Keep versions saved within this long, and always the current one.
+    KeepFor(#[serde(with = "secs")] Duration)
+}
+
+/// How hard the file system backend tries to get a write onto the disk
+/// before returning.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Durability {
+    /// Writes are atomic renames, but may still be in the OS cache after a
+    /// crash.
+    #[default]
+    Atomic,
+    /// Also flushes each file and its directory to disk.
+    Fsync
+}
+
+/// How documents are protected at rest.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encryption {
+    #[default]
+    None,
+    /// Reserved for keyrings encrypted with ChaCha20-Poly1305. Opening one
+    /// fails with `Unsupported` for now.
+    ChaCha20Poly1305
+}
+
+/// How identity documents are spread over the root of a file system
+/// keyring.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sharding {
+    /// All in the root.
+    #[default]
+    Flat,
+    /// In 256 dirs named after the first byte of the SHA-256 of the pkid,
+    /// e.g. `3f`, which keeps directories small in huge keyrings.
+    Sharded
+}
+
+/// What `DIDDir::open` and `DIDDir::init` keep the keyring in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// One file per identity and alias, see `FsStorage`.
+    #[default]
+    Fs,
+    /// A single SQLite database in the root dir. Needs the `sqlite`
+    /// feature.
+    Sqlite
+}
+
+/// Where a keyring lives and how it behaves. Besides the keyring in its
+/// base directory, a Config can have any number of named profiles, each a
+/// keyring of its own under `.profiles` in the base directory.
+///
+/// Serializes to the same form `from_file` reads.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(into = "ConfigFile", try_from = "ConfigFile")]
 pub struct Config {
+    base: PathBuf,
+    profile: Option<String>,
     root: PathBuf,
+    aliases_name: String,
+    tmp_name: String,
     aliases: PathBuf,
-    tmp: PathBuf
+    tmp: PathBuf,
+    lock: PathBuf,
+    lock_timeout: Duration,
+    pkid_derivation: PkidDerivation,
+    history_retention: Retention,
+    audit_log: bool,
+    default_namespace: Option<String>,
+    durability: Durability,
+    sharding: Sharding,
+    encryption: Encryption,
+    backend: Backend,
+    resolver: Resolver
 }
 
 impl Default for Config {
+    /// The keyring in the user's data directory, or the default profile
+    /// there if one was set with `set_default_profile`.
     fn default() -> Self {
-        let dirs = ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION).unwrap();
-        let root = dirs.data_local_dir().to_path_buf();
-        let mut aliases = root.clone();
-        aliases.push(ALIASES);
-        let mut tmp = root.clone();
-        tmp.push(TMP);
-
-        Config { 
-            root: root,
-            aliases: aliases,
-            tmp: tmp
-        }
+        Config::with_path(&default_base()).with_default_profile()
     }
 }
 
+fn default_base() -> PathBuf {
+    let dirs = ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION).unwrap();
+    dirs.data_local_dir().to_path_buf()
+}
+
 impl Config {
     pub fn new() -> Self {
         Config::default()
     }
 
     pub fn with_path(path: &Path) -> Self {
-        let root = PathBuf::from(path);
-        let mut aliases = root.clone();
-        aliases.push(ALIASES);
-        let mut tmp = root.clone();
-        tmp.push(TMP);
-
-        Config { 
-            root: root,
-            aliases: aliases,
-            tmp: tmp
+        Config {
+            base: PathBuf::from(path),
+            profile: None,
+            root: PathBuf::new(),
+            aliases_name: ALIASES.to_owned(),
+            tmp_name: TMP.to_owned(),
+            aliases: PathBuf::new(),
+            tmp: PathBuf::new(),
+            lock: PathBuf::new(),
+            lock_timeout: LOCK_TIMEOUT,
+            pkid_derivation: PkidDerivation::None,
+            history_retention: Retention::KeepAll,
+            audit_log: false,
+            default_namespace: None,
+            durability: Durability::Atomic,
+            sharding: Sharding::Flat,
+            encryption: Encryption::None,
+            backend: Backend::Fs,
+            resolver: Resolver::Skip
+        }.rooted_at(PathBuf::from(path))
+    }
+
+    /// Reads a config file, JSON if its name ends in `.json` and TOML
+    /// otherwise. A relative `home` is taken from the file's directory.
+    /// Settings the file leaves out keep their defaults, and without a
+    /// `profile` the default profile of the home is used. Deserializing a
+    /// Config reads nothing and so stays out of any profile not named.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        ConfigFile::read(path)?.into_config_in_default_profile()
+    }
+
+    /// The config the environment asks for: the file named by
+    /// `DIDDIR_CONFIG`, or else `config.toml` in the user's config
+    /// directory if there is one, then overridden by environment
+    /// variables, see `apply_env`.
+    pub fn load() -> io::Result<Self> {
+        let path = match std::env::var_os(file::ENV_CONFIG) {
+            Some(path) => Some(PathBuf::from(path)),
+            None => ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION)
+                .map(|dirs| dirs.config_dir().join(file::CONFIG_FILE))
+                .filter(|path| path.is_file())
+        };
+        let file = match path {
+            Some(path) => ConfigFile::read(&path)?,
+            None => ConfigFile::default()
+        };
+        file.merge(ConfigFile::from_env()?).into_config_in_default_profile()
+    }
+
+    /// Overrides settings from `DIDDIR_HOME`, `DIDDIR_PROFILE`,
+    /// `DIDDIR_BACKEND`, `DIDDIR_DURABILITY`, `DIDDIR_DEFAULT_NAMESPACE`
+    /// and `DIDDIR_RESOLVER`, whichever are set.
+    pub fn apply_env(&mut self) -> io::Result<()> {
+        *self = ConfigFile::from_env()?.apply(self.clone())?;
+        Ok(())
+    }
+
+    fn rooted_at(mut self, root: PathBuf) -> Self {
+        self.aliases = root.join(&self.aliases_name);
+        self.tmp = root.join(&self.tmp_name);
+        self.lock = root.join(LOCK);
+        self.root = root;
+        self
+    }
+
+    /// The profile the pointer in the base dir names, if any.
+    fn with_default_profile(self) -> Self {
+        match self.default_profile() {
+            Ok(Some(profile)) => self.with_profile(&profile).unwrap_or(self),
+            _ => self
         }
     }
 
+    /// The same settings, but for the keyring of profile `name`. The
+    /// profile comes into being with `DIDDir::init`.
+    pub fn with_profile(&self, name: &str) -> io::Result<Self> {
+        let profile = Profile::new(name)?;
+        let mut config = self.clone().rooted_at(self.profiles_dir().join(profile.as_str()));
+        config.profile = Some(profile.into());
+        Ok(config)
+    }
+
+    /// The keyring in the base directory itself, which has no profile name.
+    pub fn without_profile(&self) -> Self {
+        let mut config = self.clone().rooted_at(self.base.clone());
+        config.profile = None;
+        config
+    }
+
+    /// The directory profiles live in, and the keyring without a profile.
+    pub fn base_dir(&self) -> &Path {
+        self.base.as_path()
+    }
+
+    pub fn profiles_dir(&self) -> PathBuf {
+        self.base.join(PROFILES)
+    }
+
+    /// The profile this Config is for, if any.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Every profile that has been initialized, sorted.
+    pub fn profiles(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(self.profiles_dir()) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e)
+        };
+
+        let mut profiles = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                if Profile::new(name).map(|p| p.as_str() == name).unwrap_or(false) {
+                    profiles.push(name.to_owned());
+                }
+            }
+        }
+        profiles.sort();
+        Ok(profiles)
+    }
+
+    /// The profile `Config::default` picks, if one was set.
+    pub fn default_profile(&self) -> io::Result<Option<String>> {
+        match fs::read_to_string(self.base.join(DEFAULT_PROFILE)) {
+            Ok(name) => Ok(Some(Profile::new(name.trim())?.into())),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Makes `Config::default` pick profile `name`, which has to exist, or
+    /// the keyring without a profile for `None`.
+    pub fn set_default_profile(&self, name: Option<&str>) -> io::Result<()> {
+        let pointer = self.base.join(DEFAULT_PROFILE);
+        let name = match name {
+            Some(name) => Profile::new(name)?,
+            None => return match fs::remove_file(&pointer) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result
+            }
+        };
+
+        if !self.profiles_dir().join(name.as_str()).is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                format!("No such profile: {}", name)));
+        }
+        fs::write(&pointer, format!("{}\n", name))?;
+        DIDDirSys::set_permission(&pointer)
+    }
+
     pub fn root_dir(&self) -> &Path {
         self.root.as_path()
     }
@@ -64,4 +350,148 @@ impl Config {
     pub fn tmp_dir(&self) -> &Path {
         self.tmp.as_path()
     }
+
+    /// Whether `name` is taken by the aliases or tmp dir, which a custom
+    /// name for either may make a valid pkid.
+    pub(crate) fn is_subdir(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case(&self.aliases_name) || name.eq_ignore_ascii_case(&self.tmp_name)
+    }
+
+    /// Names the aliases dir inside the root, `aliases` unless changed. A
+    /// keyring using it can't have an identity of that name.
+    pub fn set_aliases_subdir(&mut self, name: &str) -> io::Result<()> {
+        self.aliases_name = subdir(name, &self.tmp_name)?;
+        *self = self.clone().rooted_at(self.root.clone());
+        Ok(())
+    }
+
+    /// Names the tmp dir inside the root, `tmp` unless changed. As with the
+    /// aliases dir, no identity can have that name.
+    pub fn set_tmp_subdir(&mut self, name: &str) -> io::Result<()> {
+        self.tmp_name = subdir(name, &self.aliases_name)?;
+        *self = self.clone().rooted_at(self.root.clone());
+        Ok(())
+    }
+
+    /// Where the `Sqlite` backend keeps its database.
+    pub fn sqlite_file(&self) -> PathBuf {
+        self.root.join(SQLITE_DB)
+    }
+
+    pub fn lock_file(&self) -> &Path {
+        self.lock.as_path()
+    }
+
+    pub fn lock_timeout(&self) -> Duration {
+        self.lock_timeout
+    }
+
+    pub fn set_lock_timeout(&mut self, timeout: Duration) {
+        self.lock_timeout = timeout;
+    }
+
+    pub fn pkid_derivation(&self) -> PkidDerivation {
+        self.pkid_derivation
+    }
+
+    pub fn set_pkid_derivation(&mut self, derivation: PkidDerivation) {
+        self.pkid_derivation = derivation;
+    }
+
+    pub fn history_retention(&self) -> Retention {
+        self.history_retention
+    }
+
+    pub fn set_history_retention(&mut self, retention: Retention) {
+        self.history_retention = retention;
+    }
+
+    pub fn audit_log(&self) -> bool {
+        self.audit_log
+    }
+
+    /// Has every change recorded in a hash chained audit log.
+    pub fn set_audit_log(&mut self, enabled: bool) {
+        self.audit_log = enabled;
+    }
+
+    pub fn default_namespace(&self) -> Option<&str> {
+        self.default_namespace.as_deref()
+    }
+
+    /// Puts aliases given without a namespace in `namespace`.
+    pub fn set_default_namespace(&mut self, namespace: Option<&str>) {
+        self.default_namespace = namespace.map(|n| n.to_owned());
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    pub fn sharding(&self) -> Sharding {
+        self.sharding
+    }
+
+    /// Lays out identities this way in keyrings made by `DIDDir::init`.
+    /// Existing keyrings keep their layout until `migrate::reshard`.
+    pub fn set_sharding(&mut self, sharding: Sharding) {
+        self.sharding = sharding;
+    }
+
+    pub fn encryption(&self) -> Encryption {
+        self.encryption
+    }
+
+    pub fn set_encryption(&mut self, encryption: Encryption) {
+        self.encryption = encryption;
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    /// How sync conflicts are settled when nothing else is asked for.
+    pub fn resolver(&self) -> Resolver {
+        self.resolver
+    }
+
+    pub fn set_resolver(&mut self, resolver: Resolver) {
+        self.resolver = resolver;
+    }
+}
+
+/// Checks `name` can be a dir in the root next to the identities: the
+/// usual name, or one that can't be a bookkeeping file or shard, and not
+/// `other`.
+fn subdir(name: &str, other: &str) -> io::Result<String> {
+    let valid = name == ALIASES || name == TMP ||
+        (Pkid::new(name).map(|n| n.as_str() == name).unwrap_or(false) && !is_shard(name));
+    if !valid || name == other {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("Can't use {} as a DIDDir subdirectory", name)));
+    }
+    Ok(name.to_owned())
+}
+
+/// Durations as seconds, fractions allowed.
+mod secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    }
 }
@@ -0,0 +1,233 @@
+use crate::Namespace;
+use crate::sync::Resolver;
+use serde::de::DeserializeOwned;
+use serde_derive::{Serialize, Deserialize};
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use super::{default_base, Backend, Config, Durability, Encryption, PkidDerivation, Retention, Sharding};
+
+/// Names the config file `Config::load` reads.
+pub(crate) static ENV_CONFIG: &str = "DIDDIR_CONFIG";
+static ENV_HOME: &str = "DIDDIR_HOME";
+static ENV_PROFILE: &str = "DIDDIR_PROFILE";
+static ENV_BACKEND: &str = "DIDDIR_BACKEND";
+static ENV_DURABILITY: &str = "DIDDIR_DURABILITY";
+static ENV_DEFAULT_NAMESPACE: &str = "DIDDIR_DEFAULT_NAMESPACE";
+static ENV_RESOLVER: &str = "DIDDIR_RESOLVER";
+
+/// The config file looked for in the user's config directory.
+pub(crate) static CONFIG_FILE: &str = "config.toml";
+
+/// Settings from a config file or the environment. Everything is optional
+/// so one layer can go on top of another, e.g.
+///
+/// ```toml
+/// home = "keyrings"
+/// profile = "work"
+/// history-retention = { keep-last = 10 }
+/// durability = "fsync"
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct ConfigFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    home: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aliases_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tmp_dir: Option<String>,
+    /// Seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_timeout: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pkid_derivation: Option<PkidDerivation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history_retention: Option<Retention>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audit_log: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default_namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    durability: Option<Durability>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sharding: Option<Sharding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encryption: Option<Encryption>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backend: Option<Backend>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolver: Option<Resolver>
+}
+
+impl ConfigFile {
+
+    /// JSON if the name ends in `.json`, TOML otherwise.
+    pub(crate) fn read(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let file: Result<ConfigFile, String> = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string()),
+            _ => toml::from_str(&text).map_err(|e| e.to_string())
+        };
+        let mut file = file.map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+            format!("Bad config file {}: {}", path.display(), e)))?;
+
+        if let Some(home) = file.home.as_mut() {
+            if home.is_relative() {
+                *home = path.parent().unwrap_or_else(|| Path::new("")).join(&home);
+            }
+        }
+        Ok(file)
+    }
+
+    /// The overrides set in the environment. Empty variables count as unset.
+    pub(crate) fn from_env() -> io::Result<Self> {
+        Ok(ConfigFile {
+            home: var(ENV_HOME)?.map(PathBuf::from),
+            profile: var(ENV_PROFILE)?,
+            backend: parse(ENV_BACKEND)?,
+            durability: parse(ENV_DURABILITY)?,
+            default_namespace: var(ENV_DEFAULT_NAMESPACE)?,
+            resolver: parse(ENV_RESOLVER)?,
+            ..Default::default()
+        })
+    }
+
+    /// These settings with the ones `over` sets taking precedence.
+    pub(crate) fn merge(self, over: ConfigFile) -> Self {
+        ConfigFile {
+            home: over.home.or(self.home),
+            profile: over.profile.or(self.profile),
+            aliases_dir: over.aliases_dir.or(self.aliases_dir),
+            tmp_dir: over.tmp_dir.or(self.tmp_dir),
+            lock_timeout: over.lock_timeout.or(self.lock_timeout),
+            pkid_derivation: over.pkid_derivation.or(self.pkid_derivation),
+            history_retention: over.history_retention.or(self.history_retention),
+            audit_log: over.audit_log.or(self.audit_log),
+            default_namespace: over.default_namespace.or(self.default_namespace),
+            durability: over.durability.or(self.durability),
+            sharding: over.sharding.or(self.sharding),
+            encryption: over.encryption.or(self.encryption),
+            backend: over.backend.or(self.backend),
+            resolver: over.resolver.or(self.resolver)
+        }
+    }
+
+    /// `config` with these settings on top. A new home keeps the profile
+    /// `config` had unless another one is set.
+    pub(crate) fn apply(self, mut config: Config) -> io::Result<Config> {
+        let profile = self.profile.or_else(|| config.profile.clone());
+        if let Some(home) = self.home {
+            config.base = home;
+        }
+        if let Some(name) = self.aliases_dir {
+            config.set_aliases_subdir(&name)?;
+        }
+        if let Some(name) = self.tmp_dir {
+            config.set_tmp_subdir(&name)?;
+        }
+        let mut config = match profile {
+            Some(profile) => config.with_profile(&profile)?,
+            None => config.without_profile()
+        };
+
+        if let Some(secs) = self.lock_timeout {
+            config.set_lock_timeout(Duration::try_from_secs_f64(secs).map_err(|e|
+                io::Error::new(io::ErrorKind::InvalidInput, format!("Bad lock timeout: {}", e)))?);
+        }
+        if let Some(derivation) = self.pkid_derivation {
+            config.set_pkid_derivation(derivation);
+        }
+        if let Some(retention) = self.history_retention {
+            config.set_history_retention(retention);
+        }
+        if let Some(enabled) = self.audit_log {
+            config.set_audit_log(enabled);
+        }
+        if let Some(namespace) = self.default_namespace {
+            config.set_default_namespace(Some(Namespace::new(&namespace)?.as_str()));
+        }
+        if let Some(durability) = self.durability {
+            config.set_durability(durability);
+        }
+        if let Some(sharding) = self.sharding {
+            config.set_sharding(sharding);
+        }
+        if let Some(encryption) = self.encryption {
+            config.set_encryption(encryption);
+        }
+        if let Some(backend) = self.backend {
+            config.set_backend(backend);
+        }
+        if let Some(resolver) = self.resolver {
+            config.set_resolver(resolver);
+        }
+        Ok(config)
+    }
+
+    /// A config from these settings alone, in the default home unless one
+    /// is given. Reads nothing from disk, so it is what deserializing does.
+    pub(crate) fn into_config(self) -> io::Result<Config> {
+        let home = self.home.clone().unwrap_or_else(default_base);
+        self.apply(Config::with_path(&home))
+    }
+
+    /// As `into_config`, but in the profile the pointer in the home names
+    /// unless one is named here.
+    pub(crate) fn into_config_in_default_profile(self) -> io::Result<Config> {
+        let named = self.profile.is_some();
+        let config = self.into_config()?;
+        Ok(if named { config } else { config.with_default_profile() })
+    }
+}
+
+impl From<Config> for ConfigFile {
+    fn from(config: Config) -> Self {
+        ConfigFile {
+            home: Some(config.base),
+            profile: config.profile,
+            aliases_dir: Some(config.aliases_name),
+            tmp_dir: Some(config.tmp_name),
+            lock_timeout: Some(config.lock_timeout.as_secs_f64()),
+            pkid_derivation: Some(config.pkid_derivation),
+            history_retention: Some(config.history_retention),
+            audit_log: Some(config.audit_log),
+            default_namespace: config.default_namespace,
+            durability: Some(config.durability),
+            sharding: Some(config.sharding),
+            encryption: Some(config.encryption),
+            backend: Some(config.backend),
+            resolver: Some(config.resolver)
+        }
+    }
+}
+
+impl TryFrom<ConfigFile> for Config {
+    type Error = io::Error;
+
+    fn try_from(file: ConfigFile) -> io::Result<Self> {
+        file.into_config()
+    }
+}
+
+fn var(name: &str) -> io::Result<Option<String>> {
+    match env::var(name) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("{} isn't valid unicode", name)))
+    }
+}
+
+/// Reads a variable holding one of the names an enum has in config files.
+fn parse<T: DeserializeOwned>(name: &str) -> io::Result<Option<T>> {
+    var(name)?.map(|value| serde_json::from_value(serde_json::Value::String(value.clone())).map_err(|_|
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} can't be {}", name, value))))
+        .transpose()
+}
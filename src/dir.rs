@@ -1,66 +1,222 @@
-use crate::Config;
-use rand;
-use rand::distributions::{Alphanumeric, Distribution};
-use std::collections::HashMap;
+use crate::{Alias, Archive, Backend, Encryption, Namespace, Config, ConflictPolicy, ExportOptions, ImportReport, Pkid, PkidDerivation, Retention, Version};
+use crate::audit::{self, AuditEntry, AuditHead, AuditOp, AuditQuery};
+use crate::git::{Git, Op};
+use crate::hash::sha256_hex;
+use crate::history;
+use crate::index::{self, Query};
+use crate::metadata::{self, Metadata};
+use crate::migrate;
+use crate::storage::{FsStorage, MemoryStorage, Storage};
+use crate::trust::{self, Attestation, SigningKey, Trust};
+use crate::sync::{self, Change, Entry, Replica, SyncState, ALIAS_TOMBSTONES, IDENTITY_TOMBSTONES};
 use std::fs;
-use std::io::{self, Read, Write};
-use std::path::{Path, PathBuf};
+use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+pub mod lock;
+use self::lock::LockMode;
+
+pub mod read_only;
+use self::read_only::{Reader, ReadOnlyDIDDir};
+
+pub mod shared;
+
+#[cfg(feature = "async")]
+pub mod nonblocking;
+
+pub mod transaction;
+use self::transaction::{Journal, Transaction};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod watch;
+#[cfg(not(target_arch = "wasm32"))]
+use self::watch::{Event, Snapshot, Watch, WatchMode};
 
 cfg_if! {
     if #[cfg(unix)] {
         pub mod unix;
-        use self::unix::DIDDirSys;
+        pub(crate) use self::unix::DIDDirSys;
     } else if #[cfg(target_os = "windows")] {
         pub mod windows;
-        use self::windows::DIDDirSys;
-    } else if #[cfg(wasm)] {
+        pub(crate) use self::windows::DIDDirSys;
+    } else if #[cfg(target_arch = "wasm32")] {
         pub mod wasm;
-        use self::wasm::DIDDirSys;
+        pub(crate) use self::wasm::DIDDirSys;
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// A keyring of DID documents, each stored under a pkid and reachable
+/// through any number of aliases.
+///
+/// Pkids and aliases are checked with `Pkid::new` and `Alias::new` before
+/// they are used, so names that could escape the keyring fail with an
+/// `InvalidInput` error wrapping a `NameError`.
+#[derive(Debug)]
 pub struct DIDDir<'a> {
-    config: &'a Config,
-    ids: HashMap<String, PathBuf>,
-    aliases: HashMap<String, String>
+    storage: Box<dyn Storage + 'a>,
+    derivation: PkidDerivation,
+    retention: Retention,
+    git: Option<Git>,
+    audit: bool,
+    namespace: Option<Namespace>,
+    #[cfg(not(target_arch = "wasm32"))]
+    watch: Option<Watch>
+}
+
+/// Two DIDDirs are equal when they see the same identities and aliases in
+/// the same place, with the same settings.
+impl<'a, 'b> PartialEq<DIDDir<'b>> for DIDDir<'a> {
+    fn eq(&self, other: &DIDDir<'b>) -> bool {
+        // storage we can't read isn't equal to anything
+        fn same<T: Ord>(a: io::Result<Vec<T>>, b: io::Result<Vec<T>>) -> bool {
+            match (a, b) {
+                (Ok(mut a), Ok(mut b)) => {
+                    a.sort();
+                    b.sort();
+                    a == b
+                },
+                _ => false
+            }
+        }
+
+        self.derivation == other.derivation &&
+            self.retention == other.retention &&
+            self.audit == other.audit &&
+            self.namespace == other.namespace &&
+            self.storage.root_dir() == other.storage.root_dir() &&
+            same(self.storage.identities(), other.storage.identities()) &&
+            same(self.storage.aliases(), other.storage.aliases())
+    }
 }
 
 impl<'a> DIDDir<'a> {
 
+    /// Opens the keyring `config` describes, in whichever backend it
+    /// names. A file system keyring with an older layout is migrated first,
+    /// see `migrate::migrate`.
     pub fn open(config: &'a Config) -> io::Result<Self>  {
+        Self::open_with(config, || FsStorage::open(config))
+    }
 
-        Self::check_dirs_exist(config)?;
-        Self::check_permissions(config.root_dir())?;
+    /// Like `open`, but the DIDDir keeps a copy of `config` and so doesn't
+    /// borrow it, e.g. to go in a `SharedDIDDir`.
+    pub fn open_owned(config: &Config) -> io::Result<DIDDir<'static>> {
+        DIDDir::open_with(config, || FsStorage::open_owned(config.clone()))
+    }
 
-        Ok(DIDDir { 
-            config: config,
-            ids: Self::read_ids(config.root_dir())?,
-            aliases: Self::read_aliases(config.aliases_dir())?
-        })
+    fn open_with<F>(config: &Config, fs: F) -> io::Result<Self>
+        where F: FnOnce() -> io::Result<FsStorage<'a>>
+    {
+        let storage: Box<dyn Storage + 'a> = match Self::backend(config)? {
+            Backend::Fs => {
+                migrate::migrate(config)?;
+                Box::new(fs()?)
+            },
+            Backend::Sqlite => Self::sqlite(config, false)?
+        };
+        let mut diddir = Self::with_storage(storage);
+        diddir.configure(config)?;
+        if config.backend() == Backend::Fs {
+            diddir.git = Git::open(config.root_dir());
+        }
+        diddir.recover()?;
+        Ok(diddir)
+    }
+
+    /// Opens the keyring `config` describes for lookups only, without
+    /// creating, migrating or rolling back anything, so it also works on a
+    /// read-only mount. Only file system keyrings can be opened this way.
+    pub fn open_read_only(config: &'a Config) -> io::Result<ReadOnlyDIDDir<'a>> {
+        Self::open_read_only_with(config, || FsStorage::open_read_only(config))
+    }
+
+    /// Like `open_read_only`, but keeping a copy of `config`, e.g. to share
+    /// the handle between threads in an `Arc`.
+    pub fn open_read_only_owned(config: &Config) -> io::Result<ReadOnlyDIDDir<'static>> {
+        DIDDir::open_read_only_with(config, || FsStorage::open_read_only_owned(config.clone()))
+    }
+
+    fn open_read_only_with<'s, F>(config: &Config, fs: F) -> io::Result<ReadOnlyDIDDir<'s>>
+        where F: FnOnce() -> io::Result<FsStorage<'s>>
+    {
+        if Self::backend(config)? != Backend::Fs {
+            return Err(io::Error::new(io::ErrorKind::Unsupported,
+                "Only file system keyrings can be opened read-only"));
+        }
+        let storage = fs()?;
+        if Journal::find(&storage)?.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                "The keyring has an unfinished transaction, open it writable to roll it back"));
+        }
+        let namespace = match config.default_namespace() {
+            Some(namespace) => Some(Namespace::new(namespace)?),
+            None => None
+        };
+        Ok(ReadOnlyDIDDir::new(storage, namespace))
     }
 
     pub fn init(config: &'a Config) -> io::Result<Self> {
-        let dirs = vec![config.root_dir(), config.aliases_dir(), config.tmp_dir()];
-
-        for d in dirs {
-            if d.is_dir() {
-                if fs::read_dir(d)?.count() > 0 {
-                    return Err(io::Error::new(io::ErrorKind::AlreadyExists,
-                        format!("Error creating (already exists): {}",
-                        d.to_str().unwrap())));
-                }
-            } else {
-                // create the dirs
-                fs::create_dir_all(d)?;
+        Self::init_with(config, || FsStorage::init(config))
+    }
+
+    /// Like `init`, keeping a copy of `config` as `open_owned` does.
+    pub fn init_owned(config: &Config) -> io::Result<DIDDir<'static>> {
+        DIDDir::init_with(config, || FsStorage::init_owned(config.clone()))
+    }
+
+    fn init_with<F>(config: &Config, fs: F) -> io::Result<Self>
+        where F: FnOnce() -> io::Result<FsStorage<'a>>
+    {
+        let storage: Box<dyn Storage + 'a> = match Self::backend(config)? {
+            Backend::Fs => Box::new(fs()?),
+            Backend::Sqlite => Self::sqlite(config, true)?
+        };
+        let mut diddir = Self::with_storage(storage);
+        diddir.configure(config)?;
+        Ok(diddir)
+    }
+
+    fn configure(&mut self, config: &Config) -> io::Result<()> {
+        self.set_pkid_derivation(config.pkid_derivation())?;
+        self.set_history_retention(config.history_retention());
+        self.set_audit_log(config.audit_log());
+        self.set_default_namespace(config.default_namespace())
+    }
+
+    /// The backend `config` names, if it can be opened with these settings.
+    fn backend(config: &Config) -> io::Result<Backend> {
+        if config.encryption() != Encryption::None {
+            return Err(io::Error::new(io::ErrorKind::Unsupported,
+                "Encrypted keyrings aren't supported yet"));
+        }
+        Ok(config.backend())
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn sqlite(config: &Config, create: bool) -> io::Result<Box<dyn Storage + 'a>> {
+        let path = config.sqlite_file();
+        if create {
+            if path.exists() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                    format!("Error creating (already exists): {}", path.display())));
             }
+            std::fs::create_dir_all(config.root_dir())?;
+            DIDDirSys::set_permission(config.root_dir())?;
+        } else if !path.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                format!("No DIDDir at: {}", path.display())));
         }
 
-        // set the permissions correctly
-        Self::set_permissions(config.root_dir())?;
+        let storage = crate::storage::SqliteStorage::open(&path)?;
+        DIDDirSys::set_permission(&path)?;
+        Ok(Box::new(storage))
+    }
 
-        // open the diddir
-        Self::open(config)
+    #[cfg(not(feature = "sqlite"))]
+    fn sqlite(_config: &Config, _create: bool) -> io::Result<Box<dyn Storage + 'a>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported,
+            "The SQLite backend needs diddir built with the sqlite feature"))
     }
 
     pub fn open_or_init(config: &'a Config) -> io::Result<Self> {
@@ -70,267 +226,853 @@ impl<'a> DIDDir<'a> {
         }
     }
 
-    pub fn save_identity(&mut self, pkid: &String, data: &String) -> io::Result<()> {
-        // get the path to a tmp file
-        let path = self.get_tmp_file_path(pkid)?;
+    /// Creates a DIDDir that keeps everything in memory.
+    pub fn in_memory() -> Self {
+        Self::with_storage(Box::new(MemoryStorage::new()))
+    }
 
-        // create the file, store the data
-        {
-            let mut file = fs::File::create(path.as_path())?;
-            file.write_all(data.as_bytes())?;
-        }
+    /// Opens a DIDDir kept in the SQLite database at `path`, creating the
+    /// database if needed.
+    #[cfg(feature = "sqlite")]
+    pub fn open_sqlite(path: &std::path::Path) -> io::Result<Self> {
+        Ok(Self::with_storage(Box::new(crate::storage::SqliteStorage::open(path)?)))
+    }
 
-        // set the permissions
-        DIDDirSys::set_permission(&path)?;
+    /// Creates a DIDDir on top of any storage backend.
+    pub fn with_storage(storage: Box<dyn Storage + 'a>) -> Self {
+        DIDDir {
+            storage,
+            derivation: PkidDerivation::None,
+            retention: Retention::KeepAll,
+            git: None,
+            audit: false,
+            namespace: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            watch: None
+        }
+    }
 
-        // atomically move the file from the tmp dir to the root dir
-        let mut root_path = PathBuf::new();
-        root_path.push(self.config.root_dir());
-        root_path.push(pkid);
-        fs::rename(path, root_path)?;
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
 
-        // reload our ids and aliases state
-        self.reload()?;
+    pub fn pkid_derivation(&self) -> PkidDerivation {
+        self.derivation
+    }
 
+    /// Enforces `derivation` from now on, after checking every identity
+    /// already stored follows it.
+    pub fn set_pkid_derivation(&mut self, derivation: PkidDerivation) -> io::Result<()> {
+        if derivation != PkidDerivation::None {
+            let _lock = self.storage.lock(LockMode::Shared)?;
+            for pkid in self.storage.identities()? {
+                derivation.check(&pkid, &self.storage.read_identity(&pkid)?)?;
+            }
+        }
+        self.derivation = derivation;
         Ok(())
     }
 
-    pub fn get_identity(&self, pkid: &String) -> io::Result<String> {
-        if let Some(path) = self.ids.get(pkid) {
-            let mut file = fs::File::open(path.as_path())?;
-            let mut data = String::new();
-            file.read_to_string(&mut data)?;
-            Ok(data)
-        } else {
-            Err(io::Error::new(io::ErrorKind::NotFound,
-                format!("No identity file found for: {}", pkid)))
-        }
+    pub fn save_identity(&mut self, pkid: &str, data: &str) -> io::Result<()> {
+        let pkid = Pkid::new(pkid)?;
+        self.derivation.check(pkid.as_str(), data)?;
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.write_identity(pkid.as_str(), data)?;
+        self.commit(Op::SaveIdentity(pkid.as_str()))
+    }
+
+    pub fn get_identity(&self, pkid: &str) -> io::Result<String> {
+        self.catch_up()?;
+        self.reader().get_identity(pkid)
+    }
+
+    /// An earlier version of an identity, numbered as in `history`.
+    pub fn get_identity_version(&self, pkid: &str, version: u64) -> io::Result<String> {
+        self.catch_up()?;
+        self.reader().get_identity_version(pkid, version)
+    }
+
+    /// Every version of an identity still kept, oldest first. The last one
+    /// is what `get_identity` returns, or its removal if it was removed.
+    pub fn history(&self, pkid: &str) -> io::Result<Vec<Version>> {
+        self.catch_up()?;
+        self.reader().history(pkid)
+    }
+
+    pub fn history_retention(&self) -> Retention {
+        self.retention
+    }
+
+    /// Applies from the next save of each identity on.
+    pub fn set_history_retention(&mut self, retention: Retention) {
+        self.retention = retention;
     }
 
     pub fn get_identities(&self) -> Option<Vec<String>> {
-        let mut ids = Vec::new();
-        for k in self.ids.keys() {
-            ids.push(k.to_owned());
-        }
-        if ids.len() == 0 {
-            return None;
-        }
-        Some(ids)
+        let _ = self.catch_up();
+        self.reader().get_identities()
     }
 
-    pub fn remove_identity(&mut self, pkid: &String) -> io::Result<()> {
-        // generate a path to a .deleted-XXXXX file in tmp dir
-        let del_path = self.get_tmp_file_path(&".deleted".to_string())?;
+    pub fn remove_identity(&mut self, pkid: &str) -> io::Result<()> {
+        let pkid = Pkid::new(pkid)?;
+        let pkid = pkid.as_str();
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
 
-        // calculate the path to the DID doc
-        let mut root_path = PathBuf::new();
-        root_path.push(self.config.root_dir());
-        root_path.push(pkid);
+        // pick up aliases other processes added since we last looked
+        self.storage.refresh()?;
 
         // if the DID doc doesn't exist, then throw an error
-        if !root_path.exists() {
-            return Err(io::Error::new(io::ErrorKind::Other, 
+        if !self.storage.has_identity(pkid)? {
+            return Err(io::Error::other(
                        "Identity file does not exist"));
         }
 
         // remove all aliases
-        if let Some(aliases) = self.get_aliases(pkid) {
-            for alias in aliases {
-                self.remove_alias(&alias)?;
-            }
-        } else {}
+        for alias in self.storage.aliases_of(pkid)? {
+            self.delete_alias(&alias)?;
+        }
 
-        // atomically move the alias file to tmp dir and delete it
-        fs::rename(root_path, &del_path)?;
-        fs::remove_file(del_path)?;
+        self.delete_identity(pkid)?;
+        self.commit(Op::RemoveIdentity(pkid))
+    }
 
-        // reload out ids and aliases state
-        self.reload()?;
+    /// Looks up `alias`, which is taken to be in the default namespace
+    /// unless it names one, as in `work/alice`.
+    pub fn get_pkid_from_alias(&self, alias: &str) -> io::Result<String> {
+        self.catch_up()?;
+        self.reader().get_pkid_from_alias(alias)
+    }
 
-        Ok(())
+    pub fn save_alias(&mut self, alias: &str, pkid: &str) -> io::Result<()> {
+        let alias = Alias::qualified(alias, self.default_namespace())?;
+        let pkid = Pkid::new(pkid)?;
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.write_alias(alias.as_str(), pkid.as_str())?;
+        self.commit(Op::SaveAlias(alias.as_str(), pkid.as_str()))
     }
 
-    pub fn get_pkid_from_alias(&self, alias: &String) -> io::Result<String> {
-        if let Some(pkid) = self.aliases.get(alias) {
-            Ok(pkid.to_owned())
-        } else {
-            Err(io::Error::new(io::ErrorKind::NotFound,
-                format!("No identity found for: {}", alias)))
-        }
+    pub fn remove_alias(&mut self, alias: &str) -> io::Result<()> {
+        let alias = Alias::qualified(alias, self.default_namespace())?;
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.delete_alias(alias.as_str())?;
+        self.commit(Op::RemoveAlias(alias.as_str()))
     }
 
-    pub fn save_alias(&mut self, alias: &String, pkid: &String) -> io::Result<()> {
-        // get the path to a tmp file
-        let path = self.get_tmp_file_path(alias)?;
+    pub fn get_aliases(&self, pkid: &str) -> Option<Vec<String>> {
+        let _ = self.catch_up();
+        self.reader().get_aliases(pkid)
+    }
 
-        // create the file, store the data
-        {
-            let mut file = fs::File::create(path.as_path())?;
-            file.write_all(pkid.as_bytes())?;
+    /// What we know about an identity besides its document.
+    pub fn metadata(&self, pkid: &str) -> io::Result<Metadata> {
+        self.catch_up()?;
+        self.reader().metadata(pkid)
+    }
+
+    /// Changes the metadata of an identity, e.g.
+    ///
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// # let mut diddir = diddir::DIDDir::in_memory();
+    /// diddir.update_metadata("alice", |m| {
+    ///     m.trust = diddir::TrustLevel::Full;
+    ///     m.tags.insert("work".to_string());
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn update_metadata<F: FnOnce(&mut Metadata)>(&mut self, pkid: &str, f: F) -> io::Result<()> {
+        let pkid = Pkid::new(pkid)?;
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        if !self.storage.has_identity(pkid.as_str())? {
+            return Err(crate::storage::not_found_identity(pkid.as_str()));
         }
 
-        // set the permissions
-        DIDDirSys::set_permission(&path)?;
+        let mut metadata = metadata::read(self.storage.as_ref(), pkid.as_str())?;
+        f(&mut metadata);
+        if self.write_metadata(pkid.as_str(), &metadata)? {
+            self.commit(Op::UpdateMetadata(pkid.as_str()))?;
+        }
+        Ok(())
+    }
+
+    /// Signs, as `issuer`, that the current document of `subject` belongs
+    /// to alias `name`, which has to point at it. Pointing the alias
+    /// elsewhere voids the attestation, as does changing the document. `key` is the id of the issuer's Ed25519 key that
+    /// `signing_key` is the private half of. With a `depth` above 0 the
+    /// subject is also trusted to vouch for others, that many levels deep.
+    pub fn attest(&mut self, issuer: &str, key: &str, signing_key: &SigningKey, subject: &str, name: &str,
+                  depth: u8) -> io::Result<Attestation> {
+        self.sign_attestation(issuer, key, signing_key, subject, name, depth, false)
+    }
+
+    /// Takes back whatever `issuer` attested about `subject` before.
+    pub fn revoke_attestation(&mut self, issuer: &str, key: &str, signing_key: &SigningKey,
+                              subject: &str) -> io::Result<Attestation> {
+        self.sign_attestation(issuer, key, signing_key, subject, "", 0, true)
+    }
+
+    /// Stores an attestation made elsewhere, after checking it was signed by
+    /// its issuer, whose document has to be in this DIDDir.
+    pub fn add_attestation(&mut self, attestation: &Attestation) -> io::Result<()> {
+        let issuer = Pkid::new(&attestation.issuer)?;
+        Pkid::new(&attestation.subject)?;
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        attestation.verify(&self.storage.read_identity(issuer.as_str())?)?;
+        self.attestation_added(attestation)?;
+        self.commit(Op::Attest(&attestation.issuer, &attestation.subject))
+    }
+
+    /// Every attestation about `subject`, oldest first, whether it still
+    /// holds or not.
+    pub fn attestations(&self, subject: &str) -> io::Result<Vec<Attestation>> {
+        self.catch_up()?;
+        self.reader().attestations(subject)
+    }
+
+    /// Works out whether `pkid` can be trusted, starting from our own
+    /// identities, those with `TrustLevel::Ultimate` metadata, and following
+    /// the attestations that still hold.
+    pub fn trust_of(&self, pkid: &str) -> io::Result<Trust> {
+        self.catch_up()?;
+        self.reader().trust_of(pkid)
+    }
+
+    /// The pkids of every identity matching `query`, sorted.
+    ///
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// # let diddir = diddir::DIDDir::in_memory();
+    /// // which identity owns this Ed25519 key?
+    /// let query = diddir::Query {
+    ///     public_key: Some("H3C2AVvLMv6gmMNam3uVAjZpfkcJCwDwnZn6z3wXmqPV".to_string()),
+    ///     ..Default::default()
+    /// };
+    /// let owners = diddir.query(&query)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn query(&self, query: &Query) -> io::Result<Vec<String>> {
+        self.catch_up()?;
+        self.reader().query(query)
+    }
 
-        // atomically move the file from the tmp dir to the ids dir
-        let mut alias_path = PathBuf::new();
-        alias_path.push(self.config.aliases_dir());
-        alias_path.push(alias);
-        fs::rename(path, alias_path)?;
+    /// Rebuilds the search index from the documents, e.g. after editing
+    /// them by hand. `query` copes with a stale index, just more slowly.
+    pub fn reindex(&mut self) -> io::Result<()> {
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.storage.refresh()?;
+        for pkid in self.storage.identities()? {
+            let data = self.storage.read_identity(&pkid)?;
+            index::update(self.storage.as_mut(), &pkid, &data)?;
+        }
+        Ok(())
+    }
 
-        // reload our ids and aliases state
-        self.reload()?;
+    pub fn default_namespace(&self) -> Option<&str> {
+        self.namespace.as_ref().map(|n| n.as_str())
+    }
 
+    /// Puts aliases given without a namespace in `namespace` from now on.
+    pub fn set_default_namespace(&mut self, namespace: Option<&str>) -> io::Result<()> {
+        self.namespace = match namespace {
+            Some(namespace) => Some(Namespace::new(namespace)?),
+            None => None
+        };
         Ok(())
     }
 
-    pub fn remove_alias(&mut self, alias: &String) -> io::Result<()> {
-        // generate a path to a .deleted-XXXXX file in tmp dir
-        let del_path = self.get_tmp_file_path(&".deleted".to_string())?;
+    /// Every namespace with an alias in it, sorted.
+    pub fn namespaces(&self) -> io::Result<Vec<String>> {
+        self.catch_up()?;
+        self.reader().namespaces()
+    }
+
+    /// The aliases in `namespace`, or those without one for `None`, with
+    /// the pkids they point at, sorted.
+    pub fn aliases_in(&self, namespace: Option<&str>) -> io::Result<Vec<(String, String)>> {
+        self.catch_up()?;
+        self.reader().aliases_in(namespace)
+    }
 
-        // calculate the path to the alias file
-        let mut alias_path = PathBuf::new();
-        alias_path.push(self.config.aliases_dir());
-        alias_path.push(alias);
+    /// Removes every alias in `namespace` and returns how many there were.
+    pub fn remove_namespace(&mut self, namespace: &str) -> io::Result<usize> {
+        let namespace = Namespace::new(namespace)?;
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.storage.refresh()?;
 
-        // if the alias file doesn't exist, then throw an error
-        if !alias_path.exists() {
-            return Err(io::Error::new(io::ErrorKind::Other, 
-                       "Alias file does not exist"));
+        let aliases: Vec<_> = self.storage.aliases()?.into_iter()
+            .map(|(alias, _)| alias)
+            .filter(|alias| Alias::new(alias).map(|a| a.namespace() == Some(namespace.as_str())).unwrap_or(false))
+            .collect();
+        for alias in aliases.iter() {
+            self.delete_alias(alias)?;
         }
+        if !aliases.is_empty() && self.audited()? {
+            audit::append(self.storage.as_mut(), AuditOp::RemoveNamespace, None, None, Some(namespace.as_str()),
+                None, None)?;
+        }
+        self.commit(Op::RemoveNamespace(namespace.as_str()))?;
+        Ok(aliases.len())
+    }
 
-        // atomically move the alias file to tmp dir and delete it
-        fs::rename(alias_path, &del_path)?;
-        fs::remove_file(del_path)?;
+    /// Writes every identity and alias out as a versioned `Archive`.
+    pub fn export<W: io::Write>(&self, writer: W, options: &ExportOptions) -> io::Result<()> {
+        self.catch_up()?;
+        self.reader().export(writer, options)
+    }
 
-        // reload out ids and aliases state
-        self.reload()?;
+    /// Reads an archive written by `export` into this DIDDir. The archive is
+    /// validated and checked for conflicts before anything is written, and
+    /// then written as one transaction.
+    ///
+    /// With `ConflictPolicy::Fail` any conflict fails the import with an
+    /// `AlreadyExists` error wrapping the `ImportReport`, which lists them.
+    pub fn import<R: io::Read>(&mut self, reader: R, policy: ConflictPolicy) -> io::Result<ImportReport> {
+        let archive = Archive::read(reader)?;
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.storage.refresh()?;
+        self.roll_back()?;
+        let report = archive.plan_import(self.storage.as_ref(), self.derivation, policy)?;
 
-        Ok(())
+        let changes: Vec<Change> = report.identities.iter()
+            .map(|pkid| Change::Put(Entry::Identity(pkid.to_owned()), archive.identities[pkid].to_owned()))
+            .chain(report.aliases.iter()
+                .map(|alias| Change::Put(Entry::Alias(alias.to_owned()), archive.aliases[alias].to_owned())))
+            .collect();
+        if !changes.is_empty() {
+            self.apply_journaled(&changes)?;
+        }
+        self.commit(Op::Import(report.identities.len(), report.aliases.len()))?;
+        Ok(report)
     }
 
-    pub fn get_aliases(&self, pkid: &String) -> Option<Vec<String>> {
-        let mut aliases: Vec<String> = Vec::new();
-        
-        for (alias, id) in self.aliases.iter() {
-            if id == pkid {
-                aliases.push(alias.to_owned());
+    /// Copies an identity with its aliases and metadata into `other`, e.g.
+    /// the DIDDir of another profile, as one transaction. Fails with
+    /// `AlreadyExists` if `other` has a different document under the same
+    /// pkid, or if one of the aliases points elsewhere in `other`.
+    pub fn copy_identity_to(&self, pkid: &str, other: &mut DIDDir) -> io::Result<()> {
+        let pkid = Pkid::new(pkid)?;
+        let pkid = pkid.as_str();
+        let (data, aliases, mut metadata) = {
+            let _lock = self.storage.lock(LockMode::Shared)?;
+            let data = self.storage.read_identity(pkid)?;
+            let aliases = self.storage.aliases_of(pkid)?.iter()
+                .map(|alias| Ok(Alias::new(alias)?.to_absolute()))
+                .collect::<io::Result<Vec<_>>>()?;
+            (data, aliases, metadata::read(self.storage.as_ref(), pkid)?)
+        };
+        // never saved with metadata kept, so saved just now as far as
+        // `other` knows
+        if metadata.created == 0 {
+            metadata.created = metadata::now();
+            metadata.updated = metadata.created;
+        }
+
+        other.transaction(|tx| {
+            match tx.get_identity(pkid) {
+                Ok(ref existing) if *existing != data => return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                    format!("Identity {} already exists with a different document", pkid))),
+                _ => {}
+            }
+            for alias in aliases.iter() {
+                match tx.get_pkid_from_alias(alias) {
+                    Ok(ref target) if target != pkid => return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                        format!("Alias {} already points at {}", alias, target))),
+                    _ => {}
+                }
             }
+            tx.save_identity(pkid, &data)?;
+            aliases.iter().try_for_each(|alias| tx.save_alias(alias, pkid))?;
+            tx.update_metadata(pkid, |m| *m = metadata)
+        })
+    }
+
+    /// Copies an identity into `other` like `copy_identity_to`, then
+    /// removes it here. A failure part way leaves it in both, never in
+    /// neither.
+    pub fn move_identity_to(&mut self, pkid: &str, other: &mut DIDDir) -> io::Result<()> {
+        self.copy_identity_to(pkid, other)?;
+        self.remove_identity(pkid)
+    }
+
+    /// Stages the changes `f` makes and applies them all together once it
+    /// returns `Ok`. If `f` or any write fails, nothing is changed. A
+    /// transaction cut short by a crash is rolled back the next time the
+    /// DIDDir is opened.
+    ///
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// # let mut diddir = diddir::DIDDir::in_memory();
+    /// diddir.transaction(|tx| {
+    ///     tx.save_identity("alice", "{}")?;
+    ///     tx.save_alias("alice-work", "alice")?;
+    ///     tx.save_alias("alice-home", "alice")
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn transaction<T, F>(&mut self, f: F) -> io::Result<T>
+        where F: FnOnce(&mut Transaction) -> io::Result<T>
+    {
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.storage.refresh()?;
+        self.roll_back()?;
+
+        let (value, (changes, metadata)) = {
+            let mut tx = Transaction::new(self.storage.as_ref(), self.derivation, self.default_namespace());
+            let value = f(&mut tx)?;
+            (value, tx.into_changes()?)
+        };
+        if changes.is_empty() && metadata.is_empty() {
+            return Ok(value);
+        }
+        if !changes.is_empty() {
+            self.apply_journaled(&changes)?;
         }
-        if aliases.len() == 0 {
-            return None;
+        for (pkid, metadata) in metadata.iter() {
+            self.write_metadata(pkid, metadata)?;
         }
+        self.commit(Op::Batch(changes.len() + metadata.len()))?;
+        Ok(value)
+    }
+
+    /// Whether this DIDDir was asked to start an audit log. A keyring that
+    /// has one gets every change added to it either way.
+    pub fn audit_log(&self) -> bool {
+        self.audit
+    }
 
-        Some(aliases)
+    /// Records every change from now on in a hash chained audit log. Once
+    /// the keyring has a log, every DIDDir opened on it adds to it, so
+    /// switching it off again doesn't stop it.
+    pub fn set_audit_log(&mut self, enabled: bool) {
+        self.audit = enabled;
     }
 
-    fn get_tmp_file_path(&self, name: &String) -> io::Result<PathBuf> {
-        for _ in 1..100 {
-            // generate a random string to append to the name
-            let mut rng = rand::thread_rng();
-            let rnd_ext: String = Alphanumeric.sample_iter(&mut rng).take(6).collect();
+    /// Reads back the audit log entries matching `query`, oldest first.
+    pub fn audit_entries(&self, query: &AuditQuery) -> io::Result<Vec<AuditEntry>> {
+        self.catch_up()?;
+        self.reader().audit_entries(query)
+    }
+
+    /// Checks no audit log entry has been changed, removed or added out of
+    /// turn and returns the head of the log, if there is one. Comparing the
+    /// head with a copy kept elsewhere also catches the log being cut short.
+    pub fn verify_audit_log(&self) -> io::Result<Option<AuditHead>> {
+        self.catch_up()?;
+        self.reader().verify_audit_log()
+    }
 
-            // calculate the full path
-            let mut tmp_path = PathBuf::new();
-            tmp_path.push(self.config.tmp_dir());
-            tmp_path.push(format!("{}-{}", name, rnd_ext));
+    /// Turns the DIDDir root into a git repository, committing what is
+    /// already there. From then on every change is a commit, also for
+    /// DIDDirs opened later on the same root.
+    pub fn init_git(&mut self) -> io::Result<()> {
+        let root = match self.storage.root_dir() {
+            Some(root) => root.to_path_buf(),
+            None => return Err(io::Error::new(io::ErrorKind::Unsupported,
+                "DIDDir storage has no directory to keep in git"))
+        };
 
-            if !tmp_path.exists() {
-                return Ok(tmp_path);
-            }
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.git = Some(Git::init(&root)?);
+        Ok(())
+    }
+
+    pub fn is_git(&self) -> bool {
+        self.git.is_some()
+    }
+
+    /// Pushes the keyring to `branch` of a git remote.
+    pub fn git_push(&self, remote: &str, branch: &str) -> io::Result<()> {
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        self.git()?.push(remote, branch)
+    }
+
+    /// Merges `branch` of a git remote into the keyring. The pulled tree is
+    /// checked out on the side and what changed there since we last merged
+    /// goes in as one transaction, so it is checked and recorded like any
+    /// other change; attestations we don't have are added too. Identities
+    /// and aliases changed on both sides fail the pull without changing
+    /// anything.
+    pub fn git_pull(&mut self, remote: &str, branch: &str) -> io::Result<()> {
+        let fetched = match self.git()?.fetch(remote, branch)? {
+            Some(fetched) => fetched,
+            None => return Ok(())
+        };
+        let result = self.pull(&fetched);
+        self.git()?.clear_staging()?;
+        result?;
+
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.git()?.merge_op(&fetched, &Op::Pull(remote, branch))
+    }
+
+    /// Picks up changes made by other processes. Cheap when nothing changed:
+    /// directories whose mtime hasn't moved aren't rescanned.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        self.storage.refresh()
+    }
+
+    #[deprecated(note = "use `refresh`, which only rescans what changed")]
+    pub fn reload(&mut self) -> io::Result<()> {
+        self.refresh()
+    }
+
+    /// Starts watching the DIDDir for changes made by other processes, which
+    /// from then on show up in lookups without calling `refresh`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch(&mut self, mode: WatchMode) -> io::Result<()> {
+        let dirs = self.storage.watch_dirs();
+        if dirs.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported,
+                "DIDDir storage can't be watched"));
         }
-        Err(io::Error::new(io::ErrorKind::Other, 
-            "Could not calculate unique filename for tmp file."))
+
+        let dirs: Vec<_> = dirs.iter().map(|d| d.as_path()).collect();
+        let snapshot = Snapshot::new(self.storage.as_ref())?;
+        self.watch = Some(Watch::new(&dirs, mode, snapshot)?);
+        Ok(())
     }
 
-    fn check_dirs_exist(config: &'a Config) -> io::Result<()> {
-        let dirs = vec![config.root_dir(), config.aliases_dir(), config.tmp_dir()];
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn watch_mode(&self) -> Option<WatchMode> {
+        self.watch.as_ref().map(|w| w.mode())
+    }
+
+    /// The changes seen since the last call, without blocking. Changes made
+    /// through this DIDDir are reported too.
+    ///
+    /// Lookups pick up changes on their own while the DIDDir is watched, so
+    /// this is only needed to hear about them.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn poll_events(&mut self) -> io::Result<Vec<Event>> {
+        self.wait_events(Duration::from_secs(0))
+    }
+
+    /// Like `poll_events` but waits up to `timeout` for something to change
+    /// if nothing has yet.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn wait_events(&mut self, timeout: Duration) -> io::Result<Vec<Event>> {
+        let watch = match self.watch {
+            Some(ref watch) => watch,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "DIDDir is not being watched"))
+        };
+
+        self.catch_up()?;
+        let events = watch.take_events();
+        if !events.is_empty() || timeout == Duration::from_secs(0) {
+            return Ok(events);
+        }
+
+        if watch.wait(timeout)? {
+            self.storage.refresh()?;
+            watch.record(Snapshot::new(self.storage.as_ref())?);
+        }
+        Ok(watch.take_events())
+    }
 
-        for d in dirs {
-            if !d.is_dir() {
-                return Err(io::Error::new(io::ErrorKind::NotFound,
-                    format!("No DIDDir directory at: {}",
-                    d.to_str().unwrap())));
+    /// Rescans the storage if the watcher saw anything change since we last
+    /// looked, queueing up the events for `poll_events`.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn catch_up(&self) -> io::Result<()> {
+        if let Some(ref watch) = self.watch {
+            if watch.wait(Duration::from_secs(0))? {
+                self.storage.refresh()?;
+                watch.record(Snapshot::new(self.storage.as_ref())?);
             }
         }
+        Ok(())
+    }
 
+    #[cfg(target_arch = "wasm32")]
+    fn catch_up(&self) -> io::Result<()> {
         Ok(())
     }
 
-    fn set_permissions(path: &Path) -> io::Result<()> {
-        DIDDirSys::set_permission(path)?;
-        
-        if path.is_dir() {
-            // check all contents of directory
-            for entry in fs::read_dir(path)? {
-                let entry = entry?;
-                Self::set_permissions(&entry.path())?;
+    /// The lookups, over this DIDDir's storage and default namespace.
+    fn reader(&self) -> Reader<'_> {
+        Reader::new(self.storage.as_ref(), self.namespace.as_ref())
+    }
+}
+
+// every change goes through here, with names already checked and the lock
+// already held
+impl<'a> DIDDir<'a> {
+
+    fn git(&self) -> io::Result<&Git> {
+        self.git.as_ref().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+            "DIDDir is not kept in git"))
+    }
+
+    fn commit(&self, op: Op) -> io::Result<()> {
+        match self.git {
+            Some(ref git) => git.commit_op(&op),
+            None => Ok(())
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn sign_attestation(&mut self, issuer: &str, key: &str, signing_key: &SigningKey, subject: &str, name: &str,
+                        depth: u8, revoked: bool) -> io::Result<Attestation> {
+        let issuer = Pkid::new(issuer)?;
+        let subject = Pkid::new(subject)?;
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        let issuer_doc = self.storage.read_identity(issuer.as_str())?;
+        let subject_doc = self.storage.read_identity(subject.as_str())?;
+        let name = if revoked {
+            String::new()
+        } else {
+            let alias = Alias::qualified(name, self.default_namespace())?;
+            match self.storage.read_alias(alias.as_str()) {
+                Ok(ref target) if target == subject.as_str() => {},
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Alias {} doesn't point at {}", alias.as_str(), subject.as_str())))
             }
+            alias.into()
+        };
+
+        let issued = trust::next_issued(self.storage.as_ref(), issuer.as_str(), subject.as_str())?;
+        let mut attestation = Attestation {
+            id: String::new(),
+            issuer: issuer.into(),
+            key: key.to_owned(),
+            subject: subject.into(),
+            subject_hash: sha256_hex(subject_doc.as_bytes()),
+            name,
+            depth,
+            issued,
+            revoked,
+            signature: String::new()
+        };
+        attestation.sign(&issuer_doc, signing_key)?;
+        self.attestation_added(&attestation)?;
+        if revoked {
+            self.commit(Op::Revoke(&attestation.issuer, &attestation.subject))?;
+        } else {
+            self.commit(Op::Attest(&attestation.issuer, &attestation.subject))?;
         }
-        
-        Ok(())
+        Ok(attestation)
     }
 
-    fn check_permissions(path: &Path) -> io::Result<()> {
-        DIDDirSys::check_permission(path)?;
-        
-        if path.is_dir() {
-            // check all contents of directory
-            for entry in fs::read_dir(path)? {
-                let entry = entry?;
-                Self::check_permissions(&entry.path())?;
+    /// Applies what changed in git commit `fetched` since the last commit
+    /// we have in common with it, or everything in it if there is none.
+    fn pull(&mut self, fetched: &str) -> io::Result<()> {
+        let theirs = self.staged(fetched, "theirs")?;
+        let base = match self.git()?.merge_base(fetched)? {
+            Some(base) => Some(SyncState::of(&self.staged(&base, "base")?.entries()?)),
+            None => None
+        };
+
+        let (ours, r) = (self.entries()?, theirs.entries()?);
+        let plan = sync::plan_entries(self, &theirs, &ours, &r, base.as_ref())?;
+        if !plan.conflicts.is_empty() {
+            let entries: Vec<String> = plan.conflicts.iter().map(|c| c.entry.key()).collect();
+            return Err(io::Error::other(format!("Changed here and in the pulled tree: {}", entries.join(", "))));
+        }
+        let mut changes = plan.to_left;
+        sync::apply(self, &mut changes, &ours)?;
+
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        let have = self.storage.records(trust::ATTESTATIONS)?;
+        for attestation in trust::all(theirs.storage())? {
+            if !have.contains(&attestation.id) {
+                self.attestation_added(&attestation)?;
             }
         }
-        
         Ok(())
     }
 
-    fn reload(&mut self) -> io::Result<()> {
-        self.ids = Self::read_ids(self.config.root_dir())?;
-        self.aliases = Self::read_aliases(self.config.aliases_dir())?;
-        Ok(())
+    /// Opens the tree of git commit `rev`, checked out on the side, laid out
+    /// as ours is.
+    fn staged(&self, rev: &str, name: &str) -> io::Result<DIDDir<'static>> {
+        let root = self.git()?.check_out(rev, name)?;
+        let mut config = Config::with_path(&root);
+        // the aliases dir is the second one watched
+        if let Some(aliases) = self.storage.watch_dirs().get(1).and_then(|dir| dir.file_name()) {
+            config.set_aliases_subdir(&aliases.to_string_lossy())?;
+        }
+        // git keeps no empty directories
+        for dir in [config.aliases_dir(), config.tmp_dir()].iter() {
+            fs::create_dir_all(dir)?;
+            DIDDirSys::set_permission(dir)?;
+        }
+        DIDDir::open_owned(&config)
     }
 
-    fn read_ids(path: &Path) -> io::Result<HashMap<String, PathBuf>> {
-        if !path.is_dir() {
-            return Err(io::Error::new(io::ErrorKind::NotFound,
-                format!("No DIDDir at: {}",
-                path.to_str().unwrap())));
+    /// Settles a transaction that never finished. Looking only takes a
+    /// shared lock, so opening doesn't wait on or hold off other readers;
+    /// the exclusive one is only taken when there is something to do.
+    fn recover(&mut self) -> io::Result<()> {
+        {
+            let _lock = self.storage.lock(LockMode::Shared)?;
+            if Journal::find(self.storage.as_ref())?.is_none() {
+                return Ok(());
+            }
         }
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.roll_back()
+    }
 
-        let mut ids = HashMap::new();
+    /// Rolls back a transaction cut short while making its changes, or
+    /// finishes the bookkeeping of one cut short after they all went
+    /// through.
+    fn roll_back(&mut self) -> io::Result<()> {
+        let mut journal = match Journal::find(self.storage.as_ref())? {
+            Some(journal) => journal,
+            None => return Ok(())
+        };
+        match journal.unrecorded() {
+            Some(changes) => self.record(&mut journal, &changes),
+            None => journal.roll_back(self.storage.as_mut())
+        }
+    }
 
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            if !entry.metadata()?.is_dir() {
-                let p = entry.path();
-                let pkid = p.file_name().unwrap().to_os_string().into_string().unwrap();
-                ids.insert(pkid, PathBuf::from(entry.path()));
+    /// Makes all of `changes` or, if any fails, none of them, then does the
+    /// bookkeeping for them. The journal stays until the bookkeeping is
+    /// done, so a crash on the way is made up for by `recover`. The lock
+    /// has to be held.
+    fn apply_journaled(&mut self, changes: &[Change]) -> io::Result<()> {
+        let mut journal = Journal::begin(self.storage.as_mut(), changes)?;
+        let applied = self.apply_changes(changes)
+            .and_then(|_| journal.applied(self.storage.as_mut(), changes));
+        if let Err(e) = applied {
+            journal.roll_back(self.storage.as_mut())?;
+            return Err(e);
+        }
+        self.record(&mut journal, changes)
+    }
+
+    /// Does the bookkeeping for `changes`, which have gone through, ticking
+    /// each off in the journal, then drops it.
+    fn record(&mut self, journal: &mut Journal, changes: &[Change]) -> io::Result<()> {
+        for change in changes.iter() {
+            let before = journal.before(change.entry());
+            match change {
+                Change::Put(Entry::Identity(pkid), data) => self.identity_written(pkid, before, data)?,
+                Change::Put(Entry::Alias(alias), pkid) => self.alias_written(alias, before, pkid)?,
+                Change::Remove(Entry::Identity(pkid)) => self.identity_deleted(pkid, before.unwrap_or(""))?,
+                Change::Remove(Entry::Alias(alias)) => self.alias_deleted(alias, before.unwrap_or(""))?
             }
+            journal.recorded(self.storage.as_mut(), change.entry())?;
         }
+        journal.finish(self.storage.as_mut())
+    }
 
-        Ok(ids)
+    /// Makes the changes of a transaction, leaving the bookkeeping for
+    /// when they have all gone through.
+    fn apply_changes(&mut self, changes: &[Change]) -> io::Result<()> {
+        for change in changes {
+            match change {
+                Change::Put(Entry::Identity(pkid), data) => self.storage.write_identity(pkid, data)?,
+                Change::Put(Entry::Alias(alias), pkid) => self.storage.write_alias(alias, pkid)?,
+                Change::Remove(Entry::Identity(pkid)) => self.storage.delete_identity(pkid)?,
+                Change::Remove(Entry::Alias(alias)) => self.storage.delete_alias(alias)?
+            }
+        }
+        Ok(())
     }
 
-    fn read_aliases(path: &Path) -> io::Result<HashMap<String, String>> {
-        if !path.is_dir() {
-            return Err(io::Error::new(io::ErrorKind::NotFound,
-                format!("No DIDDir at: {}",
-                path.to_str().unwrap())));
+    fn write_identity(&mut self, pkid: &str, data: &str) -> io::Result<()> {
+        let before = self.storage.read_identity(pkid).ok();
+        self.storage.write_identity(pkid, data)?;
+        self.identity_written(pkid, before.as_deref(), data)
+    }
+
+    fn delete_identity(&mut self, pkid: &str) -> io::Result<()> {
+        let before = self.storage.read_identity(pkid)?;
+        self.storage.delete_identity(pkid)?;
+        self.identity_deleted(pkid, &before)
+    }
+
+    fn write_alias(&mut self, alias: &str, pkid: &str) -> io::Result<()> {
+        let before = self.storage.read_alias(alias).ok();
+        self.storage.write_alias(alias, pkid)?;
+        self.alias_written(alias, before.as_deref(), pkid)
+    }
+
+    fn delete_alias(&mut self, alias: &str) -> io::Result<()> {
+        let before = self.storage.read_alias(alias);
+        self.storage.delete_alias(alias)?;
+        self.alias_deleted(alias, &before?)
+    }
+
+    // keeping history, tombstones and the audit log up to date once the
+    // change itself has been made
+
+    fn identity_written(&mut self, pkid: &str, before: Option<&str>, data: &str) -> io::Result<()> {
+        history::record(self.storage.as_mut(), pkid, before, data, self.retention)?;
+        index::update(self.storage.as_mut(), pkid, data)?;
+        // saving the same document again isn't an update
+        if before != Some(data) {
+            metadata::touch(self.storage.as_mut(), pkid)?;
         }
+        sync::unbury(self.storage.as_mut(), IDENTITY_TOMBSTONES, pkid)?;
+
+        let before = before.map(|d| sha256_hex(d.as_bytes()));
+        let after = Some(sha256_hex(data.as_bytes()));
+        self.audit(AuditOp::SaveIdentity, Some(pkid), None, before, after)
+    }
 
-        let mut aliases = HashMap::new();
+    fn identity_deleted(&mut self, pkid: &str, before: &str) -> io::Result<()> {
+        let hash = sha256_hex(before.as_bytes());
+        history::record_removal(self.storage.as_mut(), pkid, before, self.retention)?;
+        index::remove(self.storage.as_mut(), pkid)?;
+        metadata::remove(self.storage.as_mut(), pkid)?;
+        sync::bury(self.storage.as_mut(), IDENTITY_TOMBSTONES, pkid, &hash)?;
 
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            if !entry.metadata()?.is_dir() {
-                let p = entry.path();
-                let alias = p.file_name().unwrap().to_os_string().into_string().unwrap();
-                let pkid = fs::read_to_string(entry.path())?.trim().to_owned();
-                aliases.insert(alias, pkid);
-            }
+        self.audit(AuditOp::RemoveIdentity, Some(pkid), None, Some(hash), None)
+    }
+
+    fn alias_written(&mut self, alias: &str, before: Option<&str>, pkid: &str) -> io::Result<()> {
+        sync::unbury(self.storage.as_mut(), ALIAS_TOMBSTONES, alias)?;
+
+        let before = before.map(|p| sha256_hex(p.as_bytes()));
+        let after = Some(sha256_hex(pkid.as_bytes()));
+        self.audit(AuditOp::SaveAlias, Some(pkid), Some(alias), before, after)
+    }
+
+    fn alias_deleted(&mut self, alias: &str, before: &str) -> io::Result<()> {
+        let hash = sha256_hex(before.as_bytes());
+        sync::bury(self.storage.as_mut(), ALIAS_TOMBSTONES, alias, &hash)?;
+
+        self.audit(AuditOp::RemoveAlias, Some(before), Some(alias), Some(hash), None)
+    }
+
+    /// Replaces the metadata of `pkid` and notes it in the audit log, unless
+    /// it is unchanged. Returns whether it changed.
+    fn write_metadata(&mut self, pkid: &str, metadata: &Metadata) -> io::Result<bool> {
+        let before = metadata::hash(&metadata::read(self.storage.as_ref(), pkid)?)?;
+        let after = metadata::hash(metadata)?;
+        if after == before {
+            return Ok(false);
         }
+        metadata::write(self.storage.as_mut(), pkid, metadata)?;
+        self.audit(AuditOp::UpdateMetadata, Some(pkid), None, Some(before), Some(after))?;
+        Ok(true)
+    }
+
+    /// Stores an attestation and notes it in the audit log.
+    fn attestation_added(&mut self, attestation: &Attestation) -> io::Result<()> {
+        trust::add(self.storage.as_mut(), attestation)?;
+        let op = if attestation.revoked { AuditOp::Revoke } else { AuditOp::Attest };
+        self.audit(op, Some(&attestation.subject), None, None, Some(attestation.id.to_owned()))
+    }
+
+    fn audit(&mut self, op: AuditOp, pkid: Option<&str>, alias: Option<&str>,
+             before: Option<String>, after: Option<String>) -> io::Result<()> {
+        if !self.audited()? {
+            return Ok(());
+        }
+        audit::append(self.storage.as_mut(), op, pkid, alias, None, before, after)
+    }
 
-        Ok(aliases)
+    /// Whether changes go in the audit log: if this DIDDir was asked to keep
+    /// one, or if the keyring has one already, whoever started it, so it
+    /// has no gaps.
+    fn audited(&self) -> io::Result<bool> {
+        Ok(self.audit || audit::started(self.storage.as_ref())?)
     }
 }
@@ -0,0 +1,230 @@
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::DIDDirSys;
+
+pub(crate) static POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+thread_local! {
+    /// Locks held for this thread by code it runs on behalf of, see
+    /// `Lock::lend`.
+    static LENT: RefCell<Vec<(PathBuf, LockMode)>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockMode {
+    Shared,
+    Exclusive
+}
+
+/// The process holding the exclusive lock, as recorded in the lock file.
+/// Shared holders don't record themselves. A writer that died keeps its
+/// record until the next writer replaces it, but isn't named as a holder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockInfo {
+    pub pid: u32,
+    pub timestamp: u64
+}
+
+impl LockInfo {
+    fn current() -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        LockInfo {
+            pid: process::id(),
+            timestamp
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split_whitespace();
+        let pid = parts.next()?.parse().ok()?;
+        let timestamp = parts.next()?.parse().ok()?;
+        Some(LockInfo { pid, timestamp })
+    }
+}
+
+/// An advisory lock on a DIDDir, released when dropped.
+#[derive(Debug)]
+pub struct Lock {
+    file: File,
+    path: PathBuf,
+    mode: LockMode
+}
+
+impl Lock {
+
+    /// Waits up to `timeout` for the lock. The lock is the OS's, so it goes
+    /// away with the process holding it, however that process ends.
+    pub fn acquire(path: &Path, mode: LockMode, timeout: Duration) -> io::Result<Self> {
+        let file = Self::open_lock_file(path)?;
+        if let Err(e) = Self::wait(&file, path, mode, timeout) {
+            if e.kind() != io::ErrorKind::TimedOut {
+                return Err(e);
+            }
+            return Err(Self::timed_out(path));
+        }
+        Self::taken(file, path, mode)
+    }
+
+    /// Takes the lock if nobody holds it, without waiting, e.g. for callers
+    /// that wait some other way in between.
+    #[cfg(feature = "async")]
+    pub(crate) fn try_acquire(path: &Path, mode: LockMode) -> io::Result<Option<Self>> {
+        let file = Self::open_lock_file(path)?;
+        if !Self::try_lock(&file, mode)? {
+            return Ok(None);
+        }
+        Self::taken(file, path, mode).map(Some)
+    }
+
+    /// The error for giving up on the lock, naming its holder if known.
+    pub(crate) fn timed_out(path: &Path) -> io::Error {
+        match Self::holder(path) {
+            Ok(Some(info)) => io::Error::new(io::ErrorKind::TimedOut,
+                format!("Timed out waiting for lock on: {} (held by pid {} since {})",
+                path.to_str().unwrap(), info.pid, info.timestamp)),
+            Ok(None) => io::Error::new(io::ErrorKind::TimedOut,
+                format!("Timed out waiting for lock on: {}", path.to_str().unwrap())),
+            Err(e) => e
+        }
+    }
+
+    /// Runs `f` with this lock lent to the current thread: taking the same
+    /// lock, or a shared one under an exclusive one, finds it held already.
+    /// For locks held where the thread can't see them, e.g. in async code.
+    pub(crate) fn lend<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        struct Lent;
+        impl Drop for Lent {
+            fn drop(&mut self) {
+                LENT.with(|lent| lent.borrow_mut().pop());
+            }
+        }
+
+        LENT.with(|lent| lent.borrow_mut().push((self.path.clone(), self.mode)));
+        let _lent = Lent;
+        f()
+    }
+
+    /// Whether the current thread has been lent a lock on `path` that
+    /// covers `mode`.
+    pub(crate) fn is_lent(path: &Path, mode: LockMode) -> bool {
+        LENT.with(|lent| lent.borrow().iter()
+            .any(|(p, m)| p == path && (*m == LockMode::Exclusive || *m == mode)))
+    }
+
+    /// Takes a shared lock without creating or writing to the lock file, so
+    /// it works on read-only mounts. Returns `None` if there is no lock file
+    /// to lock.
+    pub fn acquire_read_only(path: &Path, timeout: Duration) -> io::Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        };
+        Self::wait(&file, path, LockMode::Shared, timeout)?;
+
+        Ok(Some(Lock {
+            file,
+            path: path.to_path_buf(),
+            mode: LockMode::Shared
+        }))
+    }
+
+    /// The writer recorded in the lock file, if it is still running.
+    pub fn holder(path: &Path) -> io::Result<Option<LockInfo>> {
+        let mut data = String::new();
+        match File::open(path) {
+            Ok(mut file) => { file.read_to_string(&mut data)?; },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        }
+        Ok(LockInfo::parse(&data).filter(|info| DIDDirSys::is_running(info.pid)))
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    fn wait(file: &File, path: &Path, mode: LockMode, timeout: Duration) -> io::Result<()> {
+        let start = Instant::now();
+
+        while !Self::try_lock(file, mode)? {
+            if start.elapsed() >= timeout {
+                return Err(io::Error::new(io::ErrorKind::TimedOut,
+                    format!("Timed out waiting for lock on: {}",
+                    path.to_str().unwrap())));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    fn try_lock(file: &File, mode: LockMode) -> io::Result<bool> {
+        let result = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock()
+        };
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(TryLockError::WouldBlock) => Ok(false),
+            Err(TryLockError::Error(e)) => Err(e)
+        }
+    }
+
+    fn taken(mut file: File, path: &Path, mode: LockMode) -> io::Result<Self> {
+        if mode == LockMode::Exclusive {
+            // record ourselves as the holder of the exclusive lock
+            let info = LockInfo::current();
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(format!("{} {}\n", info.pid, info.timestamp).as_bytes())?;
+        }
+
+        Ok(Lock {
+            file,
+            path: path.to_path_buf(),
+            mode
+        })
+    }
+
+    fn open_lock_file(path: &Path) -> io::Result<File> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+
+        // create the lock file with the right permissions from the start so
+        // that concurrent permission checks never see it world-readable
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let file = options.open(path)?;
+        DIDDirSys::set_permission(path)?;
+        Ok(file)
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // only a live writer is recorded in the lock file
+        if self.mode == LockMode::Exclusive {
+            let _ = self.file.set_len(0);
+        }
+        let _ = self.file.unlock();
+    }
+}
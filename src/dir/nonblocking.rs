@@ -0,0 +1,324 @@
+use crate::{Alias, Config, Durability, Pkid};
+use crate::git::Op;
+use crate::storage::Placement;
+use crate::sync::Entry;
+use std::io;
+use std::panic;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinError;
+use super::DIDDir;
+use super::lock::{Lock, LockMode, POLL_INTERVAL};
+use super::shared::SharedDIDDir;
+use super::transaction::Transaction;
+
+/// A DIDDir for async code, built with the `async` feature.
+///
+/// Waiting for the keyring's lock is done on tokio's timer, so it holds up
+/// neither the runtime nor a thread. Saving an identity or alias writes
+/// and moves its file with `tokio::fs`; the rest, e.g. keeping its history
+/// and the audit log, runs the `DIDDir` code on tokio's blocking thread
+/// pool with the lock lent to it. Everything happens in the same order and
+/// under the same lock as with `DIDDir`, so changes are exactly as atomic.
+/// Dropping a future doesn't cancel a change under way; it still goes
+/// through or fails as a whole.
+///
+/// Opening or creating a keyring, and storage that isn't kept in files,
+/// run on the blocking thread pool as a whole.
+///
+/// Clones share the same DIDDir, see `SharedDIDDir`. Changes made through
+/// `shared` wait for the keyring's lock as another process would, and like
+/// the ones here take it before the DIDDir's own.
+#[derive(Clone, Debug)]
+pub struct AsyncDIDDir {
+    dir: SharedDIDDir
+}
+
+impl AsyncDIDDir {
+
+    pub async fn open(config: &Config) -> io::Result<Self> {
+        let config = config.clone();
+        blocking(move || SharedDIDDir::open(&config)).await.map(Self::from)
+    }
+
+    pub async fn init(config: &Config) -> io::Result<Self> {
+        let config = config.clone();
+        blocking(move || SharedDIDDir::init(&config)).await.map(Self::from)
+    }
+
+    pub async fn open_or_init(config: &Config) -> io::Result<Self> {
+        let config = config.clone();
+        blocking(move || SharedDIDDir::open_or_init(&config)).await.map(Self::from)
+    }
+
+    pub fn in_memory() -> Self {
+        Self::from(SharedDIDDir::in_memory())
+    }
+
+    /// The same DIDDir for blocking code.
+    pub fn shared(&self) -> &SharedDIDDir {
+        &self.dir
+    }
+
+    pub async fn get_identity(&self, pkid: &str) -> io::Result<String> {
+        let pkid = pkid.to_owned();
+        self.with(move |dir| dir.get_identity(&pkid)).await
+    }
+
+    pub async fn get_identities(&self) -> io::Result<Option<Vec<String>>> {
+        self.with(|dir| Ok(dir.get_identities())).await
+    }
+
+    pub async fn get_pkid_from_alias(&self, alias: &str) -> io::Result<String> {
+        let alias = alias.to_owned();
+        self.with(move |dir| dir.get_pkid_from_alias(&alias)).await
+    }
+
+    pub async fn get_aliases(&self, pkid: &str) -> io::Result<Option<Vec<String>>> {
+        let pkid = pkid.to_owned();
+        self.with(move |dir| Ok(dir.get_aliases(&pkid))).await
+    }
+
+    pub async fn save_identity(&self, pkid: &str, data: &str) -> io::Result<()> {
+        self.save(Entry::Identity(pkid.to_owned()), data.to_owned()).await
+    }
+
+    pub async fn remove_identity(&self, pkid: &str) -> io::Result<()> {
+        let pkid = pkid.to_owned();
+        self.with_mut(move |dir| dir.remove_identity(&pkid)).await
+    }
+
+    pub async fn save_alias(&self, alias: &str, pkid: &str) -> io::Result<()> {
+        self.save(Entry::Alias(alias.to_owned()), pkid.to_owned()).await
+    }
+
+    pub async fn remove_alias(&self, alias: &str) -> io::Result<()> {
+        let alias = alias.to_owned();
+        self.with_mut(move |dir| dir.remove_alias(&alias)).await
+    }
+
+    pub async fn remove_namespace(&self, namespace: &str) -> io::Result<usize> {
+        let namespace = namespace.to_owned();
+        self.with_mut(move |dir| dir.remove_namespace(&namespace)).await
+    }
+
+    /// See `DIDDir::transaction`.
+    pub async fn transaction<T, F>(&self, f: F) -> io::Result<T>
+        where F: FnOnce(&mut Transaction) -> io::Result<T> + Send + 'static,
+              T: Send + 'static
+    {
+        self.with_mut(move |dir| dir.transaction(f)).await
+    }
+
+    pub async fn refresh(&self) -> io::Result<()> {
+        self.with_mut(|dir| dir.refresh()).await
+    }
+
+    /// Waits for the keyring's shared lock, then runs `f` on the blocking
+    /// thread pool with the DIDDir shared with other lookups. For anything
+    /// without an async method of its own.
+    pub async fn with<T, F>(&self, f: F) -> io::Result<T>
+        where F: FnOnce(&DIDDir<'static>) -> io::Result<T> + Send + 'static,
+              T: Send + 'static
+    {
+        let lock = Arc::new(self.lock(LockMode::Shared).await?);
+        let dir = self.dir.clone();
+        blocking(move || lent(&lock, || f(&dir.read()))).await
+    }
+
+    /// Like `with`, holding the exclusive lock and the DIDDir to itself to
+    /// change it.
+    pub async fn with_mut<T, F>(&self, f: F) -> io::Result<T>
+        where F: FnOnce(&mut DIDDir<'static>) -> io::Result<T> + Send + 'static,
+              T: Send + 'static
+    {
+        let lock = Arc::new(self.lock(LockMode::Exclusive).await?);
+        self.lent_mut(&lock, f).await
+    }
+
+    /// Saves `entry` writing its file with `tokio::fs`, if the storage keeps
+    /// files. Runs as a task of its own, so that the change is seen through
+    /// however the future ends.
+    async fn save(&self, entry: Entry, data: String) -> io::Result<()> {
+        let this = self.clone();
+        joined(tokio::spawn(async move {
+            let lock = Arc::new(this.lock(LockMode::Exclusive).await?);
+            let placing = {
+                let data = data.clone();
+                this.lent_mut(&lock, move |dir| dir.place(entry, &data)).await?
+            };
+            let placing = match placing {
+                Some(placing) => placing,
+                None => return Ok(())
+            };
+            if let Err(e) = write(&placing.placement, &data).await {
+                let _ = tokio::fs::remove_file(&placing.placement.tmp).await;
+                return Err(e);
+            }
+            this.lent_mut(&lock, move |dir| dir.placed(placing, &data)).await
+        }).await)
+    }
+
+    /// Waits for the keyring's lock as `Lock::acquire` does, but asleep on
+    /// tokio's timer. `None` for storage without a lock.
+    async fn lock(&self, mode: LockMode) -> io::Result<Option<Lock>> {
+        let (path, timeout) = match self.dir.lock_file() {
+            Some(lock) => lock,
+            None => return Ok(None)
+        };
+
+        let start = Instant::now();
+        loop {
+            if let Some(lock) = Lock::try_acquire(path, mode)? {
+                return Ok(Some(lock));
+            }
+            if start.elapsed() >= timeout {
+                return Err(Lock::timed_out(path));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Runs `f` on the blocking thread pool with the DIDDir to itself and
+    /// `lock` lent to it.
+    async fn lent_mut<T, F>(&self, lock: &Arc<Option<Lock>>, f: F) -> io::Result<T>
+        where F: FnOnce(&mut DIDDir<'static>) -> io::Result<T> + Send + 'static,
+              T: Send + 'static
+    {
+        let (dir, lock) = (self.dir.clone(), lock.clone());
+        blocking(move || lent(&lock, || f(&mut dir.write()))).await
+    }
+}
+
+impl From<SharedDIDDir> for AsyncDIDDir {
+    fn from(dir: SharedDIDDir) -> Self {
+        AsyncDIDDir { dir }
+    }
+}
+
+impl From<DIDDir<'static>> for AsyncDIDDir {
+    fn from(dir: DIDDir<'static>) -> Self {
+        Self::from(SharedDIDDir::from(dir))
+    }
+}
+
+/// A save whose file `AsyncDIDDir` writes itself.
+#[derive(Debug)]
+struct Placing {
+    entry: Entry,
+    placement: Placement,
+    before: Option<String>
+}
+
+impl<'a> DIDDir<'a> {
+
+    /// Checks a save of `entry` and says where to write its file, or saves
+    /// it right away if the storage doesn't keep files.
+    fn place(&mut self, entry: Entry, data: &str) -> io::Result<Option<Placing>> {
+        let entry = match entry {
+            Entry::Identity(pkid) => {
+                let pkid = Pkid::new(&pkid)?;
+                self.derivation.check(pkid.as_str(), data)?;
+                Entry::Identity(pkid.as_str().to_owned())
+            },
+            Entry::Alias(alias) => {
+                Pkid::new(data)?;
+                Entry::Alias(Alias::qualified(&alias, self.default_namespace())?.as_str().to_owned())
+            }
+        };
+
+        let lock = self.storage.lock(LockMode::Exclusive)?;
+        let placement = match self.storage.placement(&entry)? {
+            Some(placement) => placement,
+            None => {
+                drop(lock);
+                match entry {
+                    Entry::Identity(ref pkid) => self.save_identity(pkid, data)?,
+                    Entry::Alias(ref alias) => self.save_alias(alias, data)?
+                }
+                return Ok(None);
+            }
+        };
+        let before = match entry {
+            Entry::Identity(ref pkid) => self.storage.read_identity(pkid).ok(),
+            Entry::Alias(ref alias) => self.storage.read_alias(alias).ok()
+        };
+        Ok(Some(Placing { entry, placement, before }))
+    }
+
+    /// Finishes a save once its file is in place, as `save_identity` and
+    /// `save_alias` do after writing it.
+    fn placed(&mut self, placing: Placing, data: &str) -> io::Result<()> {
+        let _lock = self.storage.lock(LockMode::Exclusive)?;
+        self.storage.placed(&placing.entry, data)?;
+        let before = placing.before.as_deref();
+        match placing.entry {
+            Entry::Identity(ref pkid) => {
+                self.identity_written(pkid, before, data)?;
+                self.commit(Op::SaveIdentity(pkid))
+            },
+            Entry::Alias(ref alias) => {
+                self.alias_written(alias, before, data)?;
+                self.commit(Op::SaveAlias(alias, data))
+            }
+        }
+    }
+}
+
+/// Writes `data` to `placement.tmp` and moves it into place, as
+/// `FsStorage` does but with `tokio::fs`.
+async fn write(placement: &Placement, data: &str) -> io::Result<()> {
+    let fsync = placement.durability == Durability::Fsync;
+    {
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&placement.tmp).await?;
+        file.write_all(data.as_bytes()).await?;
+        file.flush().await?;
+        if fsync {
+            file.sync_all().await?;
+        }
+    }
+    #[cfg(not(unix))]
+    super::DIDDirSys::set_permission(&placement.tmp)?;
+
+    tokio::fs::rename(&placement.tmp, &placement.path).await?;
+    // only unix lets a directory be opened and synced
+    #[cfg(unix)]
+    {
+        if let (true, Some(dir)) = (fsync, placement.path.parent()) {
+            tokio::fs::File::open(dir).await?.sync_all().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs `f` with `lock`, if there is one, lent to the current thread.
+fn lent<T, F: FnOnce() -> T>(lock: &Option<Lock>, f: F) -> T {
+    match lock {
+        Some(lock) => lock.lend(f),
+        None => f()
+    }
+}
+
+async fn blocking<T, F>(f: F) -> io::Result<T>
+    where F: FnOnce() -> io::Result<T> + Send + 'static,
+          T: Send + 'static
+{
+    joined(tokio::task::spawn_blocking(f).await)
+}
+
+fn joined<T>(result: Result<io::Result<T>, JoinError>) -> io::Result<T> {
+    match result {
+        Ok(result) => result,
+        // a panic in the task is passed on as if it had happened here
+        Err(e) => match e.try_into_panic() {
+            Ok(payload) => panic::resume_unwind(payload),
+            Err(e) => Err(io::Error::other(e))
+        }
+    }
+}
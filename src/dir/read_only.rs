@@ -0,0 +1,236 @@
+use crate::{Alias, Archive, ExportOptions, Namespace, Pkid, Version};
+use crate::audit::{self, AuditEntry, AuditHead, AuditQuery};
+use crate::history;
+use crate::index::{self, Query};
+use crate::metadata::{self, Metadata};
+use crate::storage::{FsStorage, Storage};
+use crate::trust::{self, Attestation, Trust};
+use std::io;
+use super::lock::LockMode;
+
+/// A keyring opened with `DIDDir::open_read_only`. It has all the lookups of
+/// `DIDDir` and none of the changes, and can be shared between threads,
+/// e.g. in an `Arc`.
+#[derive(Debug)]
+pub struct ReadOnlyDIDDir<'a> {
+    storage: FsStorage<'a>,
+    namespace: Option<Namespace>
+}
+
+impl<'a> ReadOnlyDIDDir<'a> {
+
+    pub(crate) fn new(storage: FsStorage<'a>, namespace: Option<Namespace>) -> Self {
+        ReadOnlyDIDDir { storage, namespace }
+    }
+
+    fn reader(&self) -> Reader<'_> {
+        Reader::new(&self.storage, self.namespace.as_ref())
+    }
+
+    pub fn storage(&self) -> &dyn Storage {
+        &self.storage
+    }
+
+    pub fn get_identity(&self, pkid: &str) -> io::Result<String> {
+        self.reader().get_identity(pkid)
+    }
+
+    /// An earlier version of an identity, numbered as in `history`.
+    pub fn get_identity_version(&self, pkid: &str, version: u64) -> io::Result<String> {
+        self.reader().get_identity_version(pkid, version)
+    }
+
+    /// Every version of an identity still kept, oldest first.
+    pub fn history(&self, pkid: &str) -> io::Result<Vec<Version>> {
+        self.reader().history(pkid)
+    }
+
+    pub fn get_identities(&self) -> Option<Vec<String>> {
+        self.reader().get_identities()
+    }
+
+    /// Looks up `alias`, which is taken to be in the default namespace
+    /// unless it names one, as in `work/alice`.
+    pub fn get_pkid_from_alias(&self, alias: &str) -> io::Result<String> {
+        self.reader().get_pkid_from_alias(alias)
+    }
+
+    pub fn get_aliases(&self, pkid: &str) -> Option<Vec<String>> {
+        self.reader().get_aliases(pkid)
+    }
+
+    pub fn metadata(&self, pkid: &str) -> io::Result<Metadata> {
+        self.reader().metadata(pkid)
+    }
+
+    pub fn attestations(&self, subject: &str) -> io::Result<Vec<Attestation>> {
+        self.reader().attestations(subject)
+    }
+
+    /// See `DIDDir::trust_of`.
+    pub fn trust_of(&self, pkid: &str) -> io::Result<Trust> {
+        self.reader().trust_of(pkid)
+    }
+
+    /// The pkids of every identity matching `query`, sorted.
+    pub fn query(&self, query: &Query) -> io::Result<Vec<String>> {
+        self.reader().query(query)
+    }
+
+    pub fn default_namespace(&self) -> Option<&str> {
+        self.namespace.as_ref().map(|n| n.as_str())
+    }
+
+    /// Every namespace with an alias in it, sorted.
+    pub fn namespaces(&self) -> io::Result<Vec<String>> {
+        self.reader().namespaces()
+    }
+
+    /// The aliases in `namespace`, or those without one for `None`, with
+    /// the pkids they point at, sorted.
+    pub fn aliases_in(&self, namespace: Option<&str>) -> io::Result<Vec<(String, String)>> {
+        self.reader().aliases_in(namespace)
+    }
+
+    /// Writes every identity and alias out as a versioned `Archive`.
+    pub fn export<W: io::Write>(&self, writer: W, options: &ExportOptions) -> io::Result<()> {
+        self.reader().export(writer, options)
+    }
+
+    /// Reads back the audit log entries matching `query`, oldest first.
+    pub fn audit_entries(&self, query: &AuditQuery) -> io::Result<Vec<AuditEntry>> {
+        self.reader().audit_entries(query)
+    }
+
+    /// See `DIDDir::verify_audit_log`.
+    pub fn verify_audit_log(&self) -> io::Result<Option<AuditHead>> {
+        self.reader().verify_audit_log()
+    }
+
+    /// Picks up changes made by others. Unlike `DIDDir::refresh` this only
+    /// needs a shared reference, so one handle can serve many threads.
+    pub fn refresh(&self) -> io::Result<()> {
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        self.storage.rescan()
+    }
+}
+
+/// The lookups `DIDDir` and `ReadOnlyDIDDir` have in common, over whatever
+/// storage either has. Anything that reads more than one thing takes the
+/// shared lock; catching up with changes is up to the caller.
+pub(crate) struct Reader<'s> {
+    storage: &'s dyn Storage,
+    namespace: Option<&'s Namespace>
+}
+
+impl<'s> Reader<'s> {
+
+    pub(crate) fn new(storage: &'s dyn Storage, namespace: Option<&'s Namespace>) -> Self {
+        Reader { storage, namespace }
+    }
+
+    pub(crate) fn get_identity(&self, pkid: &str) -> io::Result<String> {
+        self.storage.read_identity(Pkid::new(pkid)?.as_str())
+    }
+
+    pub(crate) fn get_identity_version(&self, pkid: &str, version: u64) -> io::Result<String> {
+        history::read_version(self.storage, Pkid::new(pkid)?.as_str(), version)
+    }
+
+    pub(crate) fn history(&self, pkid: &str) -> io::Result<Vec<Version>> {
+        history::versions(self.storage, Pkid::new(pkid)?.as_str())
+    }
+
+    pub(crate) fn get_identities(&self) -> Option<Vec<String>> {
+        match self.storage.identities() {
+            Ok(ids) if !ids.is_empty() => Some(ids),
+            _ => None
+        }
+    }
+
+    pub(crate) fn get_pkid_from_alias(&self, alias: &str) -> io::Result<String> {
+        let namespace = self.namespace.map(|n| n.as_str());
+        self.storage.read_alias(Alias::qualified(alias, namespace)?.as_str())
+    }
+
+    pub(crate) fn get_aliases(&self, pkid: &str) -> Option<Vec<String>> {
+        let pkid = Pkid::new(pkid).ok()?;
+        match self.storage.aliases_of(pkid.as_str()) {
+            Ok(aliases) if !aliases.is_empty() => Some(aliases),
+            _ => None
+        }
+    }
+
+    pub(crate) fn metadata(&self, pkid: &str) -> io::Result<Metadata> {
+        let pkid = Pkid::new(pkid)?;
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        if !self.storage.has_identity(pkid.as_str())? {
+            return Err(crate::storage::not_found_identity(pkid.as_str()));
+        }
+        metadata::read(self.storage, pkid.as_str())
+    }
+
+    pub(crate) fn attestations(&self, subject: &str) -> io::Result<Vec<Attestation>> {
+        let subject = Pkid::new(subject)?;
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        Ok(trust::all(self.storage)?.into_iter()
+            .filter(|a| a.subject == subject.as_str())
+            .collect())
+    }
+
+    pub(crate) fn trust_of(&self, pkid: &str) -> io::Result<Trust> {
+        let pkid = Pkid::new(pkid)?;
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        if !self.storage.has_identity(pkid.as_str())? {
+            return Err(crate::storage::not_found_identity(pkid.as_str()));
+        }
+        trust::trust_of(self.storage, pkid.as_str())
+    }
+
+    pub(crate) fn query(&self, query: &Query) -> io::Result<Vec<String>> {
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        index::query(self.storage, query)
+    }
+
+    pub(crate) fn namespaces(&self) -> io::Result<Vec<String>> {
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        let mut namespaces: Vec<String> = self.storage.aliases()?.into_iter()
+            .filter_map(|(alias, _)| Alias::new(&alias).ok()?.namespace().map(|n| n.to_owned()))
+            .collect();
+        namespaces.sort();
+        namespaces.dedup();
+        Ok(namespaces)
+    }
+
+    pub(crate) fn aliases_in(&self, namespace: Option<&str>) -> io::Result<Vec<(String, String)>> {
+        let namespace = match namespace {
+            Some(namespace) => Some(Namespace::new(namespace)?),
+            None => None
+        };
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        let mut aliases: Vec<_> = self.storage.aliases()?.into_iter()
+            .filter(|(alias, _)| Alias::new(alias).map(|a| a.namespace() == namespace.as_ref().map(|n| n.as_str()))
+                .unwrap_or(false))
+            .collect();
+        aliases.sort();
+        Ok(aliases)
+    }
+
+    pub(crate) fn export<W: io::Write>(&self, writer: W, options: &ExportOptions) -> io::Result<()> {
+        let archive = {
+            let _lock = self.storage.lock(LockMode::Shared)?;
+            Archive::from_storage(self.storage, options)?
+        };
+        archive.write(writer)
+    }
+
+    pub(crate) fn audit_entries(&self, query: &AuditQuery) -> io::Result<Vec<AuditEntry>> {
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        audit::query(self.storage, query)
+    }
+
+    pub(crate) fn verify_audit_log(&self) -> io::Result<Option<AuditHead>> {
+        let _lock = self.storage.lock(LockMode::Shared)?;
+        audit::verify(self.storage)
+    }
+}
@@ -0,0 +1,141 @@
+use crate::Config;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use super::DIDDir;
+use super::lock::{Lock, LockMode};
+use super::transaction::Transaction;
+
+/// A DIDDir that owns everything it needs, so it can go in application
+/// state, be returned from constructors or be moved into threads. Clones
+/// share the same DIDDir.
+///
+/// Lookups from any number of threads run at the same time. Changes wait
+/// for each other and for lookups under way, and, as with `DIDDir`, take
+/// the keyring's lock against other processes too. The keyring's lock is
+/// taken first, as `AsyncDIDDir` does, so the two can be used side by side.
+#[derive(Clone, Debug)]
+pub struct SharedDIDDir {
+    dir: Arc<RwLock<DIDDir<'static>>>,
+    lock: Option<(PathBuf, Duration)>
+}
+
+impl SharedDIDDir {
+
+    pub fn open(config: &Config) -> io::Result<Self> {
+        DIDDir::open_owned(config).map(Self::from)
+    }
+
+    pub fn init(config: &Config) -> io::Result<Self> {
+        DIDDir::init_owned(config).map(Self::from)
+    }
+
+    pub fn open_or_init(config: &Config) -> io::Result<Self> {
+        match Self::open(config) {
+            Ok(diddir) => Ok(diddir),
+            _ => Self::init(config)
+        }
+    }
+
+    pub fn in_memory() -> Self {
+        Self::from(DIDDir::in_memory())
+    }
+
+    /// The DIDDir for lookups, shared with other readers. Calls through the
+    /// guard wait for the keyring's lock while holding it, which an
+    /// `AsyncDIDDir` on the same DIDDir may be waiting for in turn, so
+    /// alongside one use `with` instead.
+    pub fn read(&self) -> RwLockReadGuard<'_, DIDDir<'static>> {
+        self.dir.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The DIDDir to change, held by nobody else until the guard is dropped.
+    /// As with `read`, alongside an `AsyncDIDDir` use `with_mut` instead.
+    pub fn write(&self) -> RwLockWriteGuard<'_, DIDDir<'static>> {
+        self.dir.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs `f` with the keyring's shared lock and the DIDDir shared with
+    /// other lookups.
+    pub fn with<T, F>(&self, f: F) -> io::Result<T>
+        where F: FnOnce(&DIDDir<'static>) -> io::Result<T>
+    {
+        self.locked(LockMode::Shared, || f(&self.read()))
+    }
+
+    /// Like `with`, holding the exclusive lock and the DIDDir to itself to
+    /// change it.
+    pub fn with_mut<T, F>(&self, f: F) -> io::Result<T>
+        where F: FnOnce(&mut DIDDir<'static>) -> io::Result<T>
+    {
+        self.locked(LockMode::Exclusive, || f(&mut self.write()))
+    }
+
+    pub fn get_identity(&self, pkid: &str) -> io::Result<String> {
+        self.with(|dir| dir.get_identity(pkid))
+    }
+
+    pub fn get_identities(&self) -> Option<Vec<String>> {
+        self.with(|dir| Ok(dir.get_identities())).unwrap_or(None)
+    }
+
+    pub fn get_pkid_from_alias(&self, alias: &str) -> io::Result<String> {
+        self.with(|dir| dir.get_pkid_from_alias(alias))
+    }
+
+    pub fn get_aliases(&self, pkid: &str) -> Option<Vec<String>> {
+        self.with(|dir| Ok(dir.get_aliases(pkid))).unwrap_or(None)
+    }
+
+    pub fn save_identity(&self, pkid: &str, data: &str) -> io::Result<()> {
+        self.with_mut(|dir| dir.save_identity(pkid, data))
+    }
+
+    pub fn remove_identity(&self, pkid: &str) -> io::Result<()> {
+        self.with_mut(|dir| dir.remove_identity(pkid))
+    }
+
+    pub fn save_alias(&self, alias: &str, pkid: &str) -> io::Result<()> {
+        self.with_mut(|dir| dir.save_alias(alias, pkid))
+    }
+
+    pub fn remove_alias(&self, alias: &str) -> io::Result<()> {
+        self.with_mut(|dir| dir.remove_alias(alias))
+    }
+
+    /// See `DIDDir::transaction`. Other threads wait until it's done.
+    pub fn transaction<T, F>(&self, f: F) -> io::Result<T>
+        where F: FnOnce(&mut Transaction) -> io::Result<T>
+    {
+        self.with_mut(|dir| dir.transaction(f))
+    }
+
+    pub fn refresh(&self) -> io::Result<()> {
+        self.with_mut(|dir| dir.refresh())
+    }
+
+    /// The file the keyring's lock is on and how long to wait for it, `None`
+    /// for storage without a lock.
+    pub(crate) fn lock_file(&self) -> Option<(&Path, Duration)> {
+        self.lock.as_ref().map(|(path, timeout)| (path.as_path(), *timeout))
+    }
+
+    /// Runs `f` with the keyring's lock taken and lent to this thread, unless
+    /// it holds it already.
+    fn locked<T, F>(&self, mode: LockMode, f: F) -> io::Result<T>
+        where F: FnOnce() -> io::Result<T>
+    {
+        match self.lock_file() {
+            Some((path, timeout)) if !Lock::is_lent(path, mode) => Lock::acquire(path, mode, timeout)?.lend(f),
+            _ => f()
+        }
+    }
+}
+
+impl From<DIDDir<'static>> for SharedDIDDir {
+    fn from(dir: DIDDir<'static>) -> Self {
+        let lock = dir.storage().lock_file().map(|(path, timeout)| (path.to_owned(), timeout));
+        SharedDIDDir { dir: Arc::new(RwLock::new(dir)), lock }
+    }
+}
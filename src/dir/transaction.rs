@@ -0,0 +1,284 @@
+use crate::{Alias, Metadata, Pkid, PkidDerivation};
+use crate::metadata;
+use crate::storage::Storage;
+use crate::sync::{Change, Entry};
+use serde_derive::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::io;
+
+/// Record kind and key of the journal, which holds what every entry a
+/// transaction touches looked like before, so a transaction cut short can
+/// be rolled back.
+static JOURNAL: &str = "transaction";
+static JOURNAL_KEY: &str = "journal";
+
+/// A batch of changes to a DIDDir, handed to the closure given to
+/// `DIDDir::transaction`. Nothing is written until the closure returns
+/// `Ok`, and reads see the changes staged so far.
+#[derive(Debug)]
+pub struct Transaction<'t> {
+    storage: &'t dyn Storage,
+    derivation: PkidDerivation,
+    namespace: Option<&'t str>,
+    staged: BTreeMap<Entry, Option<String>>,
+    metadata: BTreeMap<String, Metadata>
+}
+
+impl<'t> Transaction<'t> {
+
+    pub(crate) fn new(storage: &'t dyn Storage, derivation: PkidDerivation, namespace: Option<&'t str>) -> Self {
+        Transaction {
+            storage,
+            derivation,
+            namespace,
+            staged: BTreeMap::new(),
+            metadata: BTreeMap::new()
+        }
+    }
+
+    pub fn save_identity(&mut self, pkid: &str, data: &str) -> io::Result<()> {
+        let pkid = Pkid::new(pkid)?;
+        self.derivation.check(pkid.as_str(), data)?;
+        self.staged.insert(Entry::Identity(pkid.into()), Some(data.to_owned()));
+        Ok(())
+    }
+
+    pub fn get_identity(&self, pkid: &str) -> io::Result<String> {
+        let pkid = Pkid::new(pkid)?;
+        match self.staged.get(&Entry::Identity(pkid.as_str().to_owned())) {
+            Some(Some(data)) => Ok(data.to_owned()),
+            Some(None) => Err(crate::storage::not_found_identity(pkid.as_str())),
+            None => self.storage.read_identity(pkid.as_str())
+        }
+    }
+
+    /// Removes an identity along with every alias pointing at it.
+    pub fn remove_identity(&mut self, pkid: &str) -> io::Result<()> {
+        let pkid = Pkid::new(pkid)?;
+        if self.get_identity(pkid.as_str()).is_err() {
+            return Err(io::Error::other(
+                       "Identity file does not exist"));
+        }
+
+        for alias in self.aliases_of(pkid.as_str())? {
+            self.staged.insert(Entry::Alias(alias), None);
+        }
+        self.metadata.remove(pkid.as_str());
+        self.staged.insert(Entry::Identity(pkid.into()), None);
+        Ok(())
+    }
+
+    /// Changes the metadata of an identity, which may have been saved
+    /// earlier in the transaction, like `DIDDir::update_metadata`.
+    pub fn update_metadata<F: FnOnce(&mut Metadata)>(&mut self, pkid: &str, f: F) -> io::Result<()> {
+        let pkid = Pkid::new(pkid)?;
+        self.get_identity(pkid.as_str())?;
+        let mut metadata = match self.metadata.get(pkid.as_str()) {
+            Some(metadata) => metadata.clone(),
+            None => metadata::read(self.storage, pkid.as_str())?
+        };
+        f(&mut metadata);
+        self.metadata.insert(pkid.into(), metadata);
+        Ok(())
+    }
+
+    pub fn get_pkid_from_alias(&self, alias: &str) -> io::Result<String> {
+        self.read_alias(&Alias::qualified(alias, self.namespace)?)
+    }
+
+    fn read_alias(&self, alias: &Alias) -> io::Result<String> {
+        match self.staged.get(&Entry::Alias(alias.as_str().to_owned())) {
+            Some(Some(pkid)) => Ok(pkid.to_owned()),
+            Some(None) => Err(crate::storage::not_found_alias(alias.as_str())),
+            None => self.storage.read_alias(alias.as_str())
+        }
+    }
+
+    pub fn save_alias(&mut self, alias: &str, pkid: &str) -> io::Result<()> {
+        let alias = Alias::qualified(alias, self.namespace)?;
+        let pkid = Pkid::new(pkid)?;
+        self.staged.insert(Entry::Alias(alias.into()), Some(pkid.into()));
+        Ok(())
+    }
+
+    pub fn remove_alias(&mut self, alias: &str) -> io::Result<()> {
+        let alias = Alias::qualified(alias, self.namespace)?;
+        self.read_alias(&alias)?;
+        self.staged.insert(Entry::Alias(alias.into()), None);
+        Ok(())
+    }
+
+    fn aliases_of(&self, pkid: &str) -> io::Result<Vec<String>> {
+        let mut aliases: Vec<String> = self.storage.aliases_of(pkid)?.into_iter()
+            .filter(|alias| !self.staged.contains_key(&Entry::Alias(alias.to_owned())))
+            .collect();
+        for (entry, value) in self.staged.iter() {
+            if let (Entry::Alias(alias), Some(target)) = (entry, value) {
+                if target == pkid {
+                    aliases.push(alias.to_owned());
+                }
+            }
+        }
+        Ok(aliases)
+    }
+
+    /// What actually differs from storage, in the order it is safe to
+    /// apply: aliases go before the identities they point at, and come back
+    /// after. The metadata staged comes separately, to be written once the
+    /// identities are.
+    pub(crate) fn into_changes(self) -> io::Result<(Vec<Change>, BTreeMap<String, Metadata>)> {
+        let mut removed_aliases = Vec::new();
+        let mut removed_ids = Vec::new();
+        let mut ids = Vec::new();
+        let mut aliases = Vec::new();
+
+        for (entry, value) in self.staged {
+            if current(self.storage, &entry)? == value {
+                continue;
+            }
+            match (entry, value) {
+                (entry @ Entry::Alias(_), None) => removed_aliases.push(Change::Remove(entry)),
+                (entry @ Entry::Identity(_), None) => removed_ids.push(Change::Remove(entry)),
+                (entry @ Entry::Identity(_), Some(data)) => ids.push(Change::Put(entry, data)),
+                (entry @ Entry::Alias(_), Some(pkid)) => aliases.push(Change::Put(entry, pkid))
+            }
+        }
+
+        removed_aliases.append(&mut removed_ids);
+        removed_aliases.append(&mut ids);
+        removed_aliases.append(&mut aliases);
+
+        let mut metadata = BTreeMap::new();
+        for (pkid, staged) in self.metadata {
+            if metadata::read(self.storage, &pkid)? != staged {
+                metadata.insert(pkid, staged);
+            }
+        }
+        Ok((removed_aliases, metadata))
+    }
+}
+
+/// What the entries a transaction changes looked like before it, keyed as
+/// in `Entry::key`, and once they have all been changed, what they became
+/// for the changes whose bookkeeping hasn't been done yet.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct Journal {
+    before: BTreeMap<String, Option<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unrecorded: Option<BTreeMap<String, Option<String>>>
+}
+
+impl Journal {
+
+    /// Saves what `changes` are about to overwrite. Storage that can't keep
+    /// records still gets rolled back on errors, just not after a crash.
+    pub(crate) fn begin(storage: &mut dyn Storage, changes: &[Change]) -> io::Result<Self> {
+        let mut journal = Journal::default();
+        for change in changes {
+            journal.before.insert(change.entry().key(), current(storage, change.entry())?);
+        }
+
+        journal.save(storage)?;
+        Ok(journal)
+    }
+
+    /// Notes that all of `changes` went through, so from here on a
+    /// transaction cut short gets its bookkeeping finished rather than
+    /// being rolled back.
+    pub(crate) fn applied(&mut self, storage: &mut dyn Storage, changes: &[Change]) -> io::Result<()> {
+        self.unrecorded = Some(changes.iter()
+            .map(|change| (change.entry().key(), match change {
+                Change::Put(_, value) => Some(value.to_owned()),
+                Change::Remove(_) => None
+            }))
+            .collect());
+        self.save(storage)
+    }
+
+    /// Notes that the bookkeeping for the change of `entry` is done.
+    pub(crate) fn recorded(&mut self, storage: &mut dyn Storage, entry: &Entry) -> io::Result<()> {
+        if let Some(unrecorded) = self.unrecorded.as_mut() {
+            unrecorded.remove(&entry.key());
+        }
+        self.save(storage)
+    }
+
+    /// The changes still waiting for their bookkeeping, `None` if they
+    /// haven't all gone through.
+    pub(crate) fn unrecorded(&self) -> Option<Vec<Change>> {
+        let unrecorded = self.unrecorded.as_ref()?;
+        let mut changes: Vec<_> = unrecorded.iter()
+            .filter_map(|(key, value)| Entry::from_key(key).map(|entry| match value {
+                Some(value) => Change::Put(entry, value.to_owned()),
+                None => Change::Remove(entry)
+            }))
+            .collect();
+        changes.sort_by(|a, b| a.entry().cmp(b.entry()));
+        Some(changes)
+    }
+
+    fn save(&self, storage: &mut dyn Storage) -> io::Result<()> {
+        let json = serde_json::to_string(self).map_err(io::Error::other)?;
+        match storage.write_record(JOURNAL, JOURNAL_KEY, &json) {
+            Err(ref e) if e.kind() == io::ErrorKind::Unsupported => Ok(()),
+            result => result
+        }
+    }
+
+    /// The journal left behind by a transaction that never finished, if any.
+    pub(crate) fn find(storage: &dyn Storage) -> io::Result<Option<Self>> {
+        match storage.read_record(JOURNAL, JOURNAL_KEY) {
+            Ok(json) => serde_json::from_str(&json).map(Some).map_err(|e|
+                io::Error::new(io::ErrorKind::InvalidData, format!("Corrupt transaction journal: {}", e))),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::Unsupported => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub(crate) fn before(&self, entry: &Entry) -> Option<&str> {
+        self.before.get(&entry.key()).and_then(|v| v.as_deref())
+    }
+
+    /// Puts every entry back the way it was and drops the journal.
+    pub(crate) fn roll_back(&self, storage: &mut dyn Storage) -> io::Result<()> {
+        // identities first, so restored aliases have something to point at
+        let mut entries: Vec<_> = self.before.iter()
+            .filter_map(|(key, value)| Entry::from_key(key).map(|e| (e, value)))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (entry, value) in entries {
+            if current(storage, &entry)?.as_ref() == value.as_ref() {
+                continue;
+            }
+            match (entry, value) {
+                (Entry::Identity(pkid), Some(data)) => storage.write_identity(&pkid, data)?,
+                (Entry::Identity(pkid), None) => storage.delete_identity(&pkid)?,
+                (Entry::Alias(alias), Some(pkid)) => storage.write_alias(&alias, pkid)?,
+                (Entry::Alias(alias), None) => storage.delete_alias(&alias)?
+            }
+        }
+        self.finish(storage)
+    }
+
+    /// Drops the journal, making the transaction stick.
+    pub(crate) fn finish(&self, storage: &mut dyn Storage) -> io::Result<()> {
+        match storage.delete_record(JOURNAL, JOURNAL_KEY) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::Unsupported => Ok(()),
+            result => result
+        }
+    }
+}
+
+/// The content of `entry` in storage, `None` when it isn't there.
+fn current(storage: &dyn Storage, entry: &Entry) -> io::Result<Option<String>> {
+    let result = match entry {
+        Entry::Identity(pkid) => storage.read_identity(pkid),
+        Entry::Alias(alias) => storage.read_alias(alias)
+    };
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)
+    }
+}
@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::io;
 use std::fs;
 use std::os::unix::fs::{MetadataExt, PermissionsExt};
@@ -6,15 +7,22 @@ use std::path::Path;
 static PERMISSIONS_MASK: u32 = 0o777;
 static DIR_PERMISSIONS: u32 = 0o700;
 static FILE_PERMISSIONS: u32 = 0o600;
+static GROUP_WORLD_WRITABLE: u32 = 0o022;
+static STICKY: u32 = 0o1000;
 
 pub struct DIDDirSys();
 
 impl DIDDirSys {
 
     pub fn set_permission(path: &Path) -> io::Result<()> {
-        let metadata = fs::metadata(path)?;
+        // never follow a symlink to chmod whatever it points at
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.file_type().is_symlink() {
+            return Err(unsafe_path(path, "is a symlink"));
+        }
+
         let mut permissions = metadata.permissions();
-        let correct_permissions = if path.is_dir() {
+        let correct_permissions = if metadata.is_dir() {
             DIR_PERMISSIONS
         } else {
             FILE_PERMISSIONS
@@ -25,19 +33,105 @@ impl DIDDirSys {
     }
 
     pub fn check_permission(path: &Path) -> io::Result<()> {
-        let metadata = fs::metadata(path)?;
-        let permissions = if path.is_dir() {
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.file_type().is_symlink() {
+            return Err(unsafe_path(path, "is a symlink"));
+        }
+
+        // a second link could be somewhere we don't control
+        if !metadata.is_dir() && metadata.nlink() > 1 {
+            return Err(unsafe_path(path,
+                &format!("has {} hard links", metadata.nlink())));
+        }
+
+        let uid = current_uid();
+        if metadata.uid() != uid {
+            return Err(unsafe_path(path,
+                &format!("is owned by uid {}, not {}", metadata.uid(), uid)));
+        }
+
+        let permissions = if metadata.is_dir() {
             DIR_PERMISSIONS
         } else {
             FILE_PERMISSIONS
         };
 
         if metadata.mode() & PERMISSIONS_MASK != permissions {
-            return Err(io::Error::new(io::ErrorKind::Other,
-                format!("Invalid permissions ({}) on: {}",
-                metadata.mode(), path.to_str().unwrap())));
+            return Err(unsafe_path(path,
+                &format!("has permissions {:o}, expected {:o}",
+                metadata.mode() & PERMISSIONS_MASK, permissions)));
         }
         Ok(())
     }
 
+    /// The check for keyrings only read from, e.g. on a read-only mount: the
+    /// modes we set when writing don't matter, as long as nobody but us or
+    /// root could have changed what we read.
+    pub fn check_read_only_permission(path: &Path) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.file_type().is_symlink() {
+            return Err(unsafe_path(path, "is a symlink"));
+        }
+
+        if !metadata.is_dir() && metadata.nlink() > 1 {
+            return Err(unsafe_path(path,
+                &format!("has {} hard links", metadata.nlink())));
+        }
+
+        let uid = current_uid();
+        if metadata.uid() != uid && metadata.uid() != 0 {
+            return Err(unsafe_path(path,
+                &format!("is owned by uid {}, not {} or root", metadata.uid(), uid)));
+        }
+
+        if metadata.mode() & GROUP_WORLD_WRITABLE != 0 {
+            return Err(unsafe_path(path,
+                &format!("is group or world writable ({:o})", metadata.mode() & PERMISSIONS_MASK)));
+        }
+        Ok(())
+    }
+
+    /// Checks that nobody but us or root can swap out any directory above
+    /// `path`. World writable directories are fine if they are sticky, like
+    /// /tmp, since others can't rename what we own in there.
+    pub fn check_parents(path: &Path) -> io::Result<()> {
+        let path = path.canonicalize()?;
+        let uid = current_uid();
+
+        for dir in path.ancestors().skip(1) {
+            let metadata = fs::metadata(dir)?;
+            if metadata.uid() != uid && metadata.uid() != 0 {
+                return Err(unsafe_path(dir,
+                    &format!("is owned by uid {}, not {} or root", metadata.uid(), uid)));
+            }
+
+            let mode = metadata.mode();
+            if mode & GROUP_WORLD_WRITABLE != 0 && mode & STICKY == 0 {
+                return Err(unsafe_path(dir,
+                    &format!("is group or world writable ({:o})", mode & PERMISSIONS_MASK)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether process `pid` is still there. One we aren't allowed to
+    /// signal is there all the same.
+    pub fn is_running(pid: u32) -> bool {
+        let pid = match libc::pid_t::try_from(pid) {
+            Ok(pid) if pid > 0 => pid,
+            _ => return false
+        };
+        let signalled = unsafe { libc::kill(pid, 0) };
+        signalled == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+
+}
+
+fn current_uid() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+fn unsafe_path(path: &Path, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied,
+        format!("Unsafe DIDDir path {}: {}", path.display(), reason))
 }
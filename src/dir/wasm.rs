@@ -1,15 +1,33 @@
+use std::io;
+use std::path::Path;
+
 pub struct DIDDirSys();
 
 impl DIDDirSys {
 
-    fn set_permission(path: &Path) -> io::Result<()> {
+    pub fn set_permission(_path: &Path) -> io::Result<()> {
+        // No-op for now
+        Ok(())
+    }
+
+    pub fn check_permission(_path: &Path) -> io::Result<()> {
         // No-op for now
         Ok(())
     }
 
-    fn check_permission(path: &Path) -> io::Result<()> {
+    pub fn check_read_only_permission(_path: &Path) -> io::Result<()> {
         // No-op for now
         Ok(())
     }
 
+    pub fn check_parents(_path: &Path) -> io::Result<()> {
+        // No-op for now
+        Ok(())
+    }
+
+    pub fn is_running(_pid: u32) -> bool {
+        // No processes to ask about
+        true
+    }
+
 }
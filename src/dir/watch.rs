@@ -0,0 +1,222 @@
+use crate::storage::Storage;
+use notify::{PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+static POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A change to the DIDDir, reported by `DIDDir::poll_events`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    IdentityAdded(String),
+    IdentityUpdated(String),
+    IdentityRemoved(String),
+    AliasAdded { alias: String, pkid: String },
+    AliasRemoved { alias: String, pkid: String }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WatchMode {
+    /// Use the platform's notification API (inotify on Linux), falling back
+    /// to polling if it isn't available.
+    #[default]
+    Native,
+    /// Scan the DIDDir at the given interval.
+    Poll(Duration)
+}
+
+/// What we last saw of the DIDDir, with a stamp per identity to tell
+/// updates apart.
+pub(crate) struct Snapshot {
+    ids: HashMap<String, u64>,
+    aliases: HashMap<String, String>
+}
+
+impl Snapshot {
+    pub(crate) fn new(storage: &dyn Storage) -> io::Result<Self> {
+        let mut stamps = HashMap::new();
+        for pkid in storage.identities()? {
+            // the identity may be gone already, in which case it isn't there
+            if let Ok(stamp) = storage.identity_stamp(&pkid) {
+                stamps.insert(pkid, stamp);
+            }
+        }
+
+        Ok(Snapshot {
+            ids: stamps,
+            aliases: storage.aliases()?.into_iter().collect()
+        })
+    }
+
+    pub(crate) fn diff(&self, newer: &Snapshot) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for (pkid, stamp) in newer.ids.iter() {
+            match self.ids.get(pkid) {
+                None => events.push(Event::IdentityAdded(pkid.to_owned())),
+                Some(old) if old != stamp => events.push(Event::IdentityUpdated(pkid.to_owned())),
+                _ => {}
+            }
+        }
+        for pkid in self.ids.keys() {
+            if !newer.ids.contains_key(pkid) {
+                events.push(Event::IdentityRemoved(pkid.to_owned()));
+            }
+        }
+
+        // an alias pointed somewhere else is reported as removed then added
+        for (alias, pkid) in self.aliases.iter() {
+            if newer.aliases.get(alias) != Some(pkid) {
+                events.push(Event::AliasRemoved { alias: alias.to_owned(), pkid: pkid.to_owned() });
+            }
+        }
+        for (alias, pkid) in newer.aliases.iter() {
+            if self.aliases.get(alias) != Some(pkid) {
+                events.push(Event::AliasAdded { alias: alias.to_owned(), pkid: pkid.to_owned() });
+            }
+        }
+
+        events
+    }
+}
+
+/// Watches the root and aliases directories of a DIDDir for changes made
+/// by anybody, including other processes.
+///
+/// Lookups catch up with changes as they go, so the snapshot and the
+/// events not yet handed out are behind mutexes along with the watcher and
+/// receiver, which also keeps a watched DIDDir shareable between threads.
+pub(crate) struct Watch {
+    _watcher: Mutex<Box<dyn Watcher + Send>>,
+    mode: WatchMode,
+    rx: Mutex<Receiver<notify::Result<notify::Event>>>,
+    snapshot: Mutex<Snapshot>,
+    pending: Mutex<Vec<Event>>
+}
+
+impl fmt::Debug for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watch").field("mode", &self.mode).finish()
+    }
+}
+
+impl Watch {
+
+    pub(crate) fn new(dirs: &[&Path], mode: WatchMode, snapshot: Snapshot) -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+
+        let (watcher, mode): (Box<dyn Watcher + Send>, WatchMode) = match mode {
+            WatchMode::Native => match Self::native(dirs, tx.clone()) {
+                Ok(watcher) => (Box::new(watcher), mode),
+                Err(_) => {
+                    let mode = WatchMode::Poll(POLL_INTERVAL);
+                    (Box::new(Self::poll(dirs, POLL_INTERVAL, tx)?), mode)
+                }
+            },
+            WatchMode::Poll(interval) => (Box::new(Self::poll(dirs, interval, tx)?), mode)
+        };
+
+        Ok(Watch {
+            _watcher: Mutex::new(watcher),
+            mode,
+            rx: Mutex::new(rx),
+            snapshot: Mutex::new(snapshot),
+            pending: Mutex::new(Vec::new())
+        })
+    }
+
+    /// The mode actually in use, which is polling if native watching failed.
+    pub(crate) fn mode(&self) -> WatchMode {
+        self.mode
+    }
+
+    /// Queues up the events between the last snapshot and `snapshot`.
+    pub(crate) fn record(&self, snapshot: Snapshot) {
+        let mut last = self.snapshot.lock().unwrap_or_else(|e| e.into_inner());
+        let events = last.diff(&snapshot);
+        *last = snapshot;
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).extend(events);
+    }
+
+    /// Hands out the events queued up so far.
+    pub(crate) fn take_events(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Waits up to `timeout` for a change notification, then drains any
+    /// others queued behind it. Returns whether anything changed.
+    pub(crate) fn wait(&self, timeout: Duration) -> io::Result<bool> {
+        let rx = self.rx.lock().unwrap_or_else(|e| e.into_inner());
+        let mut changed = match rx.recv_timeout(timeout) {
+            Ok(event) => Self::is_relevant(event),
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe,
+                    "DIDDir watcher stopped"));
+            }
+        };
+
+        while let Ok(event) = rx.try_recv() {
+            changed |= Self::is_relevant(event);
+        }
+
+        Ok(changed)
+    }
+
+    fn native(dirs: &[&Path], tx: mpsc::Sender<notify::Result<notify::Event>>) -> io::Result<RecommendedWatcher> {
+        let mut watcher = notify::recommended_watcher(tx).map_err(io::Error::other)?;
+        for d in dirs {
+            watcher.watch(d, Self::recursive_mode(d, dirs)).map_err(io::Error::other)?;
+        }
+        Ok(watcher)
+    }
+
+    fn poll(dirs: &[&Path], interval: Duration, tx: mpsc::Sender<notify::Result<notify::Event>>) -> io::Result<PollWatcher> {
+        // mtimes are only compared to the second, so look at the contents
+        // to catch quick successive writes
+        let config = notify::Config::default()
+            .with_poll_interval(interval)
+            .with_compare_contents(true);
+        let mut watcher = PollWatcher::new(tx, config).map_err(io::Error::other)?;
+        for d in dirs {
+            watcher.watch(d, Self::recursive_mode(d, dirs)).map_err(io::Error::other)?;
+        }
+        Ok(watcher)
+    }
+
+    /// Everything under a directory is watched, e.g. alias namespaces,
+    /// unless another watched directory is under it, like the aliases dir
+    /// under the root where the bookkeeping would only add noise.
+    fn recursive_mode(dir: &Path, dirs: &[&Path]) -> RecursiveMode {
+        if dirs.iter().any(|d| *d != dir && d.starts_with(dir)) {
+            RecursiveMode::NonRecursive
+        } else {
+            RecursiveMode::Recursive
+        }
+    }
+
+    fn is_relevant(event: notify::Result<notify::Event>) -> bool {
+        // if the watcher lost track of things (e.g. queue overflow) rescan
+        let event = match event {
+            Ok(event) => event,
+            Err(_) => return true
+        };
+
+        if event.kind.is_access() {
+            return false;
+        }
+
+        // the lock file changes on every write and tells us nothing
+        event.paths.iter().any(|p| {
+            match p.file_name().and_then(|n| n.to_str()) {
+                Some(name) => !name.starts_with('.'),
+                None => true
+            }
+        })
+    }
+}
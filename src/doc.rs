@@ -1,3 +1,4 @@
+use crate::hash::{hex_decode, sha256_hex};
 use serde_derive::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -23,9 +24,18 @@ impl Subject {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// The method of a DID, `example` in `did:example:123`.
+    pub fn method(&self) -> Option<&str> {
+        let mut parts = self.0.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("did"), Some(method), Some(_)) if !method.is_empty() => Some(method),
+            _ => None
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 #[serde(rename_all = "PascalCase")]
 pub enum PublicKeyType {
     Ed25519VerificationKey2018,
@@ -57,13 +67,52 @@ pub enum PublicKeyData {
 impl PublicKeyData {
     pub fn as_str(&self) -> &str {
         match self {
-            PublicKeyData::Pem{ key } => &key,
-            PublicKeyData::Jwk{ key } => &key,
-            PublicKeyData::Hex{ key } => &key,
-            PublicKeyData::Base64{ key } => &key,
-            PublicKeyData::Base58{ key } => &key,
-            PublicKeyData::Multibase{ key } => &key,
-            PublicKeyData::EthAddr{ key } => &key,
+            PublicKeyData::Pem{ key } => key,
+            PublicKeyData::Jwk{ key } => key,
+            PublicKeyData::Hex{ key } => key,
+            PublicKeyData::Base64{ key } => key,
+            PublicKeyData::Base58{ key } => key,
+            PublicKeyData::Multibase{ key } => key,
+            PublicKeyData::EthAddr{ key } => key,
+        }
+    }
+
+    /// The key itself, decoded from however the document spells it. A JWK
+    /// gives its `x` coordinate, followed by `y` in SEC1 uncompressed form
+    /// for an EC key. `None` if the key doesn't decode.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            PublicKeyData::Pem{ key } => {
+                let body: String = key.lines()
+                    .filter(|l| !l.starts_with("-----"))
+                    .flat_map(|l| l.trim().chars())
+                    .collect();
+                base64::decode(&body).ok()
+            },
+            PublicKeyData::Jwk{ key } => {
+                let jwk: serde_json::Value = serde_json::from_str(key).ok()?;
+                let coordinate = |c: &str| jwk.get(c).and_then(|v| v.as_str())
+                    .and_then(|v| base64::decode_config(v, base64::URL_SAFE_NO_PAD).ok());
+                match (coordinate("x")?, coordinate("y")) {
+                    (x, None) => Some(x),
+                    (x, Some(y)) => Some([&[4u8][..], &x, &y].concat())
+                }
+            },
+            PublicKeyData::Hex{ key } => hex_decode(key),
+            PublicKeyData::Base64{ key } => base64::decode(key).ok(),
+            PublicKeyData::Base58{ key } => bs58::decode(key).into_vec().ok(),
+            PublicKeyData::Multibase{ key } => {
+                let mut chars = key.chars();
+                let data = chars.as_str().get(1..)?;
+                match chars.next()? {
+                    'z' => bs58::decode(data).into_vec().ok(),
+                    'f' | 'F' => hex_decode(data),
+                    'm' => base64::decode_config(data, base64::STANDARD_NO_PAD).ok(),
+                    'u' => base64::decode_config(data, base64::URL_SAFE_NO_PAD).ok(),
+                    _ => None
+                }
+            },
+            PublicKeyData::EthAddr{ key } => hex_decode(key.trim_start_matches("0x"))
         }
     }
 }
@@ -78,11 +127,64 @@ pub struct PublicKey {
     pub key_data: PublicKeyData
 }
 
+impl PublicKey {
+    /// Hex encoded SHA-256 of the key bytes, so it is the same however the
+    /// key is encoded. Key material that doesn't decode is hashed as it
+    /// appears in the document.
+    pub fn fingerprint(&self) -> String {
+        match self.key_data.to_bytes() {
+            Some(bytes) => sha256_hex(&bytes),
+            None => sha256_hex(self.key_data.as_str().as_bytes())
+        }
+    }
+}
+
+/// An entry under `authentication`, either one of the document's public
+/// keys by id or a key of its own.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Authentication {
+    Reference(String),
+    Key(PublicKey)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Service {
+    pub id: Subject,
+    #[serde(rename = "type")]
+    pub service_type: String,
+    #[serde(rename = "serviceEndpoint")]
+    pub endpoint: String
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Document {
     #[serde(flatten)]
     pub context: Context,
     pub id: Subject,
     #[serde(rename = "publicKey", default)]
-    pub public_key: Vec<PublicKey>
+    pub public_key: Vec<PublicKey>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authentication: Vec<Authentication>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub service: Vec<Service>
+}
+
+impl Document {
+    /// The key that stands for the identity: the one named first under
+    /// `authentication`, or without that the only key the DID controls
+    /// itself. `None` if that doesn't single out a key.
+    pub fn primary_key(&self) -> Option<&PublicKey> {
+        match self.authentication.first() {
+            Some(Authentication::Reference(id)) => self.public_key.iter().find(|k| k.id.as_str() == id),
+            Some(Authentication::Key(key)) => Some(key),
+            None => {
+                let mut own = self.public_key.iter().filter(|k| k.controller.as_str() == self.id.as_str());
+                match (own.next(), own.next()) {
+                    (Some(key), None) => Some(key),
+                    _ => None
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,293 @@
+use crate::dir::DIDDirSys;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Kept out of the repository: scratch space, per-machine state and the
+/// search index, which is rebuilt from the documents as needed.
+static GITIGNORE: &str = "/tmp/\n/.lock\n/.index/\n/.profiles/\n/.profile\n/.backups/\n/.migrating\n";
+
+/// Git's own directory in the repository root.
+static GIT_DIR: &str = ".git";
+
+/// Where pulled trees are checked out to be read, inside `.git` so they are
+/// never committed.
+static STAGING: &str = "diddir-pull";
+
+/// Used when git has no user configured, so commits still work on build
+/// machines and in tests.
+static FALLBACK_NAME: &str = "diddir";
+static FALLBACK_EMAIL: &str = "diddir@localhost";
+
+/// A change to the DIDDir, described in a commit.
+#[derive(Debug)]
+pub(crate) enum Op<'s> {
+    SaveIdentity(&'s str),
+    RemoveIdentity(&'s str),
+    SaveAlias(&'s str, &'s str),
+    RemoveAlias(&'s str),
+    RemoveNamespace(&'s str),
+    UpdateMetadata(&'s str),
+    Attest(&'s str, &'s str),
+    Revoke(&'s str, &'s str),
+    Import(usize, usize),
+    Batch(usize),
+    Pull(&'s str, &'s str)
+}
+
+impl<'s> Op<'s> {
+    /// A subject line plus trailers that tools can pick apart, e.g.
+    ///
+    /// ```text
+    /// Save alias default
+    ///
+    /// Diddir-Op: save-alias
+    /// Diddir-Alias: default
+    /// Diddir-Pkid: c506310b...
+    /// ```
+    fn message(&self) -> String {
+        let (subject, op, trailers) = match self {
+            Op::SaveIdentity(pkid) => (format!("Save identity {}", pkid), "save-identity",
+                vec![("Pkid", pkid.to_string())]),
+            Op::RemoveIdentity(pkid) => (format!("Remove identity {}", pkid), "remove-identity",
+                vec![("Pkid", pkid.to_string())]),
+            Op::SaveAlias(alias, pkid) => (format!("Save alias {}", alias), "save-alias",
+                vec![("Alias", alias.to_string()), ("Pkid", pkid.to_string())]),
+            Op::RemoveAlias(alias) => (format!("Remove alias {}", alias), "remove-alias",
+                vec![("Alias", alias.to_string())]),
+            Op::RemoveNamespace(namespace) => (format!("Remove namespace {}", namespace), "remove-namespace",
+                vec![("Namespace", namespace.to_string())]),
+            Op::UpdateMetadata(pkid) => (format!("Update metadata of {}", pkid), "update-metadata",
+                vec![("Pkid", pkid.to_string())]),
+            Op::Attest(issuer, subject) => (format!("Attest {} as {}", subject, issuer), "attest",
+                vec![("Issuer", issuer.to_string()), ("Pkid", subject.to_string())]),
+            Op::Revoke(issuer, subject) => (format!("Revoke attestation of {} by {}", subject, issuer), "revoke",
+                vec![("Issuer", issuer.to_string()), ("Pkid", subject.to_string())]),
+            Op::Import(ids, aliases) => (format!("Import {} identities and {} aliases", ids, aliases),
+                "import", vec![("Identities", ids.to_string()), ("Aliases", aliases.to_string())]),
+            Op::Batch(changes) => (format!("Apply {} changes", changes), "batch",
+                vec![("Changes", changes.to_string())]),
+            Op::Pull(remote, branch) => (format!("Pull {} from {}", branch, remote), "pull",
+                vec![("Remote", remote.to_string()), ("Branch", branch.to_string())])
+        };
+
+        let mut message = format!("{}\n\nDiddir-Op: {}\n", subject, op);
+        for (key, value) in trailers {
+            message.push_str(&format!("Diddir-{}: {}\n", key, value));
+        }
+        message
+    }
+}
+
+/// A DIDDir root that is also a git repository. Everything goes through the
+/// `git` command so there is nothing to link against and the repository
+/// stays plain git.
+#[derive(Debug)]
+pub(crate) struct Git {
+    root: PathBuf
+}
+
+impl Git {
+
+    /// Returns the repository at `root`, if there is one.
+    pub(crate) fn open(root: &Path) -> Option<Self> {
+        if root.join(GIT_DIR).is_dir() {
+            Some(Git { root: root.to_path_buf() })
+        } else {
+            None
+        }
+    }
+
+    /// Makes `root` a repository and commits whatever is in it.
+    pub(crate) fn init(root: &Path) -> io::Result<Self> {
+        let git = Git { root: root.to_path_buf() };
+        if root.join(GIT_DIR).is_dir() {
+            return Ok(git);
+        }
+
+        git.run(&["init", "--quiet"])?;
+        git.commit("Start keeping DIDDir in git\n\nDiddir-Op: init\n")?;
+        Ok(git)
+    }
+
+    /// Commits everything that changed, if anything did.
+    pub(crate) fn commit_op(&self, op: &Op) -> io::Result<()> {
+        self.commit(&op.message())
+    }
+
+    pub(crate) fn push(&self, remote: &str, branch: &str) -> io::Result<()> {
+        self.run(&["push", "--quiet", remote, &format!("HEAD:refs/heads/{}", branch)])
+            .map(|_| ())
+    }
+
+    /// Fetches `branch` from `remote` and returns the commit fetched, unless
+    /// it is merged already.
+    pub(crate) fn fetch(&self, remote: &str, branch: &str) -> io::Result<Option<String>> {
+        self.run(&["fetch", "--quiet", remote, branch])?;
+        let fetched = self.run(&["rev-parse", "--verify", "FETCH_HEAD^{commit}"])?;
+        let fetched = String::from_utf8_lossy(&fetched.stdout).trim().to_owned();
+        let merged = self.command(&["merge-base", "--is-ancestor", &fetched, "HEAD"]).output()?;
+        Ok(if merged.status.success() { None } else { Some(fetched) })
+    }
+
+    /// The last commit both `rev` and ours descend from, if there is one.
+    pub(crate) fn merge_base(&self, rev: &str) -> io::Result<Option<String>> {
+        let args = ["merge-base", "HEAD", rev];
+        let output = self.command(&args).output()?;
+        match output.status.code() {
+            Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())),
+            // unrelated histories
+            Some(1) => Ok(None),
+            _ => Err(failed(&args, &output))
+        }
+    }
+
+    /// Checks out the tree of `rev` on the side, leaving our own working
+    /// tree and index alone, and returns where. Everything in it gets our
+    /// permissions; a symlink fails it, as it would a keyring.
+    pub(crate) fn check_out(&self, rev: &str, name: &str) -> io::Result<PathBuf> {
+        let staging = self.root.join(GIT_DIR).join(STAGING);
+        let dir = staging.join(name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+
+        let prefix = match dir.to_str() {
+            Some(dir) => format!("{}/", dir),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Not a UTF-8 path: {}", dir.display())))
+        };
+        let index = staging.join(format!("{}.index", name));
+        let read: &[&str] = &["read-tree", rev];
+        let checkout: &[&str] = &["checkout-index", "--all", "--prefix", &prefix];
+        for args in [read, checkout].iter() {
+            let output = self.command(args).env("GIT_INDEX_FILE", &index).output()?;
+            if !output.status.success() {
+                return Err(failed(args, &output));
+            }
+        }
+
+        set_permissions(&staging)?;
+        Ok(dir)
+    }
+
+    /// Removes whatever `check_out` left behind.
+    pub(crate) fn clear_staging(&self) -> io::Result<()> {
+        match fs::remove_dir_all(self.root.join(GIT_DIR).join(STAGING)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result
+        }
+    }
+
+    /// Records `rev` as merged while keeping our tree as it is: what we took
+    /// from it has been written and committed as our own changes already.
+    pub(crate) fn merge_op(&self, rev: &str, op: &Op) -> io::Result<()> {
+        self.run_with_identity(&["merge", "--quiet", "--strategy=ours", "--no-commit",
+            "--allow-unrelated-histories", rev])?;
+        let merging = self.command(&["rev-parse", "--quiet", "--verify", "MERGE_HEAD"]).output()?;
+        if !merging.status.success() {
+            return self.commit_op(op);
+        }
+
+        self.ignore()?;
+        self.run(&["add", "--all", "."])?;
+        self.run_with_identity(&["commit", "--quiet", "--no-verify", "--no-gpg-sign", "-m", &op.message()])
+            .map(|_| ())
+    }
+
+    fn commit(&self, message: &str) -> io::Result<()> {
+        self.ignore()?;
+        self.run(&["add", "--all", "."])?;
+
+        // nothing staged, e.g. a document saved again unchanged
+        let staged = self.command(&["diff", "--cached", "--quiet"]).output()?;
+        if staged.status.success() {
+            return Ok(());
+        }
+
+        self.run_with_identity(&["commit", "--quiet", "--no-verify", "--no-gpg-sign", "-m", message])
+            .map(|_| ())
+    }
+
+    /// Adds whatever `GITIGNORE` has that `.gitignore` doesn't, e.g. in a
+    /// repository started by an older version.
+    fn ignore(&self) -> io::Result<()> {
+        let path = self.root.join(".gitignore");
+        let mut gitignore = match fs::read_to_string(&path) {
+            Ok(gitignore) => gitignore,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e)
+        };
+
+        let missing: Vec<&str> = GITIGNORE.lines()
+            .filter(|line| !gitignore.lines().any(|l| l.trim() == *line))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        if !gitignore.is_empty() && !gitignore.ends_with('\n') {
+            gitignore.push('\n');
+        }
+        for line in missing {
+            gitignore.push_str(line);
+            gitignore.push('\n');
+        }
+        fs::write(&path, gitignore)?;
+        DIDDirSys::set_permission(&path)
+    }
+
+    fn has_user(&self) -> bool {
+        self.command(&["config", "user.email"]).output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+    }
+
+    fn run_with_identity(&self, args: &[&str]) -> io::Result<Output> {
+        if self.has_user() {
+            return self.run(args);
+        }
+        let name = format!("user.name={}", FALLBACK_NAME);
+        let email = format!("user.email={}", FALLBACK_EMAIL);
+        let mut with = vec!["-c", &name, "-c", &email];
+        with.extend_from_slice(args);
+        self.run(&with)
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new("git");
+        command.arg("-C").arg(&self.root).args(args);
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> io::Result<Output> {
+        let output = self.command(args).output()?;
+        if !output.status.success() {
+            return Err(failed(args, &output));
+        }
+        Ok(output)
+    }
+}
+
+fn failed(args: &[&str], output: &Output) -> io::Error {
+    io::Error::other(format!("git {} failed: {}",
+        args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+}
+
+fn set_permissions(path: &Path) -> io::Result<()> {
+    DIDDirSys::set_permission(path)?;
+
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_name() == GIT_DIR {
+                continue;
+            }
+            set_permissions(&entry.path())?;
+        }
+    }
+
+    Ok(())
+}
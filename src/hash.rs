@@ -0,0 +1,22 @@
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+/// Hex encoded SHA-256 of the data.
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    let digest = Sha256::digest(data);
+    let mut hex = String::with_capacity(digest.len() * 2);
+    for b in digest.iter() {
+        let _ = write!(hex, "{:02x}", b);
+    }
+    hex
+}
+
+/// The bytes of a hex string, either case.
+pub(crate) fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,133 @@
+use crate::config::Retention;
+use crate::hash::sha256_hex;
+use crate::storage::Storage;
+use serde_derive::{Serialize, Deserialize};
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Record kinds for the list of versions of each identity and for the
+/// documents themselves. Documents are keyed per identity so pruning one
+/// identity's history never touches another's.
+pub(crate) static INDEX: &str = "history/index";
+pub(crate) static OBJECTS: &str = "history/objects";
+
+/// One saved version of an identity.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Version {
+    /// Counts up from 1 and is never reused, even after pruning.
+    pub version: u64,
+    /// SHA-256 of the document, empty if the identity was removed.
+    pub hash: String,
+    /// When it was saved, in seconds since the Unix epoch.
+    pub saved: u64,
+    /// The identity was removed here. There is no document to read back.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub removed: bool
+}
+
+/// Every version of `pkid` still kept, oldest first.
+pub(crate) fn versions(storage: &dyn Storage, pkid: &str) -> io::Result<Vec<Version>> {
+    match storage.read_record(INDEX, pkid) {
+        Ok(index) => serde_json::from_str(&index).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData,
+                format!("Corrupt history for {}: {}", pkid, e))),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e)
+    }
+}
+
+pub(crate) fn read_version(storage: &dyn Storage, pkid: &str, version: u64) -> io::Result<String> {
+    match versions(storage, pkid)?.into_iter().find(|v| v.version == version) {
+        Some(v) if !v.removed => storage.read_record(OBJECTS, &object_key(pkid, &v.hash)),
+        Some(_) => Err(io::Error::new(io::ErrorKind::NotFound,
+            format!("Version {} of {} is its removal", version, pkid))),
+        None => Err(io::Error::new(io::ErrorKind::NotFound,
+            format!("No version {} of: {}", version, pkid)))
+    }
+}
+
+/// Adds `data` as the newest version of `pkid`. An identity saved before
+/// history was kept gets the document it replaced, `previous`, recorded
+/// first.
+pub(crate) fn record(storage: &mut dyn Storage, pkid: &str, previous: Option<&str>, data: &str,
+                     retention: Retention) -> io::Result<()> {
+    append(storage, pkid, previous, Some(data), retention)
+}
+
+/// Adds a version marking `pkid` as removed, after `previous`, its last
+/// document. The rest of its history stays, as `retention` allows.
+pub(crate) fn record_removal(storage: &mut dyn Storage, pkid: &str, previous: &str,
+                             retention: Retention) -> io::Result<()> {
+    append(storage, pkid, Some(previous), None, retention)
+}
+
+fn append(storage: &mut dyn Storage, pkid: &str, previous: Option<&str>, data: Option<&str>,
+          retention: Retention) -> io::Result<()> {
+    if retention == Retention::Off {
+        return Ok(());
+    }
+
+    let mut versions = versions(storage, pkid)?;
+    let mut added = Vec::new();
+    if let (true, Some(previous)) = (versions.is_empty(), previous) {
+        added.push(Some(previous));
+    }
+    added.push(data);
+
+    for data in added {
+        let hash = data.map(|d| sha256_hex(d.as_bytes())).unwrap_or_default();
+        if versions.last().map(|v| v.hash == hash).unwrap_or(false) {
+            continue;
+        }
+        if let Some(data) = data {
+            match storage.write_record(OBJECTS, &object_key(pkid, &hash), data) {
+                // storage that can't keep records just doesn't keep history
+                Err(ref e) if e.kind() == io::ErrorKind::Unsupported => return Ok(()),
+                result => result?
+            }
+        }
+        versions.push(Version {
+            version: versions.last().map(|v| v.version + 1).unwrap_or(1),
+            hash,
+            saved: now(),
+            removed: data.is_none()
+        });
+    }
+
+    let pruned = prune(&mut versions, retention);
+    match storage.write_record(INDEX, pkid, &serde_json::to_string(&versions).map_err(io::Error::other)?) {
+        Err(ref e) if e.kind() == io::ErrorKind::Unsupported => return Ok(()),
+        result => result?
+    }
+
+    // objects go after the index stops pointing at them
+    for v in pruned {
+        if !v.removed && !versions.iter().any(|kept| kept.hash == v.hash) {
+            storage.delete_record(OBJECTS, &object_key(pkid, &v.hash))?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes what `retention` doesn't keep from `versions` and returns it.
+/// The newest version always stays.
+fn prune(versions: &mut Vec<Version>, retention: Retention) -> Vec<Version> {
+    let keep = match retention {
+        Retention::Off | Retention::KeepAll => versions.len(),
+        Retention::KeepLast(n) => n.max(1),
+        Retention::KeepFor(age) => {
+            let cutoff = now().saturating_sub(age.as_secs());
+            versions.iter().rev().take_while(|v| v.saved >= cutoff).count().max(1)
+        }
+    };
+    let drop = versions.len().saturating_sub(keep);
+    versions.drain(..drop).collect()
+}
+
+fn object_key(pkid: &str, hash: &str) -> String {
+    sha256_hex(format!("{}/{}", pkid, hash).as_bytes())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs()
+}
@@ -0,0 +1,185 @@
+use crate::doc::{Document, PublicKeyData, PublicKeyType};
+use crate::hash::sha256_hex;
+use crate::metadata::{self, Metadata, TrustLevel};
+use crate::storage::Storage;
+use serde_derive::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::io;
+
+/// Record kind holding what each identity is searched by, keyed by pkid.
+pub(crate) static INDEX: &str = "index";
+
+/// Picks identities out of a DIDDir. Unset fields match anything, set ones
+/// all have to match.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    /// The DID method, `example` in `did:example:123`.
+    pub method: Option<String>,
+    /// The controller of any of the keys.
+    pub controller: Option<String>,
+    pub key_type: Option<PublicKeyType>,
+    /// `PublicKey::fingerprint` of any of the keys.
+    pub fingerprint: Option<String>,
+    /// Key material of any of the keys, in any of the encodings a document
+    /// can use, e.g. a base58 encoded Ed25519 public key.
+    pub public_key: Option<String>,
+    pub service_type: Option<String>,
+    /// A substring of any of the aliases pointing at the identity.
+    pub alias: Option<String>,
+    /// Identities trusted at least this much.
+    pub min_trust: Option<TrustLevel>,
+    pub tag: Option<String>,
+    pub source: Option<String>,
+    /// A substring of the notes.
+    pub notes: Option<String>,
+    /// Identities first saved at or after this Unix timestamp.
+    pub created_since: Option<u64>,
+    /// Identities last saved at or after this Unix timestamp.
+    pub updated_since: Option<u64>,
+    /// Identities verified at or after this Unix timestamp.
+    pub verified_since: Option<u64>
+}
+
+impl Query {
+    fn uses_metadata(&self) -> bool {
+        self.min_trust.is_some() || self.tag.is_some() || self.source.is_some() || self.notes.is_some() ||
+            self.created_since.is_some() || self.updated_since.is_some() || self.verified_since.is_some()
+    }
+
+    fn matches_metadata(&self, metadata: &Metadata) -> bool {
+        self.min_trust.map(|t| metadata.trust >= t).unwrap_or(true) &&
+            self.tag.as_ref().map(|t| metadata.tags.contains(t)).unwrap_or(true) &&
+            self.source.as_ref().map(|s| metadata.source.as_ref() == Some(s)).unwrap_or(true) &&
+            self.notes.as_ref().map(|n| metadata.notes.as_ref().map(|notes| notes.contains(n.as_str()))
+                .unwrap_or(false)).unwrap_or(true) &&
+            self.created_since.map(|t| metadata.created >= t).unwrap_or(true) &&
+            self.updated_since.map(|t| metadata.updated >= t).unwrap_or(true) &&
+            self.verified_since.map(|t| metadata.last_verified.map(|v| v >= t).unwrap_or(false)).unwrap_or(true)
+    }
+
+    fn matches(&self, entry: &IndexEntry, aliases: &[&str]) -> bool {
+        let fingerprints = self.public_key.as_ref().map(|k| fingerprints_of(k));
+        self.method.as_ref().map(|m| entry.method.as_ref() == Some(m)).unwrap_or(true) &&
+            self.controller.as_ref().map(|c| entry.controllers.contains(c)).unwrap_or(true) &&
+            self.key_type.map(|t| entry.key_types.contains(&t)).unwrap_or(true) &&
+            self.fingerprint.as_ref().map(|f| entry.fingerprints.contains(f)).unwrap_or(true) &&
+            fingerprints.map(|f| f.iter().any(|f| entry.fingerprints.contains(f))).unwrap_or(true) &&
+            self.service_type.as_ref().map(|t| entry.service_types.contains(t)).unwrap_or(true) &&
+            self.alias.as_ref().map(|a| aliases.iter().any(|alias| alias.contains(a.as_str()))).unwrap_or(true)
+    }
+}
+
+/// The fingerprint `key` has in whichever encodings it decodes in, see
+/// `PublicKey::fingerprint`.
+fn fingerprints_of(key: &str) -> Vec<String> {
+    let key = key.to_owned();
+    let encodings = [
+        PublicKeyData::Pem { key: key.clone() },
+        PublicKeyData::Jwk { key: key.clone() },
+        PublicKeyData::Hex { key: key.clone() },
+        PublicKeyData::Base64 { key: key.clone() },
+        PublicKeyData::Base58 { key: key.clone() },
+        PublicKeyData::Multibase { key: key.clone() },
+        PublicKeyData::EthAddr { key: key.clone() }
+    ];
+    let mut fingerprints: Vec<String> = encodings.iter()
+        .filter_map(|k| k.to_bytes())
+        .map(|bytes| sha256_hex(&bytes))
+        .collect();
+    fingerprints.push(sha256_hex(key.as_bytes()));
+    fingerprints.sort();
+    fingerprints.dedup();
+    fingerprints
+}
+
+/// What an identity is searched by. Documents that don't parse as a
+/// `Document` get an empty entry, so they only show up for alias queries.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub(crate) struct IndexEntry {
+    /// `Storage::identity_stamp` of the document indexed, to spot it being
+    /// changed behind the index's back.
+    stamp: u64,
+    method: Option<String>,
+    controllers: Vec<String>,
+    key_types: Vec<PublicKeyType>,
+    // renamed when fingerprints went from the encoded to the decoded key,
+    // so entries from before don't parse and get worked out again
+    #[serde(rename = "key_fingerprints")]
+    fingerprints: Vec<String>,
+    service_types: Vec<String>
+}
+
+impl IndexEntry {
+    fn new(stamp: u64, data: &str) -> Self {
+        let doc: Document = match serde_json::from_str(data) {
+            Ok(doc) => doc,
+            Err(_) => return IndexEntry { stamp, ..Default::default() }
+        };
+
+        IndexEntry {
+            stamp,
+            method: doc.id.method().map(|m| m.to_owned()),
+            controllers: doc.public_key.iter().map(|k| k.controller.as_str().to_owned()).collect(),
+            key_types: doc.public_key.iter().map(|k| k.key_type).collect(),
+            fingerprints: doc.public_key.iter().map(|k| k.fingerprint()).collect(),
+            service_types: doc.service.iter().map(|s| s.service_type.to_owned()).collect()
+        }
+    }
+}
+
+/// Indexes `pkid` as just written.
+pub(crate) fn update(storage: &mut dyn Storage, pkid: &str, data: &str) -> io::Result<()> {
+    let entry = IndexEntry::new(storage.identity_stamp(pkid)?, data);
+    let json = serde_json::to_string(&entry).map_err(io::Error::other)?;
+    match storage.write_record(INDEX, pkid, &json) {
+        // storage that can't keep records gets every document read instead
+        Err(ref e) if e.kind() == io::ErrorKind::Unsupported => Ok(()),
+        result => result
+    }
+}
+
+pub(crate) fn remove(storage: &mut dyn Storage, pkid: &str) -> io::Result<()> {
+    match storage.delete_record(INDEX, pkid) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::Unsupported => Ok(()),
+        result => result
+    }
+}
+
+/// The pkids of every identity matching `query`, sorted. Entries missing
+/// or out of date, e.g. for documents edited by hand, are worked out from
+/// the document instead.
+pub(crate) fn query(storage: &dyn Storage, query: &Query) -> io::Result<Vec<String>> {
+    let aliases = storage.aliases()?;
+    let mut aliases_of: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (alias, pkid) in aliases.iter() {
+        aliases_of.entry(pkid.as_str()).or_default().push(alias.as_str());
+    }
+
+    let mut pkids = Vec::new();
+    for pkid in storage.identities()? {
+        let entry = entry(storage, &pkid)?;
+        let aliases = aliases_of.get(pkid.as_str()).map(|a| a.as_slice()).unwrap_or(&[]);
+        if !query.matches(&entry, aliases) {
+            continue;
+        }
+        if query.uses_metadata() && !query.matches_metadata(&metadata::read(storage, &pkid)?) {
+            continue;
+        }
+        pkids.push(pkid);
+    }
+    pkids.sort();
+    Ok(pkids)
+}
+
+fn entry(storage: &dyn Storage, pkid: &str) -> io::Result<IndexEntry> {
+    let stamp = storage.identity_stamp(pkid)?;
+    match storage.read_record(INDEX, pkid) {
+        Ok(json) => match serde_json::from_str::<IndexEntry>(&json) {
+            Ok(entry) if entry.stamp == stamp => return Ok(entry),
+            _ => {}
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::Unsupported => {},
+        Err(e) => return Err(e)
+    }
+    Ok(IndexEntry::new(stamp, &storage.read_identity(pkid)?))
+}
@@ -1,11 +1,48 @@
 #[macro_use]
 extern crate cfg_if;
 
-pub use self::config::Config;
+pub use self::audit::{AuditEntry, AuditOp, AuditQuery};
+pub mod audit;
+
+pub use self::archive::{Archive, ConflictPolicy, ExportOptions, ImportReport};
+pub mod archive;
+
+pub use self::config::{Backend, Config, Durability, Encryption, PkidDerivation, Retention, Sharding};
 pub mod config;
 
 pub use self::dir::DIDDir;
+pub use self::dir::read_only::ReadOnlyDIDDir;
+pub use self::dir::shared::SharedDIDDir;
+#[cfg(feature = "async")]
+pub use self::dir::nonblocking::AsyncDIDDir;
+pub use self::dir::transaction::Transaction;
 pub mod dir;
 
 pub use self::doc::*;
 pub mod doc;
+
+mod git;
+mod hash;
+
+pub use self::history::Version;
+pub mod history;
+
+pub use self::index::Query;
+pub mod index;
+
+pub use self::metadata::{Metadata, TrustLevel};
+pub mod metadata;
+
+pub mod migrate;
+
+pub use self::name::{Alias, NameError, Namespace, Pkid, Profile};
+pub mod name;
+
+pub use self::storage::Storage;
+pub mod storage;
+
+pub use self::sync::Replica;
+pub mod sync;
+
+pub use self::trust::{Attestation, Trust};
+pub mod trust;
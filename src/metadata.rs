@@ -0,0 +1,90 @@
+use crate::hash::sha256_hex;
+use crate::storage::Storage;
+use serde_derive::{Serialize, Deserialize};
+use std::collections::BTreeSet;
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Record kind holding the metadata of each identity, keyed by pkid.
+pub(crate) static METADATA: &str = "metadata";
+
+/// How much an identity is trusted, from least to most.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrustLevel {
+    /// Known not to be trusted.
+    Never,
+    #[default]
+    Unknown,
+    Marginal,
+    Full,
+    /// One of our own identities.
+    Ultimate
+}
+
+/// What we know about an identity besides its document. Timestamps are
+/// seconds since the Unix epoch.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Metadata {
+    /// When the document was first saved. Set by `DIDDir`.
+    pub created: u64,
+    /// When the document was last saved. Set by `DIDDir`.
+    pub updated: u64,
+    /// When the document was last checked against its source.
+    pub last_verified: Option<u64>,
+    /// Where the document came from, e.g. a URL or "in person".
+    pub source: Option<String>,
+    pub trust: TrustLevel,
+    pub tags: BTreeSet<String>,
+    pub notes: Option<String>
+}
+
+pub(crate) fn read(storage: &dyn Storage, pkid: &str) -> io::Result<Metadata> {
+    match storage.read_record(METADATA, pkid) {
+        Ok(json) => serde_json::from_str(&json).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData,
+                format!("Corrupt metadata for {}: {}", pkid, e))),
+        // identities saved before metadata was kept
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::Unsupported =>
+            Ok(Metadata::default()),
+        Err(e) => Err(e)
+    }
+}
+
+/// SHA-256 of `metadata` as stored, for the audit log.
+pub(crate) fn hash(metadata: &Metadata) -> io::Result<String> {
+    let json = serde_json::to_string(metadata).map_err(io::Error::other)?;
+    Ok(sha256_hex(json.as_bytes()))
+}
+
+pub(crate) fn write(storage: &mut dyn Storage, pkid: &str, metadata: &Metadata) -> io::Result<()> {
+    let json = serde_json::to_string(metadata).map_err(io::Error::other)?;
+    storage.write_record(METADATA, pkid, &json)
+}
+
+/// Stamps `pkid` as saved just now.
+pub(crate) fn touch(storage: &mut dyn Storage, pkid: &str) -> io::Result<()> {
+    let mut metadata = read(storage, pkid)?;
+    let now = now();
+    if metadata.created == 0 {
+        metadata.created = now;
+    }
+    metadata.updated = now;
+    match write(storage, pkid, &metadata) {
+        // storage that can't keep records just doesn't keep metadata
+        Err(ref e) if e.kind() == io::ErrorKind::Unsupported => Ok(()),
+        result => result
+    }
+}
+
+pub(crate) fn remove(storage: &mut dyn Storage, pkid: &str) -> io::Result<()> {
+    match storage.delete_record(METADATA, pkid) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::Unsupported => Ok(()),
+        result => result
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0)).as_secs()
+}
@@ -0,0 +1,459 @@
+use crate::{Config, NameError, Pkid};
+use crate::config::{Sharding, DEFAULT_PROFILE, PROFILES};
+use crate::dir::DIDDirSys;
+use crate::dir::lock::{Lock, LockMode};
+use crate::metadata;
+use crate::name::PUNCTUATION;
+use crate::storage::fs::{is_shard, shard, shards};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The layout `DIDDir::init` creates and `FsStorage` reads.
+///
+/// 1. One file per identity in the root, aliases holding nothing but a
+///    pkid, namespaced aliases and records in subdirectories.
+/// 2. Identities may instead be sharded into 256 subdirectories, which a
+///    second line of `.version` says. See `reshard`.
+pub const LAYOUT_VERSION: u32 = 2;
+
+/// Holds the layout version, and `sharded` on the next line if the keyring
+/// is. Keyrings from before versioning have none and count as version 0.
+static VERSION: &str = ".version";
+
+/// Where each migration leaves a copy of the keyring as it was.
+static BACKUPS: &str = ".backups";
+
+/// Names the backup of a migration that is under way, so one cut short is
+/// rolled back before anything else happens.
+static MIGRATING: &str = ".migrating";
+
+/// Left alone by migrations: not part of the layout, or, like profiles,
+/// migrated on their own.
+static UNTOUCHED: &[&str] = &[BACKUPS, MIGRATING, ".lock", ".git", PROFILES, DEFAULT_PROFILE];
+
+/// The second line of `.version` in sharded keyrings.
+static SHARDED: &str = "sharded";
+
+/// Identity files are moved through here when resharding, so a pkid that
+/// looks like a shard never clashes with one.
+static RESHARDING: &str = ".resharding";
+
+/// Brings a layout from the version before up to the next one, working on
+/// the keyring in place.
+struct Step {
+    to: u32,
+    run: fn(&Config) -> io::Result<()>
+}
+
+static STEPS: &[Step] = &[
+    Step { to: 1, run: normalize_names },
+    Step { to: 2, run: stay_flat }
+];
+
+/// The layout version of the keyring `config` describes.
+pub fn layout_version(config: &Config) -> io::Result<u32> {
+    match fs::read_to_string(config.root_dir().join(VERSION)) {
+        Ok(version) => {
+            let version = version.lines().next().unwrap_or("").trim();
+            version.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData,
+                format!("Corrupt layout version: {}", version)))
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e)
+    }
+}
+
+/// Whether the identities in the keyring `config` describes are sharded.
+pub fn sharding(config: &Config) -> io::Result<Sharding> {
+    match fs::read_to_string(config.root_dir().join(VERSION)) {
+        Ok(version) => match version.lines().nth(1).map(str::trim) {
+            None | Some("") => Ok(Sharding::Flat),
+            Some(flag) if flag == SHARDED => Ok(Sharding::Sharded),
+            Some(flag) => Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("Corrupt layout flag: {}", flag)))
+        },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Sharding::Flat),
+        Err(e) => Err(e)
+    }
+}
+
+/// Fails unless the keyring has the layout this version of diddir reads.
+pub(crate) fn check(config: &Config) -> io::Result<()> {
+    let version = layout_version(config)?;
+    if config.root_dir().join(MIGRATING).exists() || version < LAYOUT_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("The keyring has layout version {} and needs migrating to {}", version, LAYOUT_VERSION)));
+    }
+    newer(version)
+}
+
+fn newer(version: u32) -> io::Result<()> {
+    if version > LAYOUT_VERSION {
+        return Err(io::Error::new(io::ErrorKind::Unsupported,
+            format!("The keyring has layout version {}, newer than the {} this diddir reads",
+                version, LAYOUT_VERSION)));
+    }
+    Ok(())
+}
+
+/// Upgrades the keyring to `LAYOUT_VERSION` one step at a time, after
+/// copying it to `.backups`. If any step fails, or the process dies part
+/// way, the keyring is put back from the copy. Returns where the copy is,
+/// or `None` if there was nothing to do.
+pub fn migrate(config: &Config) -> io::Result<Option<PathBuf>> {
+    let root = config.root_dir();
+    if !config.aliases_dir().is_dir() && !root.join(MIGRATING).exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound,
+            format!("No DIDDir at: {}", root.display())));
+    }
+    if !root.join(MIGRATING).exists() && layout_version(config)? == LAYOUT_VERSION {
+        return Ok(None);
+    }
+
+    let _lock = Lock::acquire(config.lock_file(), LockMode::Exclusive, config.lock_timeout())?;
+    recover(config)?;
+    let from = layout_version(config)?;
+    newer(from)?;
+    if from == LAYOUT_VERSION {
+        return Ok(None);
+    }
+
+    with_backup(config, from, || {
+        // nothing before version 2 can be sharded
+        for step in STEPS.iter().filter(|s| s.to > from) {
+            (step.run)(config)?;
+            write_version(root, step.to, Sharding::Flat)?;
+        }
+        Ok(())
+    }).map(Some)
+}
+
+/// Moves the identities of the keyring into the `to` layout, migrating it
+/// first if need be. Like `migrate`, it works from a copy in `.backups` and
+/// returns where that is, or `None` if the keyring was laid out that way
+/// already. Other handles on the keyring must be reopened afterwards.
+pub fn reshard(config: &Config, to: Sharding) -> io::Result<Option<PathBuf>> {
+    migrate(config)?;
+    let _lock = Lock::acquire(config.lock_file(), LockMode::Exclusive, config.lock_timeout())?;
+    recover(config)?;
+    if sharding(config)? == to {
+        return Ok(None);
+    }
+
+    let root = config.root_dir();
+    with_backup(config, LAYOUT_VERSION, || {
+        let staging = root.join(RESHARDING);
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir(&staging)?;
+        DIDDirSys::set_permission(&staging)?;
+
+        for file in identity_files(config)? {
+            let name = file.file_name().unwrap().to_owned();
+            fs::rename(&file, staging.join(&name))?;
+        }
+        if to == Sharding::Flat {
+            for shard in shards() {
+                fs::remove_dir(root.join(shard))?;
+            }
+        }
+        if to == Sharding::Sharded {
+            for shard in shards() {
+                fs::create_dir(root.join(&shard))?;
+                DIDDirSys::set_permission(&root.join(shard))?;
+            }
+        }
+        for entry in fs::read_dir(&staging)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let dir = match (to, name.to_str()) {
+                (Sharding::Sharded, Some(pkid)) => root.join(shard(pkid)),
+                _ => root.to_path_buf()
+            };
+            fs::rename(entry.path(), dir.join(&name))?;
+        }
+        fs::remove_dir(&staging)?;
+        write_version(root, LAYOUT_VERSION, to)
+    }).map(Some)
+}
+
+/// Runs `f` on the keyring after copying it to `.backups`, putting the
+/// keyring back from the copy if `f` fails. Returns where the copy is.
+fn with_backup<F>(config: &Config, from: u32, f: F) -> io::Result<PathBuf>
+        where F: FnOnce() -> io::Result<()> {
+    let root = config.root_dir();
+    let backup = back_up(config, from)?;
+    write_file(&root.join(MIGRATING), backup.file_name().and_then(|n| n.to_str()).unwrap_or(""))?;
+    if let Err(e) = f() {
+        restore(root, &backup)?;
+        return Err(e);
+    }
+    fs::remove_file(root.join(MIGRATING))?;
+    Ok(backup)
+}
+
+/// The identity documents in the keyring, wherever they are.
+fn identity_files(config: &Config) -> io::Result<Vec<PathBuf>> {
+    let sharded = sharding(config)? == Sharding::Sharded;
+    let mut files = Vec::new();
+    for entry in fs::read_dir(config.root_dir())? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = match name.to_str() {
+            Some(name) if !name.starts_with('.') => name,
+            _ => continue
+        };
+        if entry.file_type()?.is_dir() {
+            if sharded && is_shard(name) {
+                for entry in fs::read_dir(entry.path())? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_file() {
+                        files.push(entry.path());
+                    }
+                }
+            }
+        } else if !sharded {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Puts back the keyring of a migration that was cut short.
+fn recover(config: &Config) -> io::Result<()> {
+    let root = config.root_dir();
+    let name = match fs::read_to_string(root.join(MIGRATING)) {
+        Ok(name) => name,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e)
+    };
+    let name = Pkid::new(name.trim())?;
+    restore(root, &root.join(BACKUPS).join(name.as_str()))
+}
+
+/// Marks a newly created keyring as having the current layout.
+pub(crate) fn init(root: &Path, sharding: Sharding) -> io::Result<()> {
+    write_version(root, LAYOUT_VERSION, sharding)
+}
+
+fn write_version(root: &Path, version: u32, sharding: Sharding) -> io::Result<()> {
+    match sharding {
+        Sharding::Flat => write_file(&root.join(VERSION), &format!("{}\n", version)),
+        Sharding::Sharded => write_file(&root.join(VERSION), &format!("{}\n{}\n", version, SHARDED))
+    }
+}
+
+/// Copies everything a migration may touch to a fresh dir in `.backups`.
+/// The copy is made under another name first, so a backup that exists is
+/// complete.
+fn back_up(config: &Config, from: u32) -> io::Result<PathBuf> {
+    let backups = config.root_dir().join(BACKUPS);
+    if !backups.is_dir() {
+        fs::create_dir(&backups)?;
+        DIDDirSys::set_permission(&backups)?;
+    }
+
+    let stamp = metadata::now();
+    let mut n = 0;
+    let backup = loop {
+        let name = match n {
+            0 => format!("v{}-{}", from, stamp),
+            n => format!("v{}-{}-{}", from, stamp, n)
+        };
+        let backup = backups.join(name);
+        if !backup.exists() {
+            break backup;
+        }
+        n += 1;
+    };
+
+    let partial = backups.join(format!("partial-{}", backup.file_name().and_then(|n| n.to_str()).unwrap_or("")));
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    fs::create_dir(&partial)?;
+    DIDDirSys::set_permission(&partial)?;
+    for entry in layout(config.root_dir())? {
+        copy(&entry, &partial.join(entry.file_name().unwrap()))?;
+    }
+    fs::rename(&partial, &backup)?;
+    Ok(backup)
+}
+
+/// Makes the keyring exactly what `backup` holds again.
+fn restore(root: &Path, backup: &Path) -> io::Result<()> {
+    if !backup.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound,
+            format!("Migration backup is missing: {}", backup.display())));
+    }
+
+    for entry in layout(root)? {
+        if fs::symlink_metadata(&entry)?.is_dir() {
+            fs::remove_dir_all(&entry)?;
+        } else {
+            fs::remove_file(&entry)?;
+        }
+    }
+    for entry in fs::read_dir(backup)? {
+        let entry = entry?;
+        copy(&entry.path(), &root.join(entry.file_name()))?;
+    }
+    match fs::remove_file(root.join(MIGRATING)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result
+    }
+}
+
+/// Everything in the root that belongs to the layout.
+fn layout(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if !UNTOUCHED.iter().any(|u| entry.file_name() == *u) {
+            entries.push(entry.path());
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(from)?.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+    }
+    DIDDirSys::set_permission(to)
+}
+
+/// Replaces `path` in one rename.
+fn write_file(path: &Path, data: &str) -> io::Result<()> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let tmp = path.with_file_name(format!(".{}.new", name.trim_start_matches('.')));
+    {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+    }
+    DIDDirSys::set_permission(&tmp)?;
+    fs::rename(&tmp, path)
+}
+
+/// The name a pkid or alias from before layout version 1 goes by after
+/// migrating: itself in NFC if it is valid, or else with every character
+/// names can't have, and `=`, written as `=` and the hex of its UTF-8
+/// bytes, as in `did=3Aexample=3A123`.
+pub fn legacy_name(name: &str) -> Result<String, NameError> {
+    match Pkid::new(name) {
+        Ok(pkid) => Ok(pkid.into()),
+        Err(NameError::InvalidChar(_)) => {
+            let mut encoded = String::new();
+            for c in name.chars() {
+                if c.is_alphanumeric() || (c != '=' && PUNCTUATION.contains(&c)) {
+                    encoded.push(c);
+                } else {
+                    let mut buf = [0; 4];
+                    for b in c.encode_utf8(&mut buf).bytes() {
+                        encoded.push_str(&format!("={:02X}", b));
+                    }
+                }
+            }
+            Pkid::new(&encoded).map(String::from)
+        },
+        Err(e) => Err(e)
+    }
+}
+
+/// 0 to 1: identities and aliases go by names that are valid now, and
+/// alias files hold just a valid pkid, no stray whitespace.
+fn normalize_names(config: &Config) -> io::Result<()> {
+    let mut renamed = BTreeMap::new();
+    for entry in fs::read_dir(config.root_dir())? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            if let Some((from, to)) = rename_legacy(&entry.path())? {
+                renamed.insert(from, to);
+            }
+        }
+    }
+
+    for entry in fs::read_dir(config.aliases_dir())? {
+        let entry = entry?;
+        let path = match rename_legacy(&entry.path())? {
+            Some((_, to)) => config.aliases_dir().join(to),
+            None => entry.path()
+        };
+        if path.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                rename_legacy(&entry?.path())?;
+            }
+        }
+    }
+    normalize_aliases(config, &renamed)
+}
+
+/// Gives the file or dir at `path` its `legacy_name`, returning the old
+/// name and the new one if it changed. Dotfiles are left alone.
+fn rename_legacy(path: &Path) -> io::Result<Option<(String, String)>> {
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) if !name.starts_with('.') => name,
+        _ => return Ok(None)
+    };
+    let legacy = legacy_name(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+        format!("{} can't be renamed to a valid name: {}", path.display(), e)))?;
+    if legacy == name {
+        return Ok(None);
+    }
+
+    let to = path.with_file_name(&legacy);
+    if fs::symlink_metadata(&to).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+            format!("{} can't be renamed to {}, which is taken", path.display(), legacy)));
+    }
+    fs::rename(path, &to)?;
+    Ok(Some((name.to_owned(), legacy)))
+}
+
+/// Alias files hold just a valid pkid, no stray whitespace, following the
+/// identities `renamed` from their old names.
+fn normalize_aliases(config: &Config, renamed: &BTreeMap<String, String>) -> io::Result<()> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(config.aliases_dir())? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            for entry in fs::read_dir(entry.path())? {
+                files.push(entry?.path());
+            }
+        } else {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+
+    for file in files {
+        let data = fs::read_to_string(&file)?;
+        if let Some(pkid) = renamed.get(data.trim()) {
+            write_file(&file, pkid)?;
+            continue;
+        }
+        let pkid = Pkid::new(data.trim()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+            format!("Alias {} doesn't hold a pkid: {}", file.display(), e)))?;
+        if pkid.as_str() != data {
+            write_file(&file, pkid.as_str())?;
+        }
+    }
+    Ok(())
+}
+
+/// 1 to 2: keyrings stay flat until resharded.
+fn stay_flat(_config: &Config) -> io::Result<()> {
+    Ok(())
+}
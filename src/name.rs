@@ -0,0 +1,190 @@
+use crate::config::{ALIASES, TMP};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use unicode_normalization::UnicodeNormalization;
+
+/// Names end up as file names, and the tmp dir appends a random suffix to
+/// them, so keep well clear of the usual 255 byte limit.
+pub const MAX_NAME_LEN: usize = 200;
+
+/// Separates the namespace of an alias from its name, as in `work/alice`.
+pub const NAMESPACE_SEPARATOR: char = '/';
+
+/// Punctuation allowed in names on top of letters and digits.
+pub(crate) static PUNCTUATION: &[char] = &['-', '_', '.', '@', '+', '='];
+
+/// Names that mean something else on some platform.
+static DEVICE_NAMES: &[&str] = &[
+    "con", "prn", "aux", "nul",
+    "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+    "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9"
+];
+
+/// Why a string can't be used as a pkid or alias.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NameError {
+    Empty,
+    TooLong(usize),
+    InvalidChar(char),
+    Reserved(String)
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameError::Empty => write!(f, "Name is empty"),
+            NameError::TooLong(len) =>
+                write!(f, "Name is {} bytes long, the limit is {}", len, MAX_NAME_LEN),
+            NameError::InvalidChar(c) => write!(f, "Name contains invalid character: {:?}", c),
+            NameError::Reserved(name) => write!(f, "Name is reserved: {}", name)
+        }
+    }
+}
+
+impl Error for NameError {}
+
+impl From<NameError> for io::Error {
+    fn from(e: NameError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+/// Normalizes `name` to NFC and checks it is safe to use as a file name
+/// inside a DIDDir: letters, digits and `-_.@+=` only, not starting with a
+/// dot, not one of the DIDDir's own directories and not a device name.
+fn validate(name: &str) -> Result<String, NameError> {
+    let name: String = name.nfc().collect();
+
+    if name.is_empty() {
+        return Err(NameError::Empty);
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(NameError::TooLong(name.len()));
+    }
+    if let Some(c) = name.chars().find(|c| !c.is_alphanumeric() && !PUNCTUATION.contains(c)) {
+        return Err(NameError::InvalidChar(c));
+    }
+
+    // dotfiles are DIDDir bookkeeping, which also rules out "." and ".."
+    let stem = name.split('.').next().unwrap_or("");
+    if name.starts_with('.') ||
+        name.eq_ignore_ascii_case(ALIASES) ||
+        name.eq_ignore_ascii_case(TMP) ||
+        DEVICE_NAMES.iter().any(|d| stem.eq_ignore_ascii_case(d)) {
+        return Err(NameError::Reserved(name));
+    }
+
+    Ok(name)
+}
+
+/// Like `validate`, but allows a namespace in front of the name. Namespaces
+/// follow the same rules as names. A leading separator with nothing in
+/// front of it, as in `/alice`, means no namespace.
+fn validate_alias(alias: &str) -> Result<String, NameError> {
+    let alias = alias.strip_prefix(NAMESPACE_SEPARATOR).unwrap_or(alias);
+    match alias.split_once(NAMESPACE_SEPARATOR) {
+        Some((namespace, name)) =>
+            Ok(format!("{}{}{}", validate(namespace)?, NAMESPACE_SEPARATOR, validate(name)?)),
+        None => validate(alias)
+    }
+}
+
+macro_rules! name_type {
+    ($(#[$attr:meta])* $name:ident, $validate:ident) => {
+        $(#[$attr])*
+        #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn new(name: &str) -> Result<Self, NameError> {
+                $validate(name).map($name)
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = NameError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Self::new(s)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(name: $name) -> Self {
+                name.0
+            }
+        }
+    }
+}
+
+name_type! {
+    /// The name an identity is stored under.
+    Pkid, validate
+}
+
+name_type! {
+    /// A human friendly name pointing at a pkid, optionally in a namespace,
+    /// e.g. `alice` or `work/alice`.
+    Alias, validate_alias
+}
+
+name_type! {
+    /// A group of aliases, like `work` in `work/alice`.
+    Namespace, validate
+}
+
+name_type! {
+    /// The name of a keyring kept alongside others under one `Config`.
+    Profile, validate
+}
+
+impl Alias {
+    /// Puts `alias` in `default_namespace` unless it names a namespace of
+    /// its own. `/alice` is `alice` without a namespace either way.
+    pub fn qualified(alias: &str, default_namespace: Option<&str>) -> Result<Self, NameError> {
+        match default_namespace {
+            Some(namespace) if !alias.contains(NAMESPACE_SEPARATOR) =>
+                Self::new(&format!("{}{}{}", namespace, NAMESPACE_SEPARATOR, alias)),
+            _ => Self::new(alias)
+        }
+    }
+
+    /// The alias spelled out so that no default namespace applies to it,
+    /// `/alice` for `alice`.
+    pub fn to_absolute(&self) -> String {
+        match self.namespace() {
+            Some(_) => self.0.clone(),
+            None => format!("{}{}", NAMESPACE_SEPARATOR, self.0)
+        }
+    }
+
+    /// `work` in `work/alice`.
+    pub fn namespace(&self) -> Option<&str> {
+        self.0.split_once(NAMESPACE_SEPARATOR).map(|(namespace, _)| namespace)
+    }
+
+    /// `alice` in `work/alice`.
+    pub fn name(&self) -> &str {
+        match self.0.split_once(NAMESPACE_SEPARATOR) {
+            Some((_, name)) => name,
+            None => &self.0
+        }
+    }
+}
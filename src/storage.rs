@@ -0,0 +1,182 @@
+use crate::Durability;
+use crate::dir::lock::{Lock, LockMode};
+use crate::sync::Entry;
+use crate::{audit, history, index, metadata, sync, trust};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub use self::fs::FsStorage;
+pub mod fs;
+
+pub use self::memory::MemoryStorage;
+pub mod memory;
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStorage;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// The kinds of record kept besides identities and aliases. The journal of
+/// a transaction under way isn't one of them.
+static RECORD_KINDS: [&str; 9] = [
+    history::INDEX, history::OBJECTS, metadata::METADATA, index::INDEX, trust::ATTESTATIONS,
+    sync::IDENTITY_TOMBSTONES, sync::ALIAS_TOMBSTONES, audit::ENTRIES, audit::HEAD
+];
+
+/// Where a DIDDir keeps its identities and aliases.
+///
+/// Implementations only have to store things; `DIDDir` takes care of
+/// locking, keeping aliases consistent with identities and the like. They
+/// must be safe to read from several threads at once, see `SharedDIDDir`.
+pub trait Storage: fmt::Debug + Send + Sync {
+    fn identities(&self) -> io::Result<Vec<String>>;
+
+    fn has_identity(&self, pkid: &str) -> io::Result<bool>;
+
+    fn read_identity(&self, pkid: &str) -> io::Result<String>;
+
+    /// Stores the identity, replacing any previous document atomically.
+    fn write_identity(&mut self, pkid: &str, data: &str) -> io::Result<()>;
+
+    fn delete_identity(&mut self, pkid: &str) -> io::Result<()>;
+
+    /// A value that changes whenever the identity is rewritten, used to spot
+    /// updates without reading the whole document.
+    fn identity_stamp(&self, pkid: &str) -> io::Result<u64>;
+
+    /// Lists all (alias, pkid) pairs.
+    fn aliases(&self) -> io::Result<Vec<(String, String)>>;
+
+    fn read_alias(&self, alias: &str) -> io::Result<String>;
+
+    fn aliases_of(&self, pkid: &str) -> io::Result<Vec<String>> {
+        Ok(self.aliases()?
+            .into_iter()
+            .filter(|(_, id)| id == pkid)
+            .map(|(alias, _)| alias)
+            .collect())
+    }
+
+    /// Stores the alias, replacing any previous one atomically.
+    fn write_alias(&mut self, alias: &str, pkid: &str) -> io::Result<()>;
+
+    fn delete_alias(&mut self, alias: &str) -> io::Result<()>;
+
+    /// Lists the keys of the records of `kind`. Records hold DIDDir's own
+    /// bookkeeping, e.g. tombstones for sync, next to identities and
+    /// aliases. Kinds are `/` separated names, keys are plain names.
+    fn records(&self, _kind: &str) -> io::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn read_record(&self, kind: &str, key: &str) -> io::Result<String> {
+        Err(not_found_record(kind, key))
+    }
+
+    fn write_record(&mut self, _kind: &str, _key: &str, _data: &str) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported,
+            "DIDDir storage can't keep records"))
+    }
+
+    fn delete_record(&mut self, kind: &str, key: &str) -> io::Result<()> {
+        Err(not_found_record(kind, key))
+    }
+
+    /// Drops anything cached from the backing store so changes made by
+    /// others become visible.
+    fn refresh(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Locks the backing store against other processes, if it is shared
+    /// with any.
+    fn lock(&self, _mode: LockMode) -> io::Result<Option<Lock>> {
+        Ok(None)
+    }
+
+    /// The file `lock` locks and how long it waits for it, for callers that
+    /// wait for the lock themselves, e.g. async code. `None` if `lock`
+    /// takes no lock.
+    fn lock_file(&self) -> Option<(&Path, Duration)> {
+        None
+    }
+
+    /// Where `write_identity` or `write_alias` would put `entry`, for
+    /// callers that write the file themselves, e.g. with async I/O. They
+    /// write the new contents to `Placement::tmp`, move it to
+    /// `Placement::path` and hand over with `placed`, all under the lock.
+    /// `None` if the storage isn't kept in files.
+    fn placement(&mut self, _entry: &Entry) -> io::Result<Option<Placement>> {
+        Ok(None)
+    }
+
+    /// Takes note of `data`, moved into place as `placement` said.
+    fn placed(&mut self, entry: &Entry, _data: &str) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported,
+            format!("DIDDir storage can't take {} from a file", entry.key())))
+    }
+
+    /// The directory everything is kept under, if there is one.
+    fn root_dir(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Directories to watch for changes made by others, if any.
+    fn watch_dirs(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+}
+
+/// A file to write an identity or alias to, see `Storage::placement`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Placement {
+    /// Where to write the contents first, next to nothing else.
+    pub tmp: PathBuf,
+    /// Where to move `tmp` to once it is written.
+    pub path: PathBuf,
+    /// How far to flush the file and the move before going on.
+    pub durability: Durability
+}
+
+/// Copies everything from one storage into another, e.g. to move a keyring
+/// between backends: identities, aliases and every record kept about them,
+/// like history, metadata or the audit log. `to` has to be empty.
+pub fn copy(from: &dyn Storage, to: &mut dyn Storage) -> io::Result<()> {
+    let empty = to.identities()?.is_empty() && to.aliases()?.is_empty() &&
+        RECORD_KINDS.iter().map(|kind| to.records(kind)).collect::<io::Result<Vec<_>>>()?
+            .iter().all(|keys| keys.is_empty());
+    if !empty {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+            "Storage to copy into is not empty"));
+    }
+
+    for pkid in from.identities()? {
+        to.write_identity(&pkid, &from.read_identity(&pkid)?)?;
+    }
+    for (alias, pkid) in from.aliases()? {
+        to.write_alias(&alias, &pkid)?;
+    }
+    for kind in RECORD_KINDS.iter() {
+        for key in from.records(kind)? {
+            to.write_record(kind, &key, &from.read_record(kind, &key)?)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn not_found_identity(pkid: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound,
+        format!("No identity file found for: {}", pkid))
+}
+
+pub(crate) fn not_found_alias(alias: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound,
+        format!("No identity found for: {}", alias))
+}
+
+pub(crate) fn not_found_record(kind: &str, key: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound,
+        format!("No {} record found for: {}", kind, key))
+}
@@ -0,0 +1,624 @@
+use crate::{Alias, Config, NameError, Pkid};
+use crate::config::{Durability, Sharding, DEFAULT_PROFILE, PROFILES};
+use crate::hash::sha256_hex;
+use crate::name::NAMESPACE_SEPARATOR;
+use crate::dir::DIDDirSys;
+use crate::dir::lock::{Lock, LockMode};
+use crate::migrate;
+use crate::sync::Entry;
+use rand;
+use rand::distributions::{Alphanumeric, Distribution};
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+use super::{not_found_alias, not_found_identity, not_found_record, Placement, Storage};
+
+/// A DIDDir root can also be a git repository.
+static GIT_DIR: &str = ".git";
+
+/// Stands in for the namespace separator in record file names.
+static RECORD_SEPARATOR: &str = "%";
+
+mod cache;
+use self::cache::{file_stamp, Entries, Kind};
+
+/// The shard dir of an identity in a sharded keyring.
+pub(crate) fn shard(pkid: &str) -> String {
+    sha256_hex(pkid.as_bytes())[..2].to_owned()
+}
+
+/// Whether `name` is one of the 256 shard dirs.
+pub(crate) fn is_shard(name: &str) -> bool {
+    name.len() == 2 && name.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// Every shard dir name, `00` to `ff`.
+pub(crate) fn shards() -> impl Iterator<Item = String> {
+    (0..=255u8).map(|b| format!("{:02x}", b))
+}
+
+/// The DIDDir directory layout: one file per identity in the root dir named
+/// after its pkid, one file per alias in the aliases dir containing a pkid,
+/// with namespaced aliases in a directory per namespace, and a tmp dir used
+/// to make every write an atomic rename. Keyrings can also be sharded, with
+/// the identities spread over 256 subdirectories, see `Sharding`.
+///
+/// Only the directory listings are read up front; documents and alias
+/// targets are read the first time they are needed and cached after that.
+///
+/// The storage either borrows its config or, opened with `open_owned` or
+/// `init_owned`, keeps a copy of its own.
+#[derive(Debug)]
+pub struct FsStorage<'a> {
+    config: Cow<'a, Config>,
+    sharding: Sharding,
+    read_only: bool,
+    ids: RwLock<Entries>,
+    aliases: RwLock<Entries>
+}
+
+impl<'a> FsStorage<'a> {
+
+    pub fn open(config: &'a Config) -> io::Result<Self> {
+        Self::open_with(Cow::Borrowed(config))
+    }
+
+    pub fn open_owned(config: Config) -> io::Result<FsStorage<'static>> {
+        FsStorage::open_with(Cow::Owned(config))
+    }
+
+    fn open_with(config: Cow<'a, Config>) -> io::Result<Self> {
+
+        Self::check_dirs_exist(&config)?;
+
+        // hold off writers while we check and read the DIDDir
+        let _lock = Lock::acquire(config.lock_file(), LockMode::Shared, config.lock_timeout())?;
+        DIDDirSys::check_parents(config.root_dir())?;
+        Self::check_permissions(config.root_dir(), DIDDirSys::check_permission)?;
+        Self::read(config, false)
+    }
+
+    /// Opens the keyring without ever creating, changing or locking
+    /// anything exclusively, so it also works on a read-only mount. Every
+    /// write fails with `PermissionDenied`.
+    pub fn open_read_only(config: &'a Config) -> io::Result<Self> {
+        Self::open_read_only_with(Cow::Borrowed(config))
+    }
+
+    pub fn open_read_only_owned(config: Config) -> io::Result<FsStorage<'static>> {
+        FsStorage::open_read_only_with(Cow::Owned(config))
+    }
+
+    fn open_read_only_with(config: Cow<'a, Config>) -> io::Result<Self> {
+        if !config.root_dir().is_dir() || !config.aliases_dir().is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                format!("No DIDDir at: {}", config.root_dir().display())));
+        }
+
+        let _lock = Lock::acquire_read_only(config.lock_file(), config.lock_timeout())?;
+        DIDDirSys::check_parents(config.root_dir())?;
+        Self::check_permissions(config.root_dir(), DIDDirSys::check_read_only_permission)?;
+        Self::read(config, true)
+    }
+
+    fn read(config: Cow<'a, Config>, read_only: bool) -> io::Result<Self> {
+        migrate::check(&config)?;
+        let sharding = migrate::sharding(&config)?;
+        let kind = match sharding {
+            Sharding::Flat => Kind::Identities,
+            Sharding::Sharded => Kind::ShardedIdentities
+        };
+        let ids = Entries::scan(config.root_dir(), kind)?;
+        let aliases = Entries::scan(config.aliases_dir(), Kind::Aliases)?;
+
+        Ok(FsStorage {
+            config,
+            sharding,
+            read_only,
+            ids: RwLock::new(ids),
+            aliases: RwLock::new(aliases)
+        })
+    }
+
+    pub fn init(config: &'a Config) -> io::Result<Self> {
+        Self::create(config)?;
+        Self::open(config)
+    }
+
+    pub fn init_owned(config: Config) -> io::Result<FsStorage<'static>> {
+        Self::create(&config)?;
+        FsStorage::open_owned(config)
+    }
+
+    fn create(config: &Config) -> io::Result<()> {
+        let dirs = vec![config.root_dir(), config.aliases_dir(), config.tmp_dir()];
+
+        for d in dirs {
+            if d.is_dir() {
+                // profiles live alongside the keyring in the base dir
+                let in_use = fs::read_dir(d)?
+                    .filter(|e| e.as_ref().map(|e| e.file_name() != PROFILES && e.file_name() != DEFAULT_PROFILE)
+                        .unwrap_or(true))
+                    .count();
+                if in_use > 0 {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                        format!("Error creating (already exists): {}",
+                        d.to_str().unwrap())));
+                }
+            } else {
+                // create the dirs
+                fs::create_dir_all(d)?;
+            }
+        }
+
+        if config.sharding() == Sharding::Sharded {
+            for shard in shards() {
+                fs::create_dir(config.root_dir().join(shard))?;
+            }
+        }
+        migrate::init(config.root_dir(), config.sharding())?;
+
+        // set the permissions correctly
+        Self::set_permissions(config.root_dir())
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Rereads the directory listings that changed, like `refresh`, but
+    /// through a shared reference.
+    pub fn rescan(&self) -> io::Result<()> {
+        self.id_entries_mut().refresh()?;
+        self.alias_entries_mut().refresh()
+    }
+
+    fn writable(&self) -> io::Result<()> {
+        if self.read_only {
+            return Err(read_only());
+        }
+        Ok(())
+    }
+
+    fn id_path(&self, pkid: &str) -> io::Result<PathBuf> {
+        let pkid = Pkid::new(pkid)?;
+        if self.config.is_subdir(pkid.as_str()) {
+            return Err(NameError::Reserved(pkid.into()).into());
+        }
+        Ok(self.id_entries().file(pkid.as_str()))
+    }
+
+    fn alias_path(&self, alias: &str) -> io::Result<PathBuf> {
+        let alias = Alias::new(alias)?;
+        let mut path = self.config.aliases_dir().to_path_buf();
+        if let Some(namespace) = alias.namespace() {
+            path.push(namespace);
+        }
+        path.push(alias.name());
+        Ok(path)
+    }
+
+    /// The file to save `alias` to. The first alias in a namespace brings
+    /// its directory.
+    fn alias_file(&self, alias: &str) -> io::Result<PathBuf> {
+        let path = self.alias_path(alias)?;
+
+        // an alias and a namespace of the same name would be a file and a
+        // directory at the same path
+        if path.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                format!("Can't save alias {}, there is a namespace of that name", alias)));
+        }
+
+        let dir = path.parent().unwrap();
+        if !dir.is_dir() {
+            if dir.exists() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                    format!("Can't save alias {}, there is an alias named after its namespace", alias)));
+            }
+            fs::create_dir(dir)?;
+            DIDDirSys::set_permission(dir)?;
+        }
+        Ok(path)
+    }
+
+    /// Records of kind `a/b` live in `<root>/.a/b/`, out of the way of
+    /// identities and of watchers.
+    fn record_dir(&self, kind: &str) -> io::Result<PathBuf> {
+        let mut dir = self.config.root_dir().to_path_buf();
+        for (i, segment) in kind.split('/').enumerate() {
+            if segment.is_empty() || !segment.chars().all(|c| c.is_ascii_lowercase() || c == '-') {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Invalid record kind: {}", kind)));
+            }
+            if i == 0 {
+                dir.push(format!(".{}", segment));
+            } else {
+                dir.push(segment);
+            }
+        }
+        Ok(dir)
+    }
+
+    /// Keys are names, optionally namespaced like aliases. The namespace
+    /// separator can't be in a file name so it is swapped for one that
+    /// can't be in a name either.
+    fn record_path(&self, kind: &str, key: &str) -> io::Result<PathBuf> {
+        let key = Alias::new(key)?;
+        Ok(self.record_dir(kind)?.join(key.as_str().replace(NAMESPACE_SEPARATOR, RECORD_SEPARATOR)))
+    }
+
+    fn write_file(&self, final_path: &Path, data: &str) -> io::Result<()> {
+        let name = final_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+
+        // get the path to a tmp file
+        let path = self.get_tmp_file_path(name)?;
+
+        // create the file, store the data
+        {
+            let mut file = fs::File::create(path.as_path())?;
+            file.write_all(data.as_bytes())?;
+            if self.config.durability() == Durability::Fsync {
+                file.sync_all()?;
+            }
+        }
+
+        // set the permissions
+        DIDDirSys::set_permission(&path)?;
+
+        // atomically move the file from the tmp dir to its final place
+        fs::rename(path, final_path)?;
+        self.sync_dir(final_path)
+    }
+
+    /// Flushes the directory holding `path` so a rename into or out of it
+    /// survives a crash, if the config asks for that.
+    fn sync_dir(&self, path: &Path) -> io::Result<()> {
+        if self.config.durability() != Durability::Fsync {
+            return Ok(());
+        }
+        match path.parent() {
+            // only unix lets a directory be opened and synced
+            #[cfg(unix)]
+            Some(dir) => fs::File::open(dir)?.sync_all(),
+            _ => Ok(())
+        }
+    }
+
+    fn delete_file(&self, path: &Path) -> io::Result<()> {
+        // generate a path to a .deleted-XXXXX file in tmp dir
+        let del_path = self.get_tmp_file_path(".deleted")?;
+
+        // atomically move the file to tmp dir and delete it
+        fs::rename(path, &del_path)?;
+        self.sync_dir(path)?;
+        fs::remove_file(del_path)?;
+
+        Ok(())
+    }
+
+    fn get_tmp_file_path(&self, name: &str) -> io::Result<PathBuf> {
+        for _ in 1..100 {
+            // generate a random string to append to the name
+            let mut rng = rand::thread_rng();
+            let rnd_ext: String = Alphanumeric.sample_iter(&mut rng).take(6).collect();
+
+            // calculate the full path
+            let mut tmp_path = PathBuf::new();
+            tmp_path.push(self.config.tmp_dir());
+            tmp_path.push(format!("{}-{}", name, rnd_ext));
+
+            if !tmp_path.exists() {
+                return Ok(tmp_path);
+            }
+        }
+        Err(io::Error::other(
+            "Could not calculate unique filename for tmp file."))
+    }
+
+    fn check_dirs_exist(config: &Config) -> io::Result<()> {
+        let dirs = vec![config.root_dir(), config.aliases_dir(), config.tmp_dir()];
+
+        for d in dirs {
+            if !d.is_dir() {
+                return Err(io::Error::new(io::ErrorKind::NotFound,
+                    format!("No DIDDir directory at: {}",
+                    d.to_str().unwrap())));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_permissions(path: &Path) -> io::Result<()> {
+        DIDDirSys::set_permission(path)?;
+
+        if path.is_dir() {
+            // check all contents of directory
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                if entry.file_name() == GIT_DIR {
+                    // git looks after its own files
+                    continue;
+                }
+                Self::set_permissions(&entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_permissions(path: &Path, check: fn(&Path) -> io::Result<()>) -> io::Result<()> {
+        check(path)?;
+
+        if path.is_dir() {
+            // check all contents of directory
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                if entry.file_name() == GIT_DIR {
+                    // git looks after its own files
+                    continue;
+                }
+                Self::check_permissions(&entry.path(), check)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn id_entries(&self) -> RwLockReadGuard<'_, Entries> {
+        self.ids.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn id_entries_mut(&self) -> RwLockWriteGuard<'_, Entries> {
+        self.ids.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn alias_entries(&self) -> RwLockReadGuard<'_, Entries> {
+        self.aliases.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn alias_entries_mut(&self) -> RwLockWriteGuard<'_, Entries> {
+        self.aliases.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Makes sure every alias target has been read, for reverse lookups.
+    fn load_aliases(&self) -> io::Result<()> {
+        if !self.alias_entries().is_loaded() {
+            self.alias_entries_mut().load_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Storage for FsStorage<'a> {
+
+    fn identities(&self) -> io::Result<Vec<String>> {
+        Ok(self.id_entries().names())
+    }
+
+    fn has_identity(&self, pkid: &str) -> io::Result<bool> {
+        Ok(self.id_path(pkid)?.is_file())
+    }
+
+    fn read_identity(&self, pkid: &str) -> io::Result<String> {
+        if let Some(data) = self.id_entries().cached(pkid) {
+            return Ok(data.to_owned());
+        }
+
+        match self.id_entries_mut().load(pkid)? {
+            Some(data) => Ok(data.to_owned()),
+            None => Err(not_found_identity(pkid))
+        }
+    }
+
+    fn write_identity(&mut self, pkid: &str, data: &str) -> io::Result<()> {
+        self.writable()?;
+        let pkid = Pkid::new(pkid)?;
+        let pkid = pkid.as_str();
+        let path = self.id_path(pkid)?;
+        let before = self.id_entries().modified(pkid);
+        self.write_file(&path, data)?;
+        self.id_entries_mut().insert(pkid, data, before)
+    }
+
+    fn delete_identity(&mut self, pkid: &str) -> io::Result<()> {
+        self.writable()?;
+        let pkid = Pkid::new(pkid)?;
+        let pkid = pkid.as_str();
+
+        // calculate the path to the DID doc
+        let root_path = self.id_path(pkid)?;
+
+        // if the DID doc doesn't exist, then throw an error
+        if !root_path.exists() {
+            return Err(io::Error::other(
+                       "Identity file does not exist"));
+        }
+
+        let before = self.id_entries().modified(pkid);
+        self.delete_file(&root_path)?;
+        self.id_entries_mut().remove(pkid, before)
+    }
+
+    fn identity_stamp(&self, pkid: &str) -> io::Result<u64> {
+        if !self.id_entries().contains(pkid) {
+            return Err(not_found_identity(pkid));
+        }
+        Ok(file_stamp(&fs::metadata(self.id_path(pkid)?)?))
+    }
+
+    fn aliases(&self) -> io::Result<Vec<(String, String)>> {
+        self.load_aliases()?;
+        Ok(self.alias_entries().all())
+    }
+
+    fn read_alias(&self, alias: &str) -> io::Result<String> {
+        if let Some(pkid) = self.alias_entries().cached(alias) {
+            return Ok(pkid.to_owned());
+        }
+
+        match self.alias_entries_mut().load(alias)? {
+            Some(pkid) => Ok(pkid.to_owned()),
+            None => Err(not_found_alias(alias))
+        }
+    }
+
+    fn write_alias(&mut self, alias: &str, pkid: &str) -> io::Result<()> {
+        self.writable()?;
+        let alias = Alias::new(alias)?;
+        let alias = alias.as_str();
+        let path = self.alias_file(alias)?;
+        let before = self.alias_entries().modified(alias);
+        self.write_file(&path, pkid)?;
+        self.alias_entries_mut().insert(alias, pkid, before)
+    }
+
+    fn delete_alias(&mut self, alias: &str) -> io::Result<()> {
+        self.writable()?;
+        let alias = Alias::new(alias)?;
+        let alias = alias.as_str();
+
+        // calculate the path to the alias file
+        let alias_path = self.alias_path(alias)?;
+
+        // if the alias file doesn't exist, then throw an error
+        if !alias_path.exists() {
+            return Err(io::Error::other(
+                       "Alias file does not exist"));
+        }
+
+        let before = self.alias_entries().modified(alias);
+        self.delete_file(&alias_path)?;
+        self.alias_entries_mut().remove(alias, before)?;
+
+        // and the last one takes it away again
+        let dir = alias_path.parent().unwrap();
+        if dir != self.config.aliases_dir() && fs::read_dir(dir)?.next().is_none() {
+            fs::remove_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    fn records(&self, kind: &str) -> io::Result<Vec<String>> {
+        let dir = self.record_dir(kind)?;
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            // no key has a name that isn't UTF-8, so it isn't one of ours
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue
+            };
+            if entry.file_type()?.is_file() {
+                keys.push(name.replace(RECORD_SEPARATOR, &NAMESPACE_SEPARATOR.to_string()));
+            }
+        }
+        Ok(keys)
+    }
+
+    fn read_record(&self, kind: &str, key: &str) -> io::Result<String> {
+        match fs::read_to_string(self.record_path(kind, key)?) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(not_found_record(kind, key)),
+            result => result
+        }
+    }
+
+    fn write_record(&mut self, kind: &str, key: &str, data: &str) -> io::Result<()> {
+        self.writable()?;
+        let path = self.record_path(kind, key)?;
+        let dir = self.record_dir(kind)?;
+        if !dir.is_dir() {
+            fs::create_dir_all(&dir)?;
+
+            // lock down every directory we might just have created
+            let mut created = dir.as_path();
+            while created != self.config.root_dir() {
+                DIDDirSys::set_permission(created)?;
+                created = created.parent().unwrap();
+            }
+        }
+        self.write_file(&path, data)
+    }
+
+    fn delete_record(&mut self, kind: &str, key: &str) -> io::Result<()> {
+        self.writable()?;
+        let path = self.record_path(kind, key)?;
+        if !path.is_file() {
+            return Err(not_found_record(kind, key));
+        }
+        self.delete_file(&path)
+    }
+
+    fn refresh(&self) -> io::Result<()> {
+        self.rescan()
+    }
+
+    fn lock(&self, mode: LockMode) -> io::Result<Option<Lock>> {
+        if Lock::is_lent(self.config.lock_file(), mode) {
+            return Ok(None);
+        }
+        match (self.read_only, mode) {
+            (false, _) => Lock::acquire(self.config.lock_file(), mode, self.config.lock_timeout()).map(Some),
+            (true, LockMode::Shared) => Lock::acquire_read_only(self.config.lock_file(), self.config.lock_timeout()),
+            (true, LockMode::Exclusive) => Err(read_only())
+        }
+    }
+
+    fn lock_file(&self) -> Option<(&Path, Duration)> {
+        match self.read_only {
+            false => Some((self.config.lock_file(), self.config.lock_timeout())),
+            true => None
+        }
+    }
+
+    fn placement(&mut self, entry: &Entry) -> io::Result<Option<Placement>> {
+        self.writable()?;
+        let path = match entry {
+            Entry::Identity(pkid) => self.id_path(pkid)?,
+            Entry::Alias(alias) => self.alias_file(alias)?
+        };
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        Ok(Some(Placement {
+            tmp: self.get_tmp_file_path(name)?,
+            path,
+            durability: self.config.durability()
+        }))
+    }
+
+    fn placed(&mut self, entry: &Entry, data: &str) -> io::Result<()> {
+        // whoever moved the file in kept no mtime from before, so the
+        // directory is looked at again on the next refresh
+        match entry {
+            Entry::Identity(pkid) => self.id_entries_mut().insert(Pkid::new(pkid)?.as_str(), data, None),
+            Entry::Alias(alias) => self.alias_entries_mut().insert(Alias::new(alias)?.as_str(), data, None)
+        }
+    }
+
+    fn root_dir(&self) -> Option<&Path> {
+        Some(self.config.root_dir())
+    }
+
+    fn watch_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![self.config.root_dir().to_path_buf(), self.config.aliases_dir().to_path_buf()];
+        if self.sharding == Sharding::Sharded {
+            dirs.extend(shards().map(|shard| self.config.root_dir().join(shard)));
+        }
+        dirs
+    }
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "DIDDir was opened read-only")
+}
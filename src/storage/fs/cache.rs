@@ -0,0 +1,300 @@
+use crate::name::NAMESPACE_SEPARATOR;
+use super::{is_shard, shard};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Directory mtimes are only trusted once they are older than this, so a
+/// change landing in the same clock tick as our last look isn't missed.
+static MTIME_GRANULARITY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Kind {
+    /// Identity documents in the root dir, where dotfiles are bookkeeping.
+    Identities,
+    /// Identity documents in shard dirs under the root, see `shard`.
+    ShardedIdentities,
+    /// Alias files, each holding a pkid, with namespaced aliases in a
+    /// directory per namespace.
+    Aliases
+}
+
+#[derive(Debug)]
+struct Entry {
+    stamp: u64,
+    data: String
+}
+
+#[derive(Debug)]
+struct DirStamp {
+    modified: SystemTime,
+    seen: SystemTime
+}
+
+/// The files in one DIDDir directory. Names are listed up front but
+/// contents are only read when first asked for and then kept until
+/// `refresh` notices the file changed.
+#[derive(Debug)]
+pub(crate) struct Entries {
+    path: PathBuf,
+    kind: Kind,
+    /// When each directory was last listed, keyed by namespace or shard
+    /// with "" for the directory itself.
+    seen: HashMap<String, DirStamp>,
+    entries: HashMap<String, Option<Entry>>
+}
+
+impl Entries {
+
+    pub(crate) fn scan(path: &Path, kind: Kind) -> io::Result<Self> {
+        let mut entries = Entries {
+            path: path.to_path_buf(),
+            kind,
+            seen: HashMap::new(),
+            entries: HashMap::new()
+        };
+        entries.rescan()?;
+        Ok(entries)
+    }
+
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.entries.keys().cloned().collect()
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    /// The contents of the file if we already read it.
+    pub(crate) fn cached(&self, name: &str) -> Option<&str> {
+        match self.entries.get(name) {
+            Some(Some(entry)) => Some(entry.data.as_str()),
+            _ => None
+        }
+    }
+
+    pub(crate) fn is_loaded(&self) -> bool {
+        self.entries.values().all(|e| e.is_some())
+    }
+
+    /// Reads the file if we haven't yet. Returns None if there is no such
+    /// entry.
+    pub(crate) fn load(&mut self, name: &str) -> io::Result<Option<&str>> {
+        let path = self.file(name);
+        let kind = self.kind;
+        match self.entries.get_mut(name) {
+            None => Ok(None),
+            Some(slot) => {
+                if slot.is_none() {
+                    *slot = Some(Self::read(&path, kind)?);
+                }
+                Ok(slot.as_ref().map(|e| e.data.as_str()))
+            }
+        }
+    }
+
+    pub(crate) fn load_all(&mut self) -> io::Result<()> {
+        for name in self.names() {
+            self.load(&name)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn all(&self) -> Vec<(String, String)> {
+        self.entries.iter()
+            .filter_map(|(name, e)| e.as_ref().map(|e| (name.to_owned(), e.data.to_owned())))
+            .collect()
+    }
+
+    /// Records a file we just wrote ourselves.
+    pub(crate) fn insert(&mut self, name: &str, data: &str, before: Option<SystemTime>) -> io::Result<()> {
+        let stamp = file_stamp(&fs::metadata(self.file(name))?);
+        self.entries.insert(name.to_owned(), Some(Entry {
+            stamp,
+            data: data.to_owned()
+        }));
+        let dir = self.dir_of(name);
+        self.touched(&dir, before)
+    }
+
+    /// Records a file we just removed ourselves.
+    pub(crate) fn remove(&mut self, name: &str, before: Option<SystemTime>) -> io::Result<()> {
+        self.entries.remove(name);
+        let dir = self.dir_of(name);
+        self.touched(&dir, before)
+    }
+
+    /// The mtime of the directory holding `name`, taken before making a
+    /// change of our own.
+    pub(crate) fn modified(&self, name: &str) -> Option<SystemTime> {
+        self.dir_modified(&self.dir_of(name))
+    }
+
+    /// Where the file of entry `name` is.
+    pub(crate) fn file(&self, name: &str) -> PathBuf {
+        match self.kind {
+            Kind::ShardedIdentities => self.path.join(shard(name)).join(name),
+            _ => self.path.join(name)
+        }
+    }
+
+    /// The subdirectory holding `name`, "" for the directory itself.
+    fn dir_of(&self, name: &str) -> String {
+        match self.kind {
+            Kind::Identities => String::new(),
+            Kind::ShardedIdentities => shard(name),
+            Kind::Aliases => namespace_of(name).to_owned()
+        }
+    }
+
+    fn dir_modified(&self, dir: &str) -> Option<SystemTime> {
+        fs::metadata(self.path.join(dir)).and_then(|m| m.modified()).ok()
+    }
+
+    /// Picks up changes made by others. Directories whose mtime hasn't moved
+    /// since we last looked are skipped entirely.
+    pub(crate) fn refresh(&mut self) -> io::Result<()> {
+        if self.unchanged() {
+            return Ok(());
+        }
+        self.rescan()
+    }
+
+    fn unchanged(&self) -> bool {
+        // a namespace or shard coming or going changes the directory itself
+        !self.seen.is_empty() && self.seen.iter().all(|(dir, seen)| {
+            match self.dir_modified(dir) {
+                Some(modified) => seen.modified == modified &&
+                    seen.seen.duration_since(modified).map(|d| d >= MTIME_GRANULARITY).unwrap_or(false),
+                None => false
+            }
+        })
+    }
+
+    fn rescan(&mut self) -> io::Result<()> {
+        if !self.path.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                format!("No DIDDir at: {}",
+                self.path.to_str().unwrap())));
+        }
+
+        let mut entries = HashMap::new();
+        let mut seen = HashMap::new();
+        let mut dirs = vec![String::new()];
+
+        while let Some(dir) = dirs.pop() {
+            // look at the mtime first so anything changing while we list
+            // gets picked up next time
+            if let Some(modified) = self.dir_modified(&dir) {
+                seen.insert(dir.clone(), DirStamp {
+                    modified,
+                    seen: SystemTime::now()
+                });
+            }
+
+            for entry in fs::read_dir(self.path.join(&dir))? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                // no pkid or alias has a name that isn't UTF-8
+                let name = match entry.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(_) => continue
+                };
+                if self.kind != Kind::Aliases && name.starts_with('.') {
+                    // dotfiles in the root are DIDDir bookkeeping, not identities
+                    continue;
+                }
+
+                if metadata.is_dir() {
+                    let descend = match self.kind {
+                        Kind::Identities => false,
+                        Kind::ShardedIdentities => is_shard(&name),
+                        Kind::Aliases => true
+                    };
+                    if descend && dir.is_empty() {
+                        dirs.push(name);
+                    }
+                    continue;
+                }
+
+                let name = match self.kind {
+                    // sharded identities are all in shards, by their own name
+                    Kind::ShardedIdentities if dir.is_empty() => continue,
+                    Kind::Aliases if !dir.is_empty() =>
+                        format!("{}{}{}", dir, NAMESPACE_SEPARATOR, name),
+                    _ => name
+                };
+
+                // keep what we've read unless the file was replaced since
+                let cached = match self.entries.remove(&name) {
+                    Some(Some(e)) if e.stamp == file_stamp(&metadata) => Some(e),
+                    _ => None
+                };
+                entries.insert(name, cached);
+            }
+        }
+
+        self.entries = entries;
+        self.seen = seen;
+        Ok(())
+    }
+
+    fn touched(&mut self, dir: &str, before: Option<SystemTime>) -> io::Result<()> {
+        // if nobody else had changed the directory before our own change we
+        // are still up to date, otherwise leave it for the next refresh
+        let current = match self.seen.get(dir) {
+            Some(seen) => Some(seen.modified) == before,
+            None => false
+        };
+        if current {
+            if let Some(modified) = self.dir_modified(dir) {
+                self.seen.insert(dir.to_owned(), DirStamp {
+                    modified,
+                    seen: SystemTime::now()
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn read(path: &Path, kind: Kind) -> io::Result<Entry> {
+        let metadata = fs::metadata(path)?;
+        let data = fs::read_to_string(path)?;
+        let data = match kind {
+            Kind::Aliases => data.trim().to_owned(),
+            _ => data
+        };
+        Ok(Entry {
+            stamp: file_stamp(&metadata),
+            data
+        })
+    }
+}
+
+/// The namespace of an alias, "" if it has none.
+fn namespace_of(name: &str) -> &str {
+    name.split_once(NAMESPACE_SEPARATOR).map(|(namespace, _)| namespace).unwrap_or("")
+}
+
+/// Changes whenever the file is replaced or written to. The mtime alone
+/// can miss a change landing in the same clock tick, or one whose mtime
+/// was set back, so on unix the inode, which a rename of a fresh file
+/// always changes, and the ctime, which can't be set, go in too.
+pub(crate) fn file_stamp(metadata: &fs::Metadata) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    metadata.modified().ok().hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.dev().hash(&mut hasher);
+        metadata.ino().hash(&mut hasher);
+        metadata.ctime().hash(&mut hasher);
+        metadata.ctime_nsec().hash(&mut hasher);
+    }
+    hasher.finish()
+}
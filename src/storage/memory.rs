@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::io;
+use super::{not_found_alias, not_found_identity, not_found_record, Storage};
+
+/// Keeps everything in memory, for tests, embedding and targets without a
+/// filesystem. Nothing is shared with other processes so no locking is done.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    ids: HashMap<String, (String, u64)>,
+    aliases: HashMap<String, String>,
+    records: HashMap<(String, String), String>,
+    generation: u64
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+
+    fn identities(&self) -> io::Result<Vec<String>> {
+        Ok(self.ids.keys().cloned().collect())
+    }
+
+    fn has_identity(&self, pkid: &str) -> io::Result<bool> {
+        Ok(self.ids.contains_key(pkid))
+    }
+
+    fn read_identity(&self, pkid: &str) -> io::Result<String> {
+        match self.ids.get(pkid) {
+            Some((data, _)) => Ok(data.to_owned()),
+            None => Err(not_found_identity(pkid))
+        }
+    }
+
+    fn write_identity(&mut self, pkid: &str, data: &str) -> io::Result<()> {
+        self.generation += 1;
+        self.ids.insert(pkid.to_owned(), (data.to_owned(), self.generation));
+        Ok(())
+    }
+
+    fn delete_identity(&mut self, pkid: &str) -> io::Result<()> {
+        match self.ids.remove(pkid) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::other("Identity file does not exist"))
+        }
+    }
+
+    fn identity_stamp(&self, pkid: &str) -> io::Result<u64> {
+        match self.ids.get(pkid) {
+            Some((_, generation)) => Ok(*generation),
+            None => Err(not_found_identity(pkid))
+        }
+    }
+
+    fn aliases(&self) -> io::Result<Vec<(String, String)>> {
+        Ok(self.aliases.iter().map(|(a, p)| (a.to_owned(), p.to_owned())).collect())
+    }
+
+    fn read_alias(&self, alias: &str) -> io::Result<String> {
+        match self.aliases.get(alias) {
+            Some(pkid) => Ok(pkid.to_owned()),
+            None => Err(not_found_alias(alias))
+        }
+    }
+
+    fn write_alias(&mut self, alias: &str, pkid: &str) -> io::Result<()> {
+        self.aliases.insert(alias.to_owned(), pkid.to_owned());
+        Ok(())
+    }
+
+    fn delete_alias(&mut self, alias: &str) -> io::Result<()> {
+        match self.aliases.remove(alias) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::other("Alias file does not exist"))
+        }
+    }
+
+    fn records(&self, kind: &str) -> io::Result<Vec<String>> {
+        Ok(self.records.keys()
+            .filter(|(k, _)| k == kind)
+            .map(|(_, key)| key.to_owned())
+            .collect())
+    }
+
+    fn read_record(&self, kind: &str, key: &str) -> io::Result<String> {
+        match self.records.get(&(kind.to_owned(), key.to_owned())) {
+            Some(data) => Ok(data.to_owned()),
+            None => Err(not_found_record(kind, key))
+        }
+    }
+
+    fn write_record(&mut self, kind: &str, key: &str, data: &str) -> io::Result<()> {
+        self.records.insert((kind.to_owned(), key.to_owned()), data.to_owned());
+        Ok(())
+    }
+
+    fn delete_record(&mut self, kind: &str, key: &str) -> io::Result<()> {
+        match self.records.remove(&(kind.to_owned(), key.to_owned())) {
+            Some(_) => Ok(()),
+            None => Err(not_found_record(kind, key))
+        }
+    }
+}
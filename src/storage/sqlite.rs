@@ -0,0 +1,270 @@
+use crate::{Config, Document};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use super::{copy, not_found_alias, not_found_identity, not_found_record, FsStorage, Storage};
+
+static SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS identities (
+        pkid TEXT PRIMARY KEY NOT NULL,
+        data TEXT NOT NULL,
+        did TEXT,
+        stamp INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS identities_did ON identities(did);
+
+    CREATE TABLE IF NOT EXISTS aliases (
+        alias TEXT PRIMARY KEY NOT NULL,
+        pkid TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS aliases_pkid ON aliases(pkid);
+
+    CREATE TABLE IF NOT EXISTS keys (
+        pkid TEXT NOT NULL,
+        key_id TEXT NOT NULL,
+        controller TEXT NOT NULL,
+        fingerprint TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS keys_pkid ON keys(pkid);
+    CREATE INDEX IF NOT EXISTS keys_controller ON keys(controller);
+    CREATE INDEX IF NOT EXISTS keys_fingerprint ON keys(fingerprint);
+
+    CREATE TABLE IF NOT EXISTS records (
+        kind TEXT NOT NULL,
+        key TEXT NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (kind, key)
+    );
+";
+
+/// Keeps the keyring in a single SQLite database, indexed so lookups don't
+/// have to load every identity. Documents that parse as DID documents also
+/// get their DID, key controllers and key fingerprints indexed.
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+    path: Option<PathBuf>
+}
+
+impl fmt::Debug for SqliteStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SqliteStorage").field("path", &self.path).finish()
+    }
+}
+
+impl SqliteStorage {
+
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let conn = Connection::open(path).map_err(io::Error::other)?;
+        Self::with_connection(conn, Some(path.to_path_buf()))
+    }
+
+    pub fn open_in_memory() -> io::Result<Self> {
+        let conn = Connection::open_in_memory().map_err(io::Error::other)?;
+        Self::with_connection(conn, None)
+    }
+
+    /// Creates a database at `path` holding everything in the DIDDir
+    /// directory layout described by `config`.
+    pub fn import_dir(path: &Path, config: &Config) -> io::Result<Self> {
+        let from = FsStorage::open(config)?;
+        let mut to = Self::open(path)?;
+        to.batch(|to| copy(&from, to))?;
+        Ok(to)
+    }
+
+    /// Writes everything in the database out to a new DIDDir directory
+    /// layout described by `config`.
+    pub fn export_dir(&self, config: &Config) -> io::Result<()> {
+        let mut to = FsStorage::init(config)?;
+        copy(self, &mut to)
+    }
+
+    pub fn find_by_did(&self, did: &str) -> io::Result<Vec<String>> {
+        self.query_pkids("SELECT pkid FROM identities WHERE did = ?1", did)
+    }
+
+    pub fn find_by_controller(&self, controller: &str) -> io::Result<Vec<String>> {
+        self.query_pkids("SELECT DISTINCT pkid FROM keys WHERE controller = ?1", controller)
+    }
+
+    /// Finds identities holding a key with the given `PublicKey::fingerprint`.
+    pub fn find_by_fingerprint(&self, fingerprint: &str) -> io::Result<Vec<String>> {
+        self.query_pkids("SELECT DISTINCT pkid FROM keys WHERE fingerprint = ?1", fingerprint)
+    }
+
+    fn with_connection(conn: Connection, path: Option<PathBuf>) -> io::Result<Self> {
+        conn.execute_batch(SCHEMA).map_err(io::Error::other)?;
+        Ok(SqliteStorage { conn: Mutex::new(conn), path })
+    }
+
+    /// A connection can't be used from two threads at once.
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn conn_mut(&mut self) -> &mut Connection {
+        self.conn.get_mut().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs `f` inside a single transaction, which is a lot faster for bulk
+    /// writes.
+    fn batch<F>(&mut self, f: F) -> io::Result<()>
+        where F: FnOnce(&mut Self) -> io::Result<()>
+    {
+        self.conn_mut().execute_batch("SAVEPOINT batch").map_err(io::Error::other)?;
+        match f(self) {
+            Ok(()) => self.conn_mut().execute_batch("RELEASE batch").map_err(io::Error::other),
+            Err(e) => {
+                let _ = self.conn_mut().execute_batch("ROLLBACK TO batch; RELEASE batch");
+                Err(e)
+            }
+        }
+    }
+
+    fn query_pkids(&self, sql: &str, value: &str) -> io::Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached(sql).map_err(io::Error::other)?;
+        let rows = stmt.query_map(params![value], |row| row.get(0)).map_err(io::Error::other)?;
+        rows.collect::<Result<Vec<String>, _>>().map_err(io::Error::other)
+    }
+
+    fn write_identity_rows(&mut self, pkid: &str, data: &str) -> rusqlite::Result<()> {
+        // anything that isn't a DID document is stored but not indexed
+        let doc: Option<Document> = serde_json::from_str(data).ok();
+
+        let tx = self.conn_mut().savepoint()?;
+        tx.execute("INSERT INTO identities (pkid, data, did, stamp)
+                    VALUES (?1, ?2, ?3, (SELECT COALESCE(MAX(stamp), 0) + 1 FROM identities))
+                    ON CONFLICT(pkid) DO UPDATE SET
+                        data = excluded.data, did = excluded.did, stamp = excluded.stamp",
+            params![pkid, data, doc.as_ref().map(|d| d.id.as_str())])?;
+        tx.execute("DELETE FROM keys WHERE pkid = ?1", params![pkid])?;
+        if let Some(doc) = doc {
+            for key in doc.public_key.iter() {
+                tx.execute("INSERT INTO keys (pkid, key_id, controller, fingerprint)
+                            VALUES (?1, ?2, ?3, ?4)",
+                    params![pkid, key.id.as_str(), key.controller.as_str(), key.fingerprint()])?;
+            }
+        }
+        tx.commit()
+    }
+}
+
+impl Storage for SqliteStorage {
+
+    fn identities(&self) -> io::Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached("SELECT pkid FROM identities")
+            .map_err(io::Error::other)?;
+        let rows = stmt.query_map([], |row| row.get(0)).map_err(io::Error::other)?;
+        rows.collect::<Result<Vec<String>, _>>().map_err(io::Error::other)
+    }
+
+    fn has_identity(&self, pkid: &str) -> io::Result<bool> {
+        self.conn().query_row("SELECT 1 FROM identities WHERE pkid = ?1", params![pkid], |_| Ok(()))
+            .optional()
+            .map(|row| row.is_some())
+            .map_err(io::Error::other)
+    }
+
+    fn read_identity(&self, pkid: &str) -> io::Result<String> {
+        self.conn().query_row("SELECT data FROM identities WHERE pkid = ?1", params![pkid], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?
+            .ok_or_else(|| not_found_identity(pkid))
+    }
+
+    fn write_identity(&mut self, pkid: &str, data: &str) -> io::Result<()> {
+        self.write_identity_rows(pkid, data).map_err(io::Error::other)
+    }
+
+    fn delete_identity(&mut self, pkid: &str) -> io::Result<()> {
+        let tx = self.conn_mut().savepoint().map_err(io::Error::other)?;
+        let deleted = tx.execute("DELETE FROM identities WHERE pkid = ?1", params![pkid])
+            .map_err(io::Error::other)?;
+        if deleted == 0 {
+            return Err(io::Error::other("Identity file does not exist"));
+        }
+        tx.execute("DELETE FROM keys WHERE pkid = ?1", params![pkid]).map_err(io::Error::other)?;
+        tx.commit().map_err(io::Error::other)
+    }
+
+    fn identity_stamp(&self, pkid: &str) -> io::Result<u64> {
+        let stamp: Option<i64> = self.conn()
+            .query_row("SELECT stamp FROM identities WHERE pkid = ?1", params![pkid], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?;
+        match stamp {
+            Some(stamp) => Ok(stamp as u64),
+            None => Err(not_found_identity(pkid))
+        }
+    }
+
+    fn aliases(&self) -> io::Result<Vec<(String, String)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached("SELECT alias, pkid FROM aliases")
+            .map_err(io::Error::other)?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(io::Error::other)?;
+        rows.collect::<Result<Vec<(String, String)>, _>>().map_err(io::Error::other)
+    }
+
+    fn read_alias(&self, alias: &str) -> io::Result<String> {
+        self.conn().query_row("SELECT pkid FROM aliases WHERE alias = ?1", params![alias], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?
+            .ok_or_else(|| not_found_alias(alias))
+    }
+
+    fn aliases_of(&self, pkid: &str) -> io::Result<Vec<String>> {
+        self.query_pkids("SELECT alias FROM aliases WHERE pkid = ?1", pkid)
+    }
+
+    fn write_alias(&mut self, alias: &str, pkid: &str) -> io::Result<()> {
+        self.conn_mut().execute("INSERT OR REPLACE INTO aliases (alias, pkid) VALUES (?1, ?2)",
+            params![alias, pkid])
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+
+    fn delete_alias(&mut self, alias: &str) -> io::Result<()> {
+        let deleted = self.conn_mut().execute("DELETE FROM aliases WHERE alias = ?1", params![alias])
+            .map_err(io::Error::other)?;
+        if deleted == 0 {
+            return Err(io::Error::other("Alias file does not exist"));
+        }
+        Ok(())
+    }
+
+    fn records(&self, kind: &str) -> io::Result<Vec<String>> {
+        self.query_pkids("SELECT key FROM records WHERE kind = ?1", kind)
+    }
+
+    fn read_record(&self, kind: &str, key: &str) -> io::Result<String> {
+        self.conn().query_row("SELECT data FROM records WHERE kind = ?1 AND key = ?2",
+            params![kind, key], |row| row.get(0))
+            .optional()
+            .map_err(io::Error::other)?
+            .ok_or_else(|| not_found_record(kind, key))
+    }
+
+    fn write_record(&mut self, kind: &str, key: &str, data: &str) -> io::Result<()> {
+        self.conn_mut().execute("INSERT OR REPLACE INTO records (kind, key, data) VALUES (?1, ?2, ?3)",
+            params![kind, key, data])
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+
+    fn delete_record(&mut self, kind: &str, key: &str) -> io::Result<()> {
+        let deleted = self.conn_mut().execute("DELETE FROM records WHERE kind = ?1 AND key = ?2",
+            params![kind, key])
+            .map_err(io::Error::other)?;
+        if deleted == 0 {
+            return Err(not_found_record(kind, key));
+        }
+        Ok(())
+    }
+}
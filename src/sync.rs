@@ -0,0 +1,474 @@
+use crate::hash::sha256_hex;
+use crate::storage::Storage;
+use crate::{Alias, Archive, DIDDir, Transaction};
+use serde_derive::{Serialize, Deserialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+/// Record kinds holding the hash of whatever was last removed, so a removal
+/// on one side can be told apart from an addition on the other.
+pub(crate) static IDENTITY_TOMBSTONES: &str = "tombstones/identities";
+pub(crate) static ALIAS_TOMBSTONES: &str = "tombstones/aliases";
+
+/// Something that is synced: an identity or an alias.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Entry {
+    Identity(String),
+    Alias(String)
+}
+
+impl Entry {
+    /// `identities/<pkid>` or `aliases/<alias>`, as used in archive
+    /// manifests.
+    pub fn key(&self) -> String {
+        match self {
+            Entry::Identity(pkid) => format!("identities/{}", pkid),
+            Entry::Alias(alias) => format!("aliases/{}", alias)
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        if let Some(pkid) = key.strip_prefix("identities/") {
+            Some(Entry::Identity(pkid.to_owned()))
+        } else {
+            key.strip_prefix("aliases/").map(|alias| Entry::Alias(alias.to_owned()))
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// Add or replace an entry. An alias's content is its pkid.
+    Put(Entry, String),
+    Remove(Entry)
+}
+
+impl Change {
+    pub fn entry(&self) -> &Entry {
+        match self {
+            Change::Put(entry, _) => entry,
+            Change::Remove(entry) => entry
+        }
+    }
+}
+
+/// One side of a sync.
+pub trait Replica {
+    /// Every identity and alias with its content.
+    fn entries(&self) -> io::Result<BTreeMap<Entry, String>>;
+
+    /// The content hash of every removed entry when it was removed.
+    fn tombstones(&self) -> io::Result<BTreeMap<Entry, String>>;
+
+    fn apply(&mut self, change: &Change) -> io::Result<()>;
+
+    /// Makes all of `changes`, which were planned against `seen`, provided
+    /// none of the entries they touch changed since. Otherwise fails without
+    /// changing anything, e.g. when somebody else wrote in the meantime.
+    fn apply_all(&mut self, changes: &[Change], seen: &BTreeMap<Entry, String>) -> io::Result<()> {
+        let entries = self.entries()?;
+        for change in changes {
+            check(change.entry(), entries.get(change.entry()), seen)?;
+        }
+        for change in changes {
+            self.apply(change)?;
+        }
+        Ok(())
+    }
+}
+
+/// An entry both sides changed since they last agreed. `None` means that
+/// side removed it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyncConflict {
+    pub entry: Entry,
+    pub left: Option<String>,
+    pub right: Option<String>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+    /// Make the right side match the left.
+    Left,
+    /// Make the left side match the right.
+    Right,
+    /// Leave both alone, it stays a conflict.
+    Skip
+}
+
+pub trait ConflictResolver {
+    fn resolve(&mut self, conflict: &SyncConflict) -> Resolution;
+}
+
+impl<F: FnMut(&SyncConflict) -> Resolution> ConflictResolver for F {
+    fn resolve(&mut self, conflict: &SyncConflict) -> Resolution {
+        self(conflict)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PreferLeft;
+
+impl ConflictResolver for PreferLeft {
+    fn resolve(&mut self, _conflict: &SyncConflict) -> Resolution {
+        Resolution::Left
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PreferRight;
+
+impl ConflictResolver for PreferRight {
+    fn resolve(&mut self, _conflict: &SyncConflict) -> Resolution {
+        Resolution::Right
+    }
+}
+
+/// The resolvers a `Config` can name, `Skip` leaving conflicts for later.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Resolver {
+    #[default]
+    Skip,
+    PreferLeft,
+    PreferRight
+}
+
+impl ConflictResolver for Resolver {
+    fn resolve(&mut self, _conflict: &SyncConflict) -> Resolution {
+        match self {
+            Resolver::Skip => Resolution::Skip,
+            Resolver::PreferLeft => Resolution::Left,
+            Resolver::PreferRight => Resolution::Right
+        }
+    }
+}
+
+/// What both sides agreed on after a sync. Handing it to the next sync
+/// lets an update on one side be told apart from a conflicting edit.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SyncState {
+    hashes: BTreeMap<String, String>
+}
+
+impl SyncState {
+    pub fn hash(&self, entry: &Entry) -> Option<&str> {
+        self.hashes.get(&entry.key()).map(|h| h.as_str())
+    }
+
+    /// Both sides agreeing on `entries`, e.g. as of a common git commit.
+    pub(crate) fn of(entries: &BTreeMap<Entry, String>) -> Self {
+        SyncState {
+            hashes: entries.iter().map(|(entry, data)| (entry.key(), sha256_hex(data.as_bytes()))).collect()
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncPlan {
+    pub to_left: Vec<Change>,
+    pub to_right: Vec<Change>,
+    pub conflicts: Vec<SyncConflict>
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncReport {
+    pub to_left: Vec<Change>,
+    pub to_right: Vec<Change>,
+    pub conflicts: Vec<(SyncConflict, Resolution)>,
+    pub state: SyncState
+}
+
+/// Works out what each side needs to catch up with the other, without
+/// changing anything.
+pub fn plan(left: &dyn Replica, right: &dyn Replica, base: Option<&SyncState>) -> io::Result<SyncPlan> {
+    let (l, r) = (left.entries()?, right.entries()?);
+    plan_entries(left, right, &l, &r, base)
+}
+
+pub(crate) fn plan_entries(left: &dyn Replica, right: &dyn Replica, l: &BTreeMap<Entry, String>,
+                           r: &BTreeMap<Entry, String>, base: Option<&SyncState>) -> io::Result<SyncPlan> {
+    let (lt, rt) = (left.tombstones()?, right.tombstones()?);
+    let empty = SyncState::default();
+    let base = base.unwrap_or(&empty);
+
+    let mut plan = SyncPlan::default();
+    let entries: BTreeSet<&Entry> = l.keys().chain(r.keys()).collect();
+    for entry in entries {
+        let (ld, rd) = (l.get(entry), r.get(entry));
+        let lh = ld.map(|d| sha256_hex(d.as_bytes()));
+        let rh = rd.map(|d| sha256_hex(d.as_bytes()));
+        let b = base.hash(entry);
+
+        match (ld, rd) {
+            (Some(ld), None) => {
+                let lh = lh.as_deref();
+                let tomb = rt.get(entry).map(|t| t.as_str());
+                if tomb == lh || b == lh {
+                    // right removed what left still has
+                    plan.to_left.push(Change::Remove(entry.clone()));
+                } else if tomb.is_none() && b.is_none() {
+                    plan.to_right.push(Change::Put(entry.clone(), ld.to_owned()));
+                } else {
+                    plan.conflicts.push(conflict(entry, Some(ld), None));
+                }
+            },
+            (None, Some(rd)) => {
+                let rh = rh.as_deref();
+                let tomb = lt.get(entry).map(|t| t.as_str());
+                if tomb == rh || b == rh {
+                    plan.to_right.push(Change::Remove(entry.clone()));
+                } else if tomb.is_none() && b.is_none() {
+                    plan.to_left.push(Change::Put(entry.clone(), rd.to_owned()));
+                } else {
+                    plan.conflicts.push(conflict(entry, None, Some(rd)));
+                }
+            },
+            (Some(ld), Some(rd)) if lh != rh => {
+                if b == lh.as_deref() {
+                    plan.to_left.push(Change::Put(entry.clone(), rd.to_owned()));
+                } else if b == rh.as_deref() {
+                    plan.to_right.push(Change::Put(entry.clone(), ld.to_owned()));
+                } else {
+                    plan.conflicts.push(conflict(entry, Some(ld), Some(rd)));
+                }
+            },
+            _ => {}
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Brings both sides up to date with each other. Conflicts go to
+/// `resolver`; nothing is settled by which side happened to write last.
+///
+/// Each side takes its changes all at once, and only if what they were
+/// planned against is still there, see `Replica::apply_all`. A side that
+/// changed during the sync fails it; syncing again picks up from there.
+pub fn sync(left: &mut dyn Replica, right: &mut dyn Replica, base: Option<&SyncState>,
+            resolver: &mut dyn ConflictResolver) -> io::Result<SyncReport> {
+    let (l, r) = (left.entries()?, right.entries()?);
+    let plan = plan_entries(left, right, &l, &r, base)?;
+    let mut report = SyncReport {
+        to_left: plan.to_left,
+        to_right: plan.to_right,
+        ..Default::default()
+    };
+
+    for conflict in plan.conflicts {
+        let resolution = resolver.resolve(&conflict);
+        let change = |data: &Option<String>| match data {
+            Some(data) => Change::Put(conflict.entry.clone(), data.to_owned()),
+            None => Change::Remove(conflict.entry.clone())
+        };
+        match resolution {
+            Resolution::Left => report.to_right.push(change(&conflict.left)),
+            Resolution::Right => report.to_left.push(change(&conflict.right)),
+            Resolution::Skip => {}
+        }
+        report.conflicts.push((conflict, resolution));
+    }
+
+    apply(left, &mut report.to_left, &l)?;
+    apply(right, &mut report.to_right, &r)?;
+
+    // remember what both sides now agree on, and what they last agreed on
+    // for anything left in conflict
+    let (l, r) = (left.entries()?, right.entries()?);
+    for (entry, data) in l.iter() {
+        if r.get(entry) == Some(data) {
+            report.state.hashes.insert(entry.key(), sha256_hex(data.as_bytes()));
+        }
+    }
+    if let Some(base) = base {
+        for (conflict, _) in report.conflicts.iter() {
+            if let Some(hash) = base.hash(&conflict.entry) {
+                report.state.hashes.entry(conflict.entry.key()).or_insert_with(|| hash.to_owned());
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn conflict(entry: &Entry, left: Option<&String>, right: Option<&String>) -> SyncConflict {
+    SyncConflict {
+        entry: entry.clone(),
+        left: left.cloned(),
+        right: right.cloned()
+    }
+}
+
+pub(crate) fn apply(replica: &mut dyn Replica, changes: &mut [Change], seen: &BTreeMap<Entry, String>) -> io::Result<()> {
+    // aliases go before the identities they point at, and come back after
+    changes.sort_by(|a, b| match (a, b) {
+        (Change::Remove(a), Change::Remove(b)) => b.cmp(a),
+        (Change::Remove(_), Change::Put(..)) => std::cmp::Ordering::Less,
+        (Change::Put(..), Change::Remove(_)) => std::cmp::Ordering::Greater,
+        (Change::Put(a, _), Change::Put(b, _)) => a.cmp(b)
+    });
+    if changes.is_empty() {
+        return Ok(());
+    }
+    replica.apply_all(changes, seen)
+}
+
+/// Fails if `entry` no longer holds what it did when the sync was planned.
+fn check(entry: &Entry, current: Option<&String>, seen: &BTreeMap<Entry, String>) -> io::Result<()> {
+    if current != seen.get(entry) {
+        return Err(io::Error::other(
+            format!("{} changed during the sync, sync again", entry.key())));
+    }
+    Ok(())
+}
+
+impl<'a> Replica for DIDDir<'a> {
+
+    fn entries(&self) -> io::Result<BTreeMap<Entry, String>> {
+        let storage = self.storage();
+        let mut entries = BTreeMap::new();
+        for pkid in storage.identities()? {
+            let data = storage.read_identity(&pkid)?;
+            entries.insert(Entry::Identity(pkid), data);
+        }
+        for (alias, pkid) in storage.aliases()? {
+            entries.insert(Entry::Alias(alias), pkid);
+        }
+        Ok(entries)
+    }
+
+    fn tombstones(&self) -> io::Result<BTreeMap<Entry, String>> {
+        let storage = self.storage();
+        let mut tombstones = BTreeMap::new();
+        for pkid in storage.records(IDENTITY_TOMBSTONES)? {
+            let hash = storage.read_record(IDENTITY_TOMBSTONES, &pkid)?;
+            tombstones.insert(Entry::Identity(pkid), hash);
+        }
+        for alias in storage.records(ALIAS_TOMBSTONES)? {
+            let hash = storage.read_record(ALIAS_TOMBSTONES, &alias)?;
+            tombstones.insert(Entry::Alias(alias), hash);
+        }
+        Ok(tombstones)
+    }
+
+    /// One transaction, so the exclusive lock is held while the entries are
+    /// checked and changed.
+    fn apply_all(&mut self, changes: &[Change], seen: &BTreeMap<Entry, String>) -> io::Result<()> {
+        self.transaction(|tx| {
+            for change in changes {
+                check(change.entry(), staged(tx, change.entry())?.as_ref(), seen)?;
+            }
+            for change in changes {
+                match change {
+                    Change::Put(Entry::Identity(pkid), data) => tx.save_identity(pkid, data)?,
+                    Change::Put(Entry::Alias(alias), pkid) => tx.save_alias(&Alias::new(alias)?.to_absolute(), pkid)?,
+                    // removing an identity takes its aliases along, so they
+                    // may be gone already
+                    Change::Remove(entry @ Entry::Identity(pkid)) => if staged(tx, entry)?.is_some() {
+                        tx.remove_identity(pkid)?;
+                    },
+                    Change::Remove(entry @ Entry::Alias(alias)) => if staged(tx, entry)?.is_some() {
+                        tx.remove_alias(&Alias::new(alias)?.to_absolute())?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn apply(&mut self, change: &Change) -> io::Result<()> {
+        match change {
+            Change::Put(Entry::Identity(pkid), data) => self.save_identity(pkid, data),
+            // aliases are synced as stored, whatever our default namespace
+            Change::Put(Entry::Alias(alias), pkid) => self.save_alias(&Alias::new(alias)?.to_absolute(), pkid),
+            Change::Remove(Entry::Identity(pkid)) => {
+                if !self.storage().has_identity(pkid)? {
+                    return Ok(());
+                }
+                self.remove_identity(pkid)
+            },
+            Change::Remove(Entry::Alias(alias)) => {
+                let alias = Alias::new(alias)?.to_absolute();
+                // removing its identity may have taken it already
+                if self.get_pkid_from_alias(&alias).is_err() {
+                    return Ok(());
+                }
+                self.remove_alias(&alias)
+            }
+        }
+    }
+}
+
+impl Replica for Archive {
+
+    fn entries(&self) -> io::Result<BTreeMap<Entry, String>> {
+        let ids = self.identities.iter().map(|(p, d)| (Entry::Identity(p.to_owned()), d.to_owned()));
+        let aliases = self.aliases.iter().map(|(a, p)| (Entry::Alias(a.to_owned()), p.to_owned()));
+        Ok(ids.chain(aliases).collect())
+    }
+
+    fn tombstones(&self) -> io::Result<BTreeMap<Entry, String>> {
+        Ok(self.tombstones.iter()
+            .filter_map(|(key, hash)| Entry::from_key(key).map(|e| (e, hash.to_owned())))
+            .collect())
+    }
+
+    fn apply(&mut self, change: &Change) -> io::Result<()> {
+        let entry = change.entry();
+        let removed = match change {
+            Change::Put(Entry::Identity(pkid), data) => {
+                self.identities.insert(pkid.to_owned(), data.to_owned())
+            },
+            Change::Put(Entry::Alias(alias), pkid) => {
+                self.aliases.insert(alias.to_owned(), pkid.to_owned())
+            },
+            Change::Remove(Entry::Identity(pkid)) => self.identities.remove(pkid),
+            Change::Remove(Entry::Alias(alias)) => self.aliases.remove(alias)
+        };
+
+        match change {
+            Change::Put(_, data) => {
+                self.manifest.insert(entry.key(), sha256_hex(data.as_bytes()));
+                self.tombstones.remove(&entry.key());
+            },
+            Change::Remove(_) => {
+                self.manifest.remove(&entry.key());
+                if let Some(data) = removed {
+                    self.tombstones.insert(entry.key(), sha256_hex(data.as_bytes()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What `entry` holds as far as `tx` is concerned, `None` if it isn't there.
+fn staged(tx: &Transaction, entry: &Entry) -> io::Result<Option<String>> {
+    let result = match entry {
+        Entry::Identity(pkid) => tx.get_identity(pkid),
+        Entry::Alias(alias) => tx.get_pkid_from_alias(&Alias::new(alias)?.to_absolute())
+    };
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+/// Remembers that `key` was removed while it held content hashing to `hash`.
+pub(crate) fn bury(storage: &mut dyn Storage, kind: &str, key: &str, hash: &str) -> io::Result<()> {
+    match storage.write_record(kind, key, hash) {
+        // storage that can't keep records just doesn't sync removals
+        Err(ref e) if e.kind() == io::ErrorKind::Unsupported => Ok(()),
+        result => result
+    }
+}
+
+/// Forgets that `key` was ever removed, as it is back.
+pub(crate) fn unbury(storage: &mut dyn Storage, kind: &str, key: &str) -> io::Result<()> {
+    match storage.delete_record(kind, key) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result
+    }
+}
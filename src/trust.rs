@@ -0,0 +1,240 @@
+use crate::doc::{Document, PublicKeyData, PublicKeyType};
+use crate::hash::sha256_hex;
+use crate::metadata::{self, TrustLevel};
+use crate::storage::Storage;
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use serde_derive::{Serialize, Deserialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::io;
+
+pub use ed25519_dalek::SigningKey;
+
+/// Record kind holding every attestation, keyed by its id.
+pub(crate) static ATTESTATIONS: &str = "attestations";
+
+/// How many introducers deep our own identities can vouch.
+const OWN_DEPTH: u8 = u8::MAX;
+
+/// A signed statement by `issuer` that the document of `subject` with hash
+/// `subject_hash` belongs to alias `name`, or with `revoked` set, that it takes
+/// back what it said about `subject` before.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Attestation {
+    /// SHA-256 over the attestation with this field and `signature` left
+    /// empty. Also what is signed.
+    pub id: String,
+    /// Pkid of the identity making the statement.
+    pub issuer: String,
+    /// Id of the issuer's Ed25519 key that signed it.
+    pub key: String,
+    pub subject: String,
+    /// SHA-256 of the subject's document. Changing the document voids the
+    /// attestation.
+    pub subject_hash: String,
+    /// The alias, with its namespace, that the subject goes by. Empty for
+    /// revocations.
+    pub name: String,
+    /// How many levels of introducers the issuer trusts the subject to
+    /// vouch for in turn. 0 only vouches for the subject itself.
+    pub depth: u8,
+    /// Seconds since the Unix epoch. The newest statement from an issuer
+    /// about a subject is the one that counts, so each one is issued at
+    /// least a second after the one before.
+    pub issued: u64,
+    pub revoked: bool,
+    /// Base58 encoded Ed25519 signature over `id`.
+    pub signature: String
+}
+
+impl Attestation {
+    /// Fills in `id` and `signature`. Fails unless `signing_key` is the key
+    /// `key` of `issuer_doc`.
+    pub(crate) fn sign(&mut self, issuer_doc: &str, signing_key: &SigningKey) -> io::Result<()> {
+        if verifying_key(issuer_doc, &self.key)? != signing_key.verifying_key() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Signing key doesn't match key {} of {}", self.key, self.issuer)));
+        }
+
+        self.id = self.compute_id()?;
+        let signature = signing_key.sign(self.id.as_bytes());
+        self.signature = bs58::encode(signature.to_bytes()).into_string();
+        Ok(())
+    }
+
+    fn compute_id(&self) -> io::Result<String> {
+        let mut unsigned = self.clone();
+        unsigned.id = String::new();
+        unsigned.signature = String::new();
+        let json = serde_json::to_string(&unsigned).map_err(io::Error::other)?;
+        Ok(sha256_hex(json.as_bytes()))
+    }
+
+    /// Checks the attestation hasn't been changed and was signed by the key
+    /// it names in `issuer_doc`.
+    pub(crate) fn verify(&self, issuer_doc: &str) -> io::Result<()> {
+        if self.compute_id()? != self.id {
+            return Err(invalid(&self.id, "has been modified"));
+        }
+
+        let bytes = bs58::decode(&self.signature).into_vec().map_err(|_| invalid(&self.id, "has a corrupt signature"))?;
+        let signature = Signature::from_slice(&bytes).map_err(|_| invalid(&self.id, "has a corrupt signature"))?;
+        verifying_key(issuer_doc, &self.key)?
+            .verify(self.id.as_bytes(), &signature)
+            .map_err(|_| invalid(&self.id, "has a bad signature"))
+    }
+}
+
+/// How far an identity is trusted to belong to who it says.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Trust {
+    /// One of our own identities, with `TrustLevel::Ultimate` metadata.
+    Own,
+    /// Vouched for, directly or through introducers. `path` runs from one
+    /// of our own identities to the identity itself.
+    Attested { path: Vec<String> },
+    /// Every attestation that would have vouched for it was revoked.
+    Revoked,
+    /// Marked `TrustLevel::Never`, so no attestation counts for it.
+    Never,
+    Unknown
+}
+
+pub(crate) fn add(storage: &mut dyn Storage, attestation: &Attestation) -> io::Result<()> {
+    let json = serde_json::to_string(attestation).map_err(io::Error::other)?;
+    storage.write_record(ATTESTATIONS, &attestation.id, &json)
+}
+
+/// When the next statement from `issuer` about `subject` is issued.
+pub(crate) fn next_issued(storage: &dyn Storage, issuer: &str, subject: &str) -> io::Result<u64> {
+    let last = all(storage)?.into_iter()
+        .filter(|a| a.issuer == issuer && a.subject == subject)
+        .map(|a| a.issued + 1)
+        .max();
+    Ok(last.unwrap_or(0).max(metadata::now()))
+}
+
+/// Every attestation stored, oldest first.
+pub(crate) fn all(storage: &dyn Storage) -> io::Result<Vec<Attestation>> {
+    let mut attestations = Vec::new();
+    for id in storage.records(ATTESTATIONS)? {
+        let json = storage.read_record(ATTESTATIONS, &id)?;
+        let attestation: Attestation = serde_json::from_str(&json).map_err(|_| invalid(&id, "can't be read"))?;
+        attestations.push(attestation);
+    }
+    attestations.sort_by(|a, b| (a.issued, &a.id).cmp(&(b.issued, &b.id)));
+    Ok(attestations)
+}
+
+/// Walks out from our own identities along the attestations that still
+/// hold: signed by their issuer, about the subject's current document and
+/// not taken back since. Identities marked `TrustLevel::Never` are neither
+/// vouched for nor can they introduce anyone.
+pub(crate) fn trust_of(storage: &dyn Storage, pkid: &str) -> io::Result<Trust> {
+    match metadata::read(storage, pkid)?.trust {
+        TrustLevel::Ultimate => return Ok(Trust::Own),
+        TrustLevel::Never => return Ok(Trust::Never),
+        _ => {}
+    }
+
+    // the newest statement from each issuer about each subject
+    let mut latest: BTreeMap<(String, String), Attestation> = BTreeMap::new();
+    for attestation in all(storage)? {
+        latest.insert((attestation.issuer.clone(), attestation.subject.clone()), attestation);
+    }
+
+    let mut revokers = Vec::new();
+    let mut edges: BTreeMap<String, Vec<(String, u8)>> = BTreeMap::new();
+    for attestation in latest.into_values() {
+        if !holds(storage, &attestation)? {
+            continue;
+        }
+        if attestation.revoked {
+            if attestation.subject == pkid {
+                revokers.push(attestation.issuer);
+            }
+            continue;
+        }
+        edges.entry(attestation.issuer).or_default().push((attestation.subject, attestation.depth));
+    }
+
+    // breadth first, so the shortest path wins, revisiting an identity
+    // only when it can now introduce further than before
+    let mut reach: BTreeMap<String, (u8, Vec<String>)> = BTreeMap::new();
+    let mut queue = VecDeque::new();
+    for own in storage.identities()? {
+        if metadata::read(storage, &own)?.trust == TrustLevel::Ultimate {
+            reach.insert(own.clone(), (OWN_DEPTH, vec![own.clone()]));
+            queue.push_back(own);
+        }
+    }
+    while let Some(issuer) = queue.pop_front() {
+        let (depth, path) = reach[&issuer].clone();
+        if depth == 0 {
+            continue;
+        }
+        for (subject, vouched) in edges.get(&issuer).into_iter().flatten() {
+            let depth = (*vouched).min(depth - 1);
+            if reach.get(subject).map(|(d, _)| *d >= depth).unwrap_or(false) {
+                continue;
+            }
+            if metadata::read(storage, subject)?.trust == TrustLevel::Never {
+                continue;
+            }
+            let mut path = path.clone();
+            path.push(subject.clone());
+            reach.insert(subject.clone(), (depth, path));
+            queue.push_back(subject.clone());
+        }
+    }
+
+    // a revocation only counts from someone we trust, or anyone could
+    // mark anyone as revoked
+    let revoked = revokers.iter().any(|issuer| reach.contains_key(issuer));
+    Ok(match reach.remove(pkid) {
+        Some((_, path)) => Trust::Attested { path },
+        None if revoked => Trust::Revoked,
+        None => Trust::Unknown
+    })
+}
+
+/// Whether `attestation` is signed by its issuer, about its subject's
+/// current document and, unless it is a revocation, about the alias still
+/// pointing at the subject.
+fn holds(storage: &dyn Storage, attestation: &Attestation) -> io::Result<bool> {
+    if !attestation.revoked && storage.read_alias(&attestation.name).ok().as_ref() != Some(&attestation.subject) {
+        return Ok(false);
+    }
+    let (issuer_doc, subject_doc) = match (storage.read_identity(&attestation.issuer),
+                                           storage.read_identity(&attestation.subject)) {
+        (Ok(issuer_doc), Ok(subject_doc)) => (issuer_doc, subject_doc),
+        _ => return Ok(false)
+    };
+    if sha256_hex(subject_doc.as_bytes()) != attestation.subject_hash {
+        return Ok(false);
+    }
+    Ok(attestation.verify(&issuer_doc).is_ok())
+}
+
+/// The Ed25519 key `key` of a document.
+fn verifying_key(doc: &str, key: &str) -> io::Result<VerifyingKey> {
+    let doc: Document = serde_json::from_str(doc).map_err(|e|
+        io::Error::new(io::ErrorKind::InvalidData, format!("Issuer document can't be read: {}", e)))?;
+    let public_key = doc.public_key.iter()
+        .find(|k| k.id.as_str() == key && k.key_type == PublicKeyType::Ed25519VerificationKey2018)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound,
+            format!("No Ed25519 key {} in the issuer document", key)))?;
+
+    let bytes = match public_key.key_data {
+        PublicKeyData::Base58 { ref key } => bs58::decode(key).into_vec().ok(),
+        _ => None
+    };
+    bytes.and_then(|b| <[u8; 32]>::try_from(b.as_slice()).ok())
+        .and_then(|b| VerifyingKey::from_bytes(&b).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+            format!("Key {} isn't a base58 Ed25519 public key", key)))
+}
+
+fn invalid(id: &str, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Attestation {} {}", id, reason))
+}
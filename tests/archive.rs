@@ -0,0 +1,191 @@
+#[macro_use]
+extern crate cfg_if;
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Archive, Config, ConflictPolicy, DIDDir, ExportOptions, ImportReport};
+use diddir::archive::Conflict;
+use tempfile::tempdir;
+use std::io;
+
+cfg_if! {
+    if #[cfg(unix)] {
+        use diddir::dir::unix::DIDDirSys;
+    } else if #[cfg(target_os = "windows")] {
+        use diddir::dir::windows::DIDDirSys;
+    } else if #[cfg(target_arch = "wasm32")] {
+        use diddir::dir::wasm::DIDDirSys;
+    }
+}
+
+static CHAD: &str = r#"{
+  "name": "Chad Smith",
+  "secrets": {
+    "secret_key": "d065bee71747546ace230a33a6e5aa23"
+  }
+}"#;
+
+fn export(diddir: &DIDDir, options: &ExportOptions) -> Vec<u8> {
+    let mut buf = Vec::new();
+    diddir.export(&mut buf, options).unwrap();
+    buf
+}
+
+fn source() -> DIDDir<'static> {
+    let mut diddir = DIDDir::in_memory();
+    diddir.save_identity("chad", CHAD).unwrap();
+    diddir.save_identity("stacy", "{\"name\": \"Stacy Jones\"}").unwrap();
+    diddir.save_alias("default", "chad").unwrap();
+    diddir.save_alias("stacy.jones@no.email", "stacy").unwrap();
+    diddir
+}
+
+#[test]
+fn archive_roundtrip() {
+    let buf = export(&source(), &ExportOptions { include_secrets: true });
+
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+    let report = diddir.import(&buf[..], ConflictPolicy::Fail).unwrap();
+    assert_eq!(report.identities, vec!["chad".to_string(), "stacy".to_string()]);
+    assert_eq!(report.aliases, vec!["default".to_string(), "stacy.jones@no.email".to_string()]);
+    assert!(report.conflicts.is_empty());
+
+    assert_eq!(diddir.get_identity("chad").unwrap(), CHAD);
+    assert_eq!(diddir.get_pkid_from_alias("default").unwrap(), "chad");
+    for f in &["chad", "stacy"] {
+        DIDDirSys::check_permission(&config.root_dir().join(f)).unwrap();
+    }
+    DIDDirSys::check_permission(&config.aliases_dir().join("default")).unwrap();
+
+    // importing the same thing again changes nothing
+    let report = diddir.import(&buf[..], ConflictPolicy::Fail).unwrap();
+    assert!(report.identities.is_empty() && report.aliases.is_empty() && report.conflicts.is_empty());
+}
+
+#[test]
+fn archive_secrets() {
+    let source = source();
+
+    let archive = Archive::read(&export(&source, &ExportOptions::default())[..]).unwrap();
+    assert!(!archive.identities["chad"].contains("secret"));
+    assert!(archive.identities["chad"].contains("Chad Smith"));
+    assert_eq!(archive.identities["stacy"], "{\"name\": \"Stacy Jones\"}");
+
+    let archive = Archive::read(&export(&source, &ExportOptions { include_secrets: true })[..]).unwrap();
+    assert_eq!(archive.identities["chad"], CHAD);
+}
+
+#[test]
+fn archive_keeps_documents_without_secrets() {
+    // nothing to strip, so not even the formatting changes
+    let doc = "{\"name\":\"Stacy Jones\",  \"b\": [1,2]}";
+    let mut source = DIDDir::in_memory();
+    source.save_identity("stacy", doc).unwrap();
+
+    let mut diddir = DIDDir::in_memory();
+    diddir.import(&export(&source, &ExportOptions::default())[..], ConflictPolicy::Fail).unwrap();
+    assert_eq!(diddir.get_identity("stacy").unwrap(), doc);
+    assert_eq!(diddir.history("stacy").unwrap()[0].hash, source.history("stacy").unwrap()[0].hash);
+}
+
+#[test]
+fn archive_invalid() {
+    let archive = Archive::read(&export(&source(), &ExportOptions::default())[..]).unwrap();
+
+    let mut tampered = archive.clone();
+    tampered.identities.insert("stacy".to_string(), "{}".to_string());
+    assert_invalid(&tampered, "Archive entry doesn't match manifest: identities/stacy");
+
+    let mut tampered = archive.clone();
+    tampered.manifest.remove("aliases/default");
+    assert_invalid(&tampered, "Archive entry missing from manifest: aliases/default");
+
+    let mut tampered = archive.clone();
+    tampered.version = 2;
+    assert_invalid(&tampered, "Unsupported DIDDir archive version: 2");
+
+    let mut tampered = archive.clone();
+    tampered.aliases.insert("../escape".to_string(), "chad".to_string());
+    let mut buf = Vec::new();
+    tampered.write(&mut buf).unwrap();
+    assert_eq!(Archive::read(&buf[..]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    assert_eq!(Archive::read(&b"not json"[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn archive_conflicts() {
+    let buf = export(&source(), &ExportOptions::default());
+    let setup = || {
+        let mut diddir = DIDDir::in_memory();
+        diddir.save_identity("stacy", "{}").unwrap();
+        diddir.save_identity("other", "{}").unwrap();
+        diddir.save_alias("default", "other").unwrap();
+        diddir
+    };
+    let conflicts = vec![
+        Conflict::Identity("stacy".to_string()),
+        Conflict::Alias {
+            alias: "default".to_string(),
+            existing: "other".to_string(),
+            incoming: "chad".to_string()
+        }
+    ];
+
+    // fail leaves everything alone
+    let mut diddir = setup();
+    let err = diddir.import(&buf[..], ConflictPolicy::Fail).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(err.to_string(), "Archive conflicts with: stacy, alias default");
+    assert!(diddir.get_identity("chad").is_err());
+    let report = err.get_ref().and_then(|e| e.downcast_ref::<ImportReport>()).unwrap();
+    assert_eq!(report.conflicts, conflicts);
+    assert_eq!(report.identities, vec!["chad".to_string()]);
+
+    // skip keeps ours
+    let mut diddir = setup();
+    let report = diddir.import(&buf[..], ConflictPolicy::Skip).unwrap();
+    assert_eq!(report.conflicts, conflicts);
+    assert_eq!(report.identities, vec!["chad".to_string()]);
+    assert_eq!(diddir.get_identity("stacy").unwrap(), "{}");
+    assert_eq!(diddir.get_pkid_from_alias("default").unwrap(), "other");
+    assert_eq!(diddir.get_pkid_from_alias("stacy.jones@no.email").unwrap(), "stacy");
+
+    // overwrite takes theirs
+    let mut diddir = setup();
+    let report = diddir.import(&buf[..], ConflictPolicy::Overwrite).unwrap();
+    assert_eq!(report.conflicts, conflicts);
+    assert_eq!(diddir.get_identity("stacy").unwrap(), "{\"name\": \"Stacy Jones\"}");
+    assert_eq!(diddir.get_pkid_from_alias("default").unwrap(), "chad");
+}
+
+fn assert_invalid(archive: &Archive, msg: &str) {
+    let mut buf = Vec::new();
+    archive.write(&mut buf).unwrap();
+    let err = DIDDir::in_memory().import(&buf[..], ConflictPolicy::Overwrite).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), msg);
+}
+
+#[test]
+fn archive_import_all_or_nothing() {
+    let mut source = DIDDir::in_memory();
+    source.save_identity("bob", "{}").unwrap();
+    source.save_alias("work", "bob").unwrap();
+    let buf = export(&source, &ExportOptions::default());
+
+    // the alias can't be written next to the namespace of the same name
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.save_identity("alice", "{}").unwrap();
+    diddir.save_alias("work/alice", "alice").unwrap();
+
+    let err = diddir.import(&buf[..], ConflictPolicy::Skip).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert!(diddir.get_identity("bob").is_err());
+    assert_eq!(diddir.history("bob").unwrap().len(), 0);
+    assert_eq!(diddir.get_identities().unwrap(), vec!["alice".to_string()]);
+}
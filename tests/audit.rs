@@ -0,0 +1,155 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{AuditOp, AuditQuery, Config, DIDDir};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use tempfile::{tempdir, TempDir};
+
+fn audited() -> (TempDir, Config) {
+    audited_with("{}")
+}
+
+fn audited_with(doc: &str) -> (TempDir, Config) {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    config.set_audit_log(true);
+    {
+        let mut diddir = DIDDir::init(&config).unwrap();
+        diddir.save_identity("foo", doc).unwrap();
+        diddir.save_identity("foo", "{\"v\": 2}").unwrap();
+        diddir.save_alias("default", "foo").unwrap();
+        diddir.save_identity("bar", "{}").unwrap();
+        diddir.remove_identity("foo").unwrap();
+    }
+    (dir, config)
+}
+
+fn entry_path(config: &Config, seq: u64) -> PathBuf {
+    config.root_dir().join(".audit").join("entries").join(format!("{:020}", seq))
+}
+
+#[test]
+fn audit_entries() {
+    let (_dir, config) = audited();
+    let diddir = DIDDir::open(&config).unwrap();
+
+    let entries = diddir.audit_entries(&AuditQuery::default()).unwrap();
+    let ops: Vec<_> = entries.iter().map(|e| e.op).collect();
+    assert_eq!(ops, vec![
+        AuditOp::SaveIdentity,
+        AuditOp::SaveIdentity,
+        AuditOp::SaveAlias,
+        AuditOp::SaveIdentity,
+        // removing an identity takes its aliases with it
+        AuditOp::RemoveAlias,
+        AuditOp::RemoveIdentity
+    ]);
+
+    assert_eq!(entries[0].before, None);
+    assert_eq!(entries[1].before, entries[0].after);
+    assert_eq!(entries[5].before, entries[1].after);
+    assert_eq!(entries[5].after, None);
+    assert_eq!(entries[4].alias.as_deref(), Some("default"));
+    assert_eq!(entries[4].pkid.as_deref(), Some("foo"));
+    assert!(entries.iter().all(|e| e.pid == std::process::id() && e.timestamp > 0));
+    assert!(entries.windows(2).all(|w| w[1].prev == w[0].hash && w[1].seq == w[0].seq + 1));
+
+    let query = AuditQuery { pkid: Some("foo".to_string()), op: Some(AuditOp::SaveIdentity), ..Default::default() };
+    assert_eq!(diddir.audit_entries(&query).unwrap().len(), 2);
+    let query = AuditQuery { alias: Some("default".to_string()), ..Default::default() };
+    assert_eq!(diddir.audit_entries(&query).unwrap().len(), 2);
+    let query = AuditQuery { until: Some(entries[0].timestamp), ..Default::default() };
+    assert!(diddir.audit_entries(&query).unwrap().is_empty());
+
+    let head = diddir.verify_audit_log().unwrap().unwrap();
+    assert_eq!(head.seq, 6);
+    assert_eq!(head.hash, entries[5].hash);
+}
+
+#[test]
+fn audit_off() {
+    let mut diddir = DIDDir::in_memory();
+    diddir.save_identity("foo", "{}").unwrap();
+    assert!(diddir.audit_entries(&AuditQuery::default()).unwrap().is_empty());
+    assert_eq!(diddir.verify_audit_log().unwrap(), None);
+
+    diddir.set_audit_log(true);
+    diddir.save_alias("foo", "foo").unwrap();
+    assert_eq!(diddir.audit_entries(&AuditQuery::default()).unwrap().len(), 1);
+}
+
+#[test]
+fn audit_tampering() {
+    let assert_broken = |config: &Config, msg: &str| {
+        let err = DIDDir::open(config).unwrap().verify_audit_log().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), msg);
+    };
+
+    // edited
+    let (_dir, config) = audited();
+    let path = entry_path(&config, 2);
+    let original = fs::read_to_string(&path).unwrap();
+    let edited = original.replace("\"op\":\"save-identity\"", "\"op\":\"remove-identity\"");
+    assert_ne!(original, edited);
+    fs::write(&path, edited).unwrap();
+    assert_broken(&config, "Audit log entry 2 has been modified");
+
+    // cut out of the middle
+    let (_dir, config) = audited();
+    fs::remove_file(entry_path(&config, 3)).unwrap();
+    assert_broken(&config, "Audit log entry 3 is missing");
+
+    // truncated
+    let (_dir, config) = audited();
+    fs::remove_file(entry_path(&config, 6)).unwrap();
+    assert_broken(&config, "Audit log entry 6 is missing");
+
+    // replaced by an entry from another chain
+    let (_dir, config) = audited();
+    let (_other_dir, other) = audited_with("{\"other\": true}");
+    fs::copy(entry_path(&other, 6), entry_path(&config, 6)).unwrap();
+    assert_broken(&config, "Audit log entry 6 doesn't follow the entry before it");
+
+    // truncated along with the head is only caught against a saved head
+    let (_dir, config) = audited();
+    let saved = DIDDir::open(&config).unwrap().verify_audit_log().unwrap().unwrap();
+    let entries = DIDDir::open(&config).unwrap().audit_entries(&AuditQuery::default()).unwrap();
+    fs::remove_file(entry_path(&config, 6)).unwrap();
+    let head = format!("{{\"seq\":5,\"hash\":\"{}\"}}", entries[4].hash);
+    fs::write(config.root_dir().join(".audit").join("head"), head).unwrap();
+    let head = DIDDir::open(&config).unwrap().verify_audit_log().unwrap().unwrap();
+    assert_eq!(head.seq, 5);
+    assert_ne!(head, saved);
+}
+
+#[test]
+fn audit_continues_once_started() {
+    let (_dir, mut config) = audited();
+    config.set_audit_log(false);
+
+    // a handle that didn't ask for it still adds to an existing log
+    let mut diddir = DIDDir::open(&config).unwrap();
+    assert!(!diddir.audit_log());
+    diddir.save_alias("work/bar", "bar").unwrap();
+    diddir.update_metadata("bar", |m| m.notes = Some("met at work".to_string())).unwrap();
+    // unchanged metadata isn't a change
+    diddir.update_metadata("bar", |_| {}).unwrap();
+    diddir.remove_namespace("work").unwrap();
+
+    let entries = diddir.audit_entries(&AuditQuery::default()).unwrap();
+    let ops: Vec<_> = entries[6..].iter().map(|e| e.op).collect();
+    assert_eq!(ops, vec![
+        AuditOp::SaveAlias,
+        AuditOp::UpdateMetadata,
+        AuditOp::RemoveAlias,
+        AuditOp::RemoveNamespace
+    ]);
+    assert_eq!(entries[7].pkid.as_deref(), Some("bar"));
+    assert_ne!(entries[7].before, entries[7].after);
+    let query = AuditQuery { namespace: Some("work".to_string()), ..Default::default() };
+    assert_eq!(diddir.audit_entries(&query).unwrap(), vec![entries[9].clone()]);
+    assert_eq!(diddir.verify_audit_log().unwrap().unwrap().seq, 10);
+}
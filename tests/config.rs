@@ -2,29 +2,29 @@ extern crate diddir;
 
 mod common {
     use diddir::Config;
-    use std::path::PathBuf;
+    use std::path::Path;
 
-    static ALIASES: &'static str = "aliases";
-    static TMP: &'static str = "tmp";
+    static ALIASES: &str = "aliases";
+    static TMP: &str = "tmp";
 
-    pub fn config_default(root: &PathBuf) {
-	let mut aliases = root.clone();
+    pub fn config_default(root: &Path) {
+	let mut aliases = root.to_path_buf();
 	aliases.push(ALIASES);
-        let mut tmp = root.clone();
+        let mut tmp = root.to_path_buf();
         tmp.push(TMP);
         let config = Config::default();
-        assert_eq!(root.as_path(), config.root_dir());
+        assert_eq!(root, config.root_dir());
         assert_eq!(aliases.as_path(), config.aliases_dir());
         assert_eq!(tmp.as_path(), config.tmp_dir());
     }
 
-    pub fn config_with_path(root: &PathBuf) {
-	let mut aliases = root.clone();
+    pub fn config_with_path(root: &Path) {
+	let mut aliases = root.to_path_buf();
 	aliases.push(ALIASES);
-	let mut tmp = root.clone();
+	let mut tmp = root.to_path_buf();
         tmp.push(TMP);
-        let config = Config::with_path(root.as_path());
-        assert_eq!(root.as_path(), config.root_dir());
+        let config = Config::with_path(root);
+        assert_eq!(root, config.root_dir());
         assert_eq!(aliases.as_path(), config.aliases_dir());
         assert_eq!(tmp.as_path(), config.tmp_dir());
     }
@@ -36,8 +36,7 @@ mod common {
     target_os = "freebsd",
     target_os = "openbsd",
     target_os = "netbsd",
-    target_os = "dragonfly",
-    target_os = "bitrig"
+    target_os = "dragonfly"
 ))]
 mod unix_test {
     use crate::common;
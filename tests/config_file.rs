@@ -0,0 +1,181 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Backend, Config, DIDDir, Durability, Encryption, PkidDerivation, Retention};
+use diddir::sync::Resolver;
+use std::env;
+use std::fs;
+use std::io;
+use std::time::Duration;
+use tempfile::tempdir;
+
+#[test]
+fn config_file_toml() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("diddir.toml");
+    fs::write(&path, r#"
+        home = "keyrings"
+        profile = "work"
+        aliases-dir = "names"
+        lock-timeout = 0.5
+        pkid-derivation = "did"
+        history-retention = { keep-for = 60 }
+        audit-log = true
+        default-namespace = "team"
+        durability = "fsync"
+        resolver = "prefer-left"
+    "#).unwrap();
+
+    let config = Config::from_file(&path).unwrap();
+    let base = dir.path().join("keyrings");
+    assert_eq!(config.base_dir(), base);
+    assert_eq!(config.profile(), Some("work"));
+    assert_eq!(config.root_dir(), base.join(".profiles").join("work"));
+    assert_eq!(config.aliases_dir(), config.root_dir().join("names"));
+    assert_eq!(config.tmp_dir(), config.root_dir().join("tmp"));
+    assert_eq!(config.lock_timeout(), Duration::from_millis(500));
+    assert_eq!(config.pkid_derivation(), PkidDerivation::Did);
+    assert_eq!(config.history_retention(), Retention::KeepFor(Duration::from_secs(60)));
+    assert!(config.audit_log());
+    assert_eq!(config.default_namespace(), Some("team"));
+    assert_eq!(config.durability(), Durability::Fsync);
+    assert_eq!(config.encryption(), Encryption::None);
+    assert_eq!(config.backend(), Backend::Fs);
+    assert_eq!(config.resolver(), Resolver::PreferLeft);
+
+    // and the keyring is laid out that way
+    let mut config = config;
+    config.set_pkid_derivation(PkidDerivation::None);
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.save_identity("foo", "{}").unwrap();
+    diddir.save_alias("bar", "foo").unwrap();
+    assert!(config.aliases_dir().join("team").join("bar").is_file());
+    diddir.remove_alias("bar").unwrap();
+    assert_eq!(DIDDir::open(&config).unwrap().get_identity("foo").unwrap(), "{}");
+}
+
+#[test]
+fn config_file_serde() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path()).with_profile("test").unwrap();
+    config.set_tmp_subdir("scratch").unwrap();
+    config.set_history_retention(Retention::KeepLast(3));
+    config.set_default_namespace(Some("home"));
+    config.set_resolver(Resolver::PreferRight);
+
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+    let toml = toml::to_string(&config).unwrap();
+    assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+
+    let path = dir.path().join("config.json");
+    fs::write(&path, &json).unwrap();
+    assert_eq!(Config::from_file(&path).unwrap(), config);
+
+    // anything left out keeps its default
+    let path = dir.path().join("partial.json");
+    fs::write(&path, format!(r#"{{"home": {:?}}}"#, dir.path())).unwrap();
+    assert_eq!(Config::from_file(&path).unwrap(), Config::with_path(dir.path()));
+}
+
+#[test]
+fn config_file_serde_reads_nothing() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    DIDDir::init(&config.with_profile("work").unwrap()).unwrap();
+    config.set_default_profile(Some("work")).unwrap();
+
+    // deserializing takes the settings as they are, reading a file follows the pointer
+    let json = format!(r#"{{"home": {:?}}}"#, dir.path());
+    assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+    let path = dir.path().join("config.json");
+    fs::write(&path, &json).unwrap();
+    assert_eq!(Config::from_file(&path).unwrap().profile(), Some("work"));
+}
+
+#[test]
+fn config_file_errors() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    let read = |text: &str| {
+        fs::write(&path, text).unwrap();
+        Config::from_file(&path).unwrap_err().kind()
+    };
+
+    assert_eq!(read("hmoe = \"/tmp\""), io::ErrorKind::InvalidData);
+    assert_eq!(read("backend = \"floppy\""), io::ErrorKind::InvalidData);
+    assert_eq!(read("lock-timeout = -1"), io::ErrorKind::InvalidInput);
+    assert_eq!(read("profile = \"../x\""), io::ErrorKind::InvalidInput);
+    assert_eq!(read("default-namespace = \"a/b\""), io::ErrorKind::InvalidInput);
+    assert_eq!(read("aliases-dir = \"a/b\""), io::ErrorKind::InvalidInput);
+    assert_eq!(read("aliases-dir = \".lock\""), io::ErrorKind::InvalidInput);
+    assert_eq!(read("tmp-dir = \"aliases\""), io::ErrorKind::InvalidInput);
+    assert_eq!(Config::from_file(&dir.path().join("missing.toml")).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    let mut config = Config::with_path(dir.path());
+    config.set_encryption(Encryption::ChaCha20Poly1305);
+    assert_eq!(DIDDir::init(&config).unwrap_err().kind(), io::ErrorKind::Unsupported);
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn config_file_sqlite() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(&dir.path().join("keyring"));
+    config.set_backend(Backend::Sqlite);
+    assert_eq!(DIDDir::open(&config).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.save_identity("foo", "{}").unwrap();
+    assert!(config.sqlite_file().is_file());
+    assert!(!config.aliases_dir().exists());
+    assert_eq!(DIDDir::open(&config).unwrap().get_identity("foo").unwrap(), "{}");
+    assert_eq!(DIDDir::init(&config).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+}
+
+/// The only test touching the environment, so nothing else races it.
+#[test]
+fn config_file_env() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "home = \"from-file\"\nprofile = \"work\"\ndurability = \"fsync\"\nresolver = \"prefer-left\"\n").unwrap();
+    env::set_var("DIDDIR_CONFIG", &path);
+
+    // the file sets what the environment doesn't
+    let config = Config::load().unwrap();
+    assert_eq!(config.base_dir(), dir.path().join("from-file"));
+    assert_eq!(config.profile(), Some("work"));
+    assert_eq!(config.durability(), Durability::Fsync);
+    assert_eq!(config.resolver(), Resolver::PreferLeft);
+
+    env::set_var("DIDDIR_HOME", dir.path().join("from-env"));
+    env::set_var("DIDDIR_PROFILE", "ci");
+    env::set_var("DIDDIR_DURABILITY", "atomic");
+    env::set_var("DIDDIR_DEFAULT_NAMESPACE", "");
+    env::set_var("DIDDIR_RESOLVER", "prefer-right");
+    let config = Config::load().unwrap();
+    assert_eq!(config.base_dir(), dir.path().join("from-env"));
+    assert_eq!(config.root_dir(), dir.path().join("from-env").join(".profiles").join("ci"));
+    assert_eq!(config.durability(), Durability::Atomic);
+    assert_eq!(config.default_namespace(), None);
+    assert_eq!(config.resolver(), Resolver::PreferRight);
+
+    // code can still override the environment, and the other way around
+    let mut config = Config::with_path(dir.path());
+    config.set_lock_timeout(Duration::from_secs(1));
+    config.apply_env().unwrap();
+    assert_eq!(config.base_dir(), dir.path().join("from-env"));
+    assert_eq!(config.lock_timeout(), Duration::from_secs(1));
+    assert_eq!(config.resolver(), Resolver::PreferRight);
+
+    env::set_var("DIDDIR_RESOLVER", "coin-toss");
+    assert_eq!(Config::load().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    env::remove_var("DIDDIR_RESOLVER");
+    env::set_var("DIDDIR_BACKEND", "floppy");
+    assert_eq!(Config::load().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    for var in ["DIDDIR_CONFIG", "DIDDIR_HOME", "DIDDIR_PROFILE", "DIDDIR_DURABILITY",
+                "DIDDIR_DEFAULT_NAMESPACE", "DIDDIR_BACKEND"].iter() {
+        env::remove_var(var);
+    }
+}
@@ -5,7 +5,6 @@ extern crate tempfile;
 
 use diddir::{Config, DIDDir, Document, PublicKeyType};
 use tempfile::{tempdir, TempDir};
-use serde_json;
 use std::io;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -16,7 +15,7 @@ cfg_if! {
         use diddir::dir::unix::DIDDirSys;
     } else if #[cfg(target_os = "windows")] {
         use diddir::dir::windows::DIDDirSys;
-    } else if #[cfg(wasm)] {
+    } else if #[cfg(target_arch = "wasm32")] {
         use diddir::dir::wasm::DIDDirSys;
     }
 }
@@ -30,7 +29,7 @@ fn diddir_init() {
     let dirs = vec![config.root_dir(), config.aliases_dir(), config.tmp_dir()];
     for d in dirs {
         assert!(d.is_dir());
-        assert_eq!((), DIDDirSys::check_permission(&d).unwrap());
+        assert_eq!((), DIDDirSys::check_permission(d).unwrap());
     }
 }
 
@@ -43,7 +42,7 @@ fn diddir_open_or_init_init() {
     let dirs = vec![config.root_dir(), config.aliases_dir(), config.tmp_dir()];
     for d in dirs {
         assert!(d.is_dir());
-        assert_eq!((), DIDDirSys::check_permission(&d).unwrap());
+        assert_eq!((), DIDDirSys::check_permission(d).unwrap());
     }
 }
 
@@ -55,7 +54,7 @@ fn diddir_open_or_init_open() {
     let dirs = vec![config.root_dir(), config.aliases_dir(), config.tmp_dir()];
     for d in dirs {
         assert!(d.is_dir());
-        assert_eq!((), DIDDirSys::check_permission(&d).unwrap());
+        assert_eq!((), DIDDirSys::check_permission(d).unwrap());
     }
 }
 
@@ -65,16 +64,16 @@ fn diddir_open() {
     let diddir = DIDDir::open(&config).unwrap();
 
     // get default identity from "default" alias
-    let default_id = diddir.get_pkid_from_alias(&"default".to_string()).unwrap();
+    let default_id = diddir.get_pkid_from_alias("default").unwrap();
     assert_eq!(default_id, "c506310b2c1ceb27212c4478055a44ac6b26969af73da0828bf28fc4867f09bb".to_string());
 
     // get all aliases from default pkid
     let aliases = diddir.get_aliases(&default_id).unwrap();
     assert_eq!(aliases.len(), 2);
-    assert!((aliases[0] == "default".to_string()) || (aliases[0] == "chad.smith@no.email".to_string()));
-    assert!((aliases[1] == "default".to_string()) || (aliases[1] == "chad.smith@no.email".to_string()));
+    assert!((aliases[0] == "default") || (aliases[0] == "chad.smith@no.email"));
+    assert!((aliases[1] == "default") || (aliases[1] == "chad.smith@no.email"));
 
-    let stacy_id = diddir.get_pkid_from_alias(&"stacy.jones@no.email".to_string()).unwrap();
+    let stacy_id = diddir.get_pkid_from_alias("stacy.jones@no.email").unwrap();
     assert_eq!(stacy_id, "8b69351b707a187559ef7e87d898430dc016680c52b36e23d8703a2e030b30dd".to_string());
 }
 
@@ -255,6 +254,61 @@ fn diddir_remove_alias() {
     assert!(!path.exists());
 }
 
+#[test]
+fn diddir_refresh() {
+    let (_tmpdir, config) = create_test_diddir().unwrap();
+    let mut reader = DIDDir::open(&config).unwrap();
+    let mut writer = DIDDir::open(&config).unwrap();
+
+    // read chad so he's cached
+    let chad_pkid = "c506310b2c1ceb27212c4478055a44ac6b26969af73da0828bf28fc4867f09bb";
+    let chad_did = reader.get_identity(chad_pkid).unwrap();
+
+    // make changes the reader doesn't know about
+    writer.save_identity("foo", "{}").unwrap();
+    writer.save_alias("foo", "foo").unwrap();
+    writer.save_identity(chad_pkid, "{\"name\": \"Chad\"}").unwrap();
+    assert!(reader != writer);
+    assert_eq!(reader.get_identities().unwrap().len(), 2);
+    assert!(reader.get_pkid_from_alias("foo").is_err());
+    assert_eq!(reader.get_identity(chad_pkid).unwrap(), chad_did);
+
+    // until it refreshes
+    reader.refresh().unwrap();
+    assert!(reader == writer);
+    assert_eq!(reader.get_identities().unwrap().len(), 3);
+    assert_eq!(reader.get_pkid_from_alias("foo").unwrap(), "foo");
+    assert_eq!(reader.get_identity(chad_pkid).unwrap(), "{\"name\": \"Chad\"}");
+
+    // removals too
+    writer.remove_identity("foo").unwrap();
+    reader.refresh().unwrap();
+    assert_eq!(reader.get_identities().unwrap().len(), 2);
+    assert!(reader.get_pkid_from_alias("foo").is_err());
+}
+
+#[test]
+fn diddir_refresh_same_size() {
+    let (_tmpdir, config) = create_test_diddir().unwrap();
+    let mut reader = DIDDir::open(&config).unwrap();
+    let chad_pkid = "c506310b2c1ceb27212c4478055a44ac6b26969af73da0828bf28fc4867f09bb";
+    let path = config.root_dir().join(chad_pkid);
+    let chad_did = reader.get_identity(chad_pkid).unwrap();
+
+    // replace the file with one of the same size and mtime
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    let replaced = chad_did.replacen("Chad", "Chaz", 1);
+    assert_ne!(replaced, chad_did);
+    let tmp = config.tmp_dir().join(chad_pkid);
+    fs::write(&tmp, &replaced).unwrap();
+    fs::File::options().write(true).open(&tmp).unwrap().set_modified(modified).unwrap();
+    DIDDirSys::set_permission(&tmp).unwrap();
+    fs::rename(&tmp, &path).unwrap();
+
+    reader.refresh().unwrap();
+    assert_eq!(reader.get_identity(chad_pkid).unwrap(), replaced);
+}
+
 fn create_test_diddir() -> io::Result<(TempDir, Config)> {
     // get a temporary root dir
     let dir = tempdir().unwrap();
@@ -266,9 +320,9 @@ fn create_test_diddir() -> io::Result<(TempDir, Config)> {
     let dirs = vec![config.root_dir(), config.aliases_dir(), config.tmp_dir()];
     for d in dirs {
         if !d.exists() {
-            fs::create_dir_all(&d)?;
+            fs::create_dir_all(d)?;
         }
-        DIDDirSys::set_permission(&d)?;
+        DIDDirSys::set_permission(d)?;
     }
 
     // create the id files
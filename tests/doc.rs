@@ -1,7 +1,6 @@
 extern crate diddir;
 
 use diddir::{Document, PublicKeyType};
-use serde_json;
 
 #[test]
 fn diddir_parse_document() {
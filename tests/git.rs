@@ -0,0 +1,203 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir};
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn have_git() -> bool {
+    Command::new("git").arg("--version").output().is_ok()
+}
+
+#[test]
+fn git_commits() {
+    if !have_git() {
+        return;
+    }
+
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(&dir.path().join("diddir"));
+    let root = config.root_dir();
+    {
+        let mut diddir = DIDDir::init(&config).unwrap();
+        diddir.save_identity("foo", "{}").unwrap();
+        assert!(!diddir.is_git());
+        diddir.init_git().unwrap();
+        assert!(diddir.is_git());
+
+        diddir.save_alias("default", "foo").unwrap();
+        diddir.save_identity("foo", "{\"v\": 2}").unwrap();
+        // unchanged, so no commit
+        diddir.save_identity("foo", "{\"v\": 2}").unwrap();
+    }
+
+    // reopening picks up git mode, and git's own files pass the checks
+    let mut diddir = DIDDir::open(&config).unwrap();
+    assert!(diddir.is_git());
+    diddir.remove_identity("foo").unwrap();
+
+    let subjects = git(root, &["log", "--format=%s"]);
+    assert_eq!(subjects.lines().collect::<Vec<_>>(), vec![
+        "Remove identity foo",
+        "Save identity foo",
+        "Save alias default",
+        "Start keeping DIDDir in git"
+    ]);
+
+    let message = git(root, &["log", "--format=%B", "-n", "1", "HEAD~2"]);
+    assert_eq!(message.trim(), "Save alias default\n\nDiddir-Op: save-alias\nDiddir-Alias: default\nDiddir-Pkid: foo");
+    let trailers = git(root, &["log", "--format=%(trailers:key=Diddir-Op,valueonly)", "-n", "1"]);
+    assert_eq!(trailers.trim(), "remove-identity");
+
+    // the working tree is all committed, scratch files are ignored
+    assert_eq!(git(root, &["status", "--porcelain"]), "");
+    assert!(git(root, &["ls-files"]).lines().all(|f| !f.starts_with("tmp/") && f != ".lock"));
+}
+
+#[test]
+fn git_push_pull() {
+    if !have_git() {
+        return;
+    }
+
+    let dir = tempdir().unwrap();
+    let remote = dir.path().join("remote.git");
+    let remote = remote.to_str().unwrap();
+    git(dir.path(), &["init", "--quiet", "--bare", remote]);
+
+    let laptop = Config::with_path(&dir.path().join("laptop"));
+    let server = Config::with_path(&dir.path().join("server"));
+
+    let mut a = DIDDir::init(&laptop).unwrap();
+    a.init_git().unwrap();
+    a.save_identity("foo", "{}").unwrap();
+    a.save_alias("default", "foo").unwrap();
+    a.git_push(remote, "main").unwrap();
+
+    let mut b = DIDDir::init(&server).unwrap();
+    b.init_git().unwrap();
+    b.git_pull(remote, "main").unwrap();
+    assert_eq!(b.get_identity("foo").unwrap(), "{}");
+    assert_eq!(b.get_pkid_from_alias("default").unwrap(), "foo");
+
+    // whatever git checked out is locked down again
+    drop(b);
+    let mut b = DIDDir::open(&server).unwrap();
+
+    // and back the other way
+    b.save_identity("bar", "{}").unwrap();
+    b.git_push(remote, "main").unwrap();
+    a.git_pull(remote, "main").unwrap();
+    assert_eq!(a.get_identity("bar").unwrap(), "{}");
+
+    assert!(DIDDir::in_memory().init_git().is_err());
+    assert!(DIDDir::in_memory().git_push(remote, "main").is_err());
+}
+
+#[test]
+fn git_pull_goes_through_the_keyring() {
+    if !have_git() {
+        return;
+    }
+
+    let dir = tempdir().unwrap();
+    let remote = dir.path().join("remote.git");
+    let remote = remote.to_str().unwrap();
+    git(dir.path(), &["init", "--quiet", "--bare", remote]);
+
+    let laptop = Config::with_path(&dir.path().join("laptop"));
+    let server = Config::with_path(&dir.path().join("server"));
+
+    let mut a = DIDDir::init(&laptop).unwrap();
+    a.init_git().unwrap();
+    a.save_identity("foo", "{}").unwrap();
+    a.git_push(remote, "main").unwrap();
+
+    let mut b = DIDDir::init(&server).unwrap();
+    b.init_git().unwrap();
+    b.git_pull(remote, "main").unwrap();
+    // recorded as written here, and the merge is in the history
+    assert_eq!(b.history("foo").unwrap().len(), 1);
+    let subjects = git(server.root_dir(), &["log", "--format=%s", "-n", "2"]);
+    assert_eq!(subjects.lines().collect::<Vec<_>>(), vec![
+        &format!("Pull main from {}", remote),
+        "Apply 1 changes"
+    ]);
+    assert_eq!(git(server.root_dir(), &["status", "--porcelain"]), "");
+
+    // pulling again changes nothing
+    b.git_pull(remote, "main").unwrap();
+
+    // a removal comes across as one
+    a.remove_identity("foo").unwrap();
+    a.git_push(remote, "main").unwrap();
+    b.git_pull(remote, "main").unwrap();
+    assert!(b.get_identity("foo").is_err());
+    assert!(b.history("foo").unwrap().last().unwrap().removed);
+    b.git_push(remote, "main").unwrap();
+    a.git_pull(remote, "main").unwrap();
+
+    // both sides changing the same identity fails the pull untouched
+    a.save_identity("bar", "{\"v\": 1}").unwrap();
+    a.git_push(remote, "main").unwrap();
+    b.git_pull(remote, "main").unwrap();
+    a.save_identity("bar", "{\"v\": 2}").unwrap();
+    a.git_push(remote, "main").unwrap();
+    b.save_identity("bar", "{\"v\": 3}").unwrap();
+    assert!(b.git_pull(remote, "main").is_err());
+    assert_eq!(b.get_identity("bar").unwrap(), "{\"v\": 3}");
+    assert_eq!(git(server.root_dir(), &["status", "--porcelain"]), "");
+}
+
+#[test]
+fn git_pull_rejects_what_the_keyring_would() {
+    if !have_git() {
+        return;
+    }
+
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(&dir.path().join("diddir"));
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.init_git().unwrap();
+
+    // a tree that was never a keyring, with a file that isn't a valid pkid
+    let other = dir.path().join("other");
+    git(dir.path(), &["init", "--quiet", other.to_str().unwrap()]);
+    std::fs::create_dir(other.join("aliases")).unwrap();
+    std::fs::write(other.join("aliases").join("default"), "../../etc/passwd").unwrap();
+    git(&other, &["add", "--all"]);
+    git(&other, &["-c", "user.name=x", "-c", "user.email=x@x", "commit", "--quiet", "-m", "x"]);
+
+    assert!(diddir.git_pull(other.to_str().unwrap(), "HEAD").is_err());
+    assert!(diddir.get_pkid_from_alias("default").is_err());
+    assert_eq!(git(config.root_dir(), &["status", "--porcelain"]), "");
+}
+
+#[test]
+fn git_ignores_added_lines() {
+    if !have_git() {
+        return;
+    }
+
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(&dir.path().join("diddir"));
+    let root = config.root_dir();
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.init_git().unwrap();
+
+    // as an older version left it
+    std::fs::write(root.join(".gitignore"), "/tmp/\n/.lock\n").unwrap();
+    diddir.save_identity("foo", "{}").unwrap();
+
+    let gitignore = std::fs::read_to_string(root.join(".gitignore")).unwrap();
+    assert!(gitignore.starts_with("/tmp/\n/.lock\n"));
+    assert!(gitignore.lines().any(|l| l == "/.index/"));
+    assert!(git(root, &["ls-files"]).lines().all(|f| !f.starts_with(".index/")));
+}
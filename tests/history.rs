@@ -0,0 +1,107 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir, Retention};
+use std::io;
+use std::time::Duration;
+use tempfile::tempdir;
+
+fn doc(v: u32) -> String {
+    format!("{{\"v\": {}}}", v)
+}
+
+#[test]
+fn history_versions() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+
+    for v in 1..4 {
+        diddir.save_identity("foo", &doc(v)).unwrap();
+    }
+    // saving the same document again isn't a new version
+    diddir.save_identity("foo", &doc(3)).unwrap();
+
+    let history = diddir.history("foo").unwrap();
+    assert_eq!(history.iter().map(|v| v.version).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_ne!(history[0].hash, history[1].hash);
+    assert!(history[0].saved > 0);
+    for v in 1..4 {
+        assert_eq!(diddir.get_identity_version("foo", v as u64).unwrap(), doc(v));
+    }
+    assert_eq!(diddir.get_identity("foo").unwrap(), doc(3));
+    assert_eq!(diddir.get_identity_version("foo", 4).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    // history is kept out of the way and survives reopening
+    drop(diddir);
+    let diddir = DIDDir::open(&config).unwrap();
+    assert_eq!(diddir.get_identities().unwrap(), vec!["foo".to_string()]);
+    assert_eq!(diddir.history("foo").unwrap().len(), 3);
+    assert_eq!(diddir.get_identity_version("foo", 1).unwrap(), doc(1));
+}
+
+#[test]
+fn history_existing_identity() {
+    let mut diddir = DIDDir::in_memory();
+    diddir.set_history_retention(Retention::Off);
+    diddir.save_identity("foo", &doc(1)).unwrap();
+    assert!(diddir.history("foo").unwrap().is_empty());
+
+    // the document saved before history was kept becomes version 1
+    diddir.set_history_retention(Retention::KeepAll);
+    diddir.save_identity("foo", &doc(2)).unwrap();
+    assert_eq!(diddir.history("foo").unwrap().len(), 2);
+    assert_eq!(diddir.get_identity_version("foo", 1).unwrap(), doc(1));
+}
+
+#[test]
+fn history_retention() {
+    let mut diddir = DIDDir::in_memory();
+    diddir.set_history_retention(Retention::KeepLast(2));
+    for v in 1..6 {
+        diddir.save_identity("foo", &doc(v)).unwrap();
+    }
+
+    // version numbers aren't reused after pruning
+    let history = diddir.history("foo").unwrap();
+    assert_eq!(history.iter().map(|v| v.version).collect::<Vec<_>>(), vec![4, 5]);
+    assert!(diddir.get_identity_version("foo", 3).is_err());
+    assert_eq!(diddir.get_identity_version("foo", 4).unwrap(), doc(4));
+
+    // the current version is always kept
+    diddir.set_history_retention(Retention::KeepFor(Duration::from_secs(0)));
+    diddir.save_identity("foo", &doc(6)).unwrap();
+    let history = diddir.history("foo").unwrap();
+    assert!(history.last().unwrap().version == 6);
+    assert_eq!(diddir.get_identity_version("foo", 6).unwrap(), doc(6));
+
+    diddir.set_history_retention(Retention::KeepFor(Duration::from_secs(3600)));
+    diddir.save_identity("foo", &doc(7)).unwrap();
+    assert!(diddir.history("foo").unwrap().len() >= 2);
+}
+
+#[test]
+fn history_remove_identity() {
+    let mut diddir = DIDDir::in_memory();
+    diddir.save_identity("foo", &doc(1)).unwrap();
+    diddir.save_identity("foo", &doc(2)).unwrap();
+    diddir.save_identity("bar", &doc(1)).unwrap();
+    diddir.remove_identity("foo").unwrap();
+
+    let history = diddir.history("foo").unwrap();
+    assert_eq!(history.len(), 3);
+    assert!(!history[1].removed);
+    assert!(history[2].removed);
+    assert_eq!(diddir.get_identity_version("foo", 1).unwrap(), doc(1));
+    assert_eq!(diddir.get_identity_version("foo", 2).unwrap(), doc(2));
+    assert_eq!(diddir.get_identity_version("foo", 3).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+    // saving it again carries on from the removal
+    diddir.save_identity("foo", &doc(1)).unwrap();
+    let history = diddir.history("foo").unwrap();
+    assert_eq!(history.last().unwrap().version, 4);
+    assert!(!history.last().unwrap().removed);
+
+    // the same document under another pkid keeps its own history
+    assert_eq!(diddir.get_identity_version("bar", 1).unwrap(), doc(1));
+}
@@ -0,0 +1,220 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir};
+use diddir::dir::lock::{Lock, LockMode};
+use tempfile::tempdir;
+use std::env;
+use std::fs;
+use std::io;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static HAMMER_ROOT: &str = "DIDDIR_HAMMER_ROOT";
+static HAMMER_WORKER: &str = "DIDDIR_HAMMER_WORKER";
+static HOLDER_LOCK: &str = "DIDDIR_HOLDER_LOCK";
+static WORKERS: usize = 4;
+static ROUNDS: usize = 25;
+
+#[test]
+fn lock_exclusive_times_out() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join(".lock");
+
+    let _held = Lock::acquire(&path, LockMode::Exclusive, Duration::from_secs(1)).unwrap();
+    let start = Instant::now();
+    let err = Lock::acquire(&path, LockMode::Shared, Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    // the error names the holder
+    assert!(err.to_string().contains(&std::process::id().to_string()));
+}
+
+#[test]
+fn lock_shared_is_shared() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join(".lock");
+
+    let _a = Lock::acquire(&path, LockMode::Shared, Duration::from_secs(1)).unwrap();
+    let _b = Lock::acquire(&path, LockMode::Shared, Duration::from_millis(50)).unwrap();
+    let err = Lock::acquire(&path, LockMode::Exclusive, Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn lock_released_on_drop() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join(".lock");
+
+    {
+        let _held = Lock::acquire(&path, LockMode::Exclusive, Duration::from_secs(1)).unwrap();
+        assert!(Lock::holder(&path).unwrap().is_some());
+    }
+
+    assert_eq!(Lock::holder(&path).unwrap(), None);
+    let _again = Lock::acquire(&path, LockMode::Exclusive, Duration::from_millis(50)).unwrap();
+}
+
+#[test]
+fn lock_dead_holder() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join(".lock");
+
+    // a writer that gets killed while holding the lock
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["lock_holder", "--exact"])
+        .env(HOLDER_LOCK, &path)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    while Lock::holder(&path).unwrap().map(|info| info.pid) != Some(child.id()) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // the lock went with it, the lock file stays
+    let _lock = Lock::acquire(&path, LockMode::Exclusive, Duration::from_millis(50)).unwrap();
+    assert_eq!(Lock::holder(&path).unwrap().unwrap().pid, std::process::id());
+    assert!(path.exists());
+}
+
+#[test]
+fn lock_stale_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join(".lock");
+
+    // a writer that died without getting to clear its record
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["lock_holder", "--exact"])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let dead = child.id();
+    child.wait().unwrap();
+    fs::write(&path, format!("{} 1\n", dead)).unwrap();
+    assert_eq!(Lock::holder(&path).unwrap(), None);
+
+    // so it isn't blamed for a lock somebody else holds
+    let _shared = Lock::acquire(&path, LockMode::Shared, Duration::from_secs(1)).unwrap();
+    let err = Lock::acquire(&path, LockMode::Exclusive, Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(!err.to_string().contains(&dead.to_string()));
+}
+
+#[test]
+fn lock_holder() {
+    // only does anything when spawned by lock_dead_holder
+    let path = match env::var(HOLDER_LOCK) {
+        Ok(path) => path,
+        Err(_) => return
+    };
+    let _lock = Lock::acquire(path.as_ref(), LockMode::Exclusive, Duration::from_secs(1)).unwrap();
+    thread::sleep(Duration::from_secs(60));
+}
+
+#[test]
+fn diddir_write_waits_for_lock() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    config.set_lock_timeout(Duration::from_millis(50));
+    let mut diddir = DIDDir::init(&config).unwrap();
+
+    let _held = Lock::acquire(config.lock_file(), LockMode::Shared, Duration::from_secs(1)).unwrap();
+    let err = diddir.save_identity("foo", "{}").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn diddir_open_shares_with_readers() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    config.set_lock_timeout(Duration::from_millis(50));
+    DIDDir::init(&config).unwrap().save_identity("foo", "{}").unwrap();
+
+    // with no transaction to roll back, opening only needs a shared lock
+    let _held = Lock::acquire(config.lock_file(), LockMode::Shared, Duration::from_secs(1)).unwrap();
+    let diddir = DIDDir::open(&config).unwrap();
+    assert_eq!(diddir.get_identity("foo").unwrap(), "{}");
+}
+
+#[test]
+fn diddir_lock_file_is_not_an_identity() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+
+    diddir.save_identity("foo", "{}").unwrap();
+    assert!(config.lock_file().exists());
+    assert_eq!(diddir.get_identities().unwrap(), vec!["foo".to_string()]);
+}
+
+#[test]
+fn diddir_hammer() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    DIDDir::init(&config).unwrap();
+
+    // run the worker test below in several processes at once
+    let exe = env::current_exe().unwrap();
+    let children: Vec<_> = (0..WORKERS).map(|worker| {
+        Command::new(&exe)
+            .args(["hammer_worker", "--exact"])
+            .env(HAMMER_ROOT, dir.path())
+            .env(HAMMER_WORKER, worker.to_string())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap()
+    }).collect();
+
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    // every worker keeps all but every fifth identity
+    let diddir = DIDDir::open(&config).unwrap();
+    let ids = diddir.get_identities().unwrap();
+    assert_eq!(ids.len(), WORKERS * (ROUNDS - ROUNDS / 5));
+
+    for worker in 0..WORKERS {
+        for round in 0..ROUNDS {
+            let pkid = format!("{}-{}", worker, round);
+            let alias = format!("alias-{}-{}", worker, round);
+            if round % 5 == 4 {
+                assert!(diddir.get_identity(&pkid).is_err());
+                assert!(diddir.get_pkid_from_alias(&alias).is_err());
+            } else {
+                assert_eq!(diddir.get_identity(&pkid).unwrap(), format!("{{\"round\": {}}}", round));
+                assert_eq!(diddir.get_pkid_from_alias(&alias).unwrap(), pkid);
+            }
+        }
+    }
+
+    // nothing left behind in tmp
+    assert_eq!(fs::read_dir(config.tmp_dir()).unwrap().count(), 0);
+}
+
+#[test]
+fn hammer_worker() {
+    // only does anything when spawned by diddir_hammer
+    let root = match env::var(HAMMER_ROOT) {
+        Ok(root) => root,
+        Err(_) => return
+    };
+    let worker = env::var(HAMMER_WORKER).unwrap();
+
+    let config = Config::with_path(root.as_ref());
+    let mut diddir = DIDDir::open(&config).unwrap();
+
+    for round in 0..ROUNDS {
+        let pkid = format!("{}-{}", worker, round);
+        let alias = format!("alias-{}-{}", worker, round);
+        diddir.save_identity(&pkid, &format!("{{\"round\": {}}}", round)).unwrap();
+        diddir.save_alias(&alias, &pkid).unwrap();
+        if round % 5 == 4 {
+            diddir.remove_identity(&pkid).unwrap();
+        }
+    }
+}
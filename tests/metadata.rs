@@ -0,0 +1,91 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir, Metadata, Query, TrustLevel};
+use std::io;
+use tempfile::tempdir;
+
+#[test]
+fn metadata_timestamps() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+
+    diddir.save_identity("foo", "{}").unwrap();
+    let saved = diddir.metadata("foo").unwrap();
+    assert!(saved.created > 0);
+    assert_eq!(saved.updated, saved.created);
+    assert_eq!(saved.trust, TrustLevel::Unknown);
+    assert!(config.root_dir().join(".metadata").join("foo").exists());
+
+    diddir.update_metadata("foo", |m| {
+        m.source = Some("https://example.com/foo".to_string());
+        m.trust = TrustLevel::Full;
+        m.tags.insert("work".to_string());
+        m.notes = Some("met at the conference".to_string());
+        m.last_verified = Some(m.created);
+    }).unwrap();
+
+    // saving the document again keeps what we know about it
+    diddir.save_identity("foo", "{\"v\": 2}").unwrap();
+    let metadata = DIDDir::open(&config).unwrap().metadata("foo").unwrap();
+    assert_eq!(metadata.created, saved.created);
+    assert!(metadata.updated >= saved.updated);
+    assert_eq!(metadata.trust, TrustLevel::Full);
+    assert_eq!(metadata.source.as_deref(), Some("https://example.com/foo"));
+
+    // saving the same document again isn't an update
+    diddir.update_metadata("foo", |m| m.updated = 1).unwrap();
+    diddir.save_identity("foo", "{\"v\": 2}").unwrap();
+    assert_eq!(diddir.metadata("foo").unwrap().updated, 1);
+
+    // and removing it forgets it
+    diddir.remove_identity("foo").unwrap();
+    assert_eq!(diddir.metadata("foo").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(diddir.update_metadata("foo", |_| {}).unwrap_err().kind(), io::ErrorKind::NotFound);
+    diddir.save_identity("foo", "{}").unwrap();
+    assert_eq!(diddir.metadata("foo").unwrap().trust, TrustLevel::Unknown);
+}
+
+#[test]
+fn metadata_query() {
+    let mut diddir = DIDDir::in_memory();
+    for pkid in ["alice", "bob", "carol"].iter() {
+        diddir.save_identity(pkid, "{}").unwrap();
+    }
+    diddir.update_metadata("alice", |m| {
+        m.trust = TrustLevel::Ultimate;
+        m.tags.insert("me".to_string());
+        m.last_verified = Some(2000);
+    }).unwrap();
+    diddir.update_metadata("bob", |m| {
+        m.trust = TrustLevel::Marginal;
+        m.tags.insert("work".to_string());
+        m.source = Some("keyserver".to_string());
+        m.notes = Some("Bob from accounting".to_string());
+        m.last_verified = Some(1000);
+    }).unwrap();
+    diddir.update_metadata("carol", |m| m.trust = TrustLevel::Never).unwrap();
+
+    let find = |query: Query| diddir.query(&query).unwrap();
+    assert_eq!(find(Query { min_trust: Some(TrustLevel::Marginal), ..Default::default() }), vec!["alice", "bob"]);
+    assert_eq!(find(Query { min_trust: Some(TrustLevel::Unknown), ..Default::default() }), vec!["alice", "bob"]);
+    assert_eq!(find(Query { tag: Some("work".to_string()), ..Default::default() }), vec!["bob"]);
+    assert_eq!(find(Query { source: Some("keyserver".to_string()), ..Default::default() }), vec!["bob"]);
+    assert_eq!(find(Query { notes: Some("accounting".to_string()), ..Default::default() }), vec!["bob"]);
+    assert_eq!(find(Query { verified_since: Some(1500), ..Default::default() }), vec!["alice"]);
+    assert_eq!(find(Query { created_since: Some(1), ..Default::default() }), vec!["alice", "bob", "carol"]);
+    assert!(find(Query { updated_since: Some(u64::MAX), ..Default::default() }).is_empty());
+    assert!(find(Query { tag: Some("me".to_string()), alias: Some("a".to_string()), ..Default::default() })
+        .is_empty());
+}
+
+#[test]
+fn metadata_defaults() {
+    // older records and ones written by hand only need some of the fields
+    let metadata: Metadata = serde_json::from_str("{\"trust\": \"marginal\"}").unwrap();
+    assert_eq!(metadata.trust, TrustLevel::Marginal);
+    assert_eq!(metadata.created, 0);
+    assert!(metadata.tags.is_empty());
+    assert!(TrustLevel::Never < TrustLevel::Unknown && TrustLevel::Full < TrustLevel::Ultimate);
+}
@@ -0,0 +1,159 @@
+extern crate diddir;
+#[macro_use]
+extern crate cfg_if;
+extern crate tempfile;
+
+cfg_if! {
+    if #[cfg(unix)] {
+        use diddir::dir::unix::DIDDirSys;
+    } else if #[cfg(target_os = "windows")] {
+        use diddir::dir::windows::DIDDirSys;
+    } else if #[cfg(target_arch = "wasm32")] {
+        use diddir::dir::wasm::DIDDirSys;
+    }
+}
+
+use diddir::{Config, DIDDir};
+use diddir::migrate::{self, LAYOUT_VERSION};
+use diddir::storage::FsStorage;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tempfile::{tempdir, TempDir};
+
+/// A keyring as written before layouts had versions.
+fn unversioned() -> (TempDir, Config) {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    for d in [config.root_dir(), config.aliases_dir(), config.tmp_dir()].iter() {
+        fs::create_dir_all(d).unwrap();
+        DIDDirSys::set_permission(d).unwrap();
+    }
+    write(&config.root_dir().join("foo"), "{}");
+    write(&config.aliases_dir().join("bar"), "foo\n");
+    fs::create_dir(config.aliases_dir().join("work")).unwrap();
+    DIDDirSys::set_permission(&config.aliases_dir().join("work")).unwrap();
+    write(&config.aliases_dir().join("work").join("baz"), " foo ");
+    (dir, config)
+}
+
+fn write(path: &Path, data: &str) {
+    fs::write(path, data).unwrap();
+    DIDDirSys::set_permission(path).unwrap();
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn migrate_init() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    assert_eq!(migrate::migrate(&config).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    DIDDir::init(&config).unwrap();
+    assert_eq!(migrate::layout_version(&config).unwrap(), LAYOUT_VERSION);
+    assert_eq!(migrate::migrate(&config).unwrap(), None);
+    assert!(!config.root_dir().join(".backups").exists());
+}
+
+#[test]
+fn migrate_v0_to_v1() {
+    let (_dir, config) = unversioned();
+    assert_eq!(migrate::layout_version(&config).unwrap(), 0);
+    assert_eq!(FsStorage::open(&config).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    // opening migrates, keeping a copy of how it was
+    let diddir = DIDDir::open(&config).unwrap();
+    assert_eq!(migrate::layout_version(&config).unwrap(), LAYOUT_VERSION);
+    assert_eq!(read(&config.aliases_dir().join("bar")), "foo");
+    assert_eq!(read(&config.aliases_dir().join("work").join("baz")), "foo");
+    assert_eq!(diddir.get_pkid_from_alias("work/baz").unwrap(), "foo");
+    assert_eq!(diddir.get_identity("foo").unwrap(), "{}");
+
+    let backups: Vec<_> = fs::read_dir(config.root_dir().join(".backups")).unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    assert_eq!(backups.len(), 1);
+    let backup = &backups[0];
+    assert!(backup.file_name().unwrap().to_str().unwrap().starts_with("v0-"));
+    assert_eq!(read(&backup.join("foo")), "{}");
+    assert_eq!(read(&backup.join("aliases").join("bar")), "foo\n");
+    assert!(!backup.join(".version").exists());
+    assert!(!config.root_dir().join(".migrating").exists());
+    FsStorage::open(&config).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn migrate_legacy_names() {
+    let (_dir, config) = unversioned();
+    write(&config.root_dir().join("did:example:123"), "{\"v\": 1}");
+    write(&config.aliases_dir().join("alice"), "did:example:123\n");
+    write(&config.aliases_dir().join("bob smith"), "foo");
+
+    // names that are no longer valid get encoded rather than failing the migration
+    let diddir = DIDDir::open(&config).unwrap();
+    assert_eq!(migrate::layout_version(&config).unwrap(), LAYOUT_VERSION);
+    assert_eq!(migrate::legacy_name("did:example:123").unwrap(), "did=3Aexample=3A123");
+    assert_eq!(diddir.get_identity("did=3Aexample=3A123").unwrap(), "{\"v\": 1}");
+    assert_eq!(diddir.get_pkid_from_alias("alice").unwrap(), "did=3Aexample=3A123");
+    assert_eq!(diddir.get_pkid_from_alias("bob=20smith").unwrap(), "foo");
+    assert_eq!(diddir.get_pkid_from_alias("work/baz").unwrap(), "foo");
+    assert!(!config.root_dir().join("did:example:123").exists());
+
+    // valid names stay as they are, names that can't be made valid fail
+    assert_eq!(migrate::legacy_name("a=b").unwrap(), "a=b");
+    assert!(migrate::legacy_name("con").is_err());
+}
+
+#[test]
+fn migrate_failure_rolls_back() {
+    let (_dir, config) = unversioned();
+    write(&config.aliases_dir().join("zzz"), "../../etc/passwd");
+
+    // bar is fixed before zzz fails, and put back after
+    let err = DIDDir::open(&config).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(migrate::layout_version(&config).unwrap(), 0);
+    assert_eq!(read(&config.aliases_dir().join("bar")), "foo\n");
+    assert!(!config.root_dir().join(".migrating").exists());
+
+    fs::remove_file(config.aliases_dir().join("zzz")).unwrap();
+    DIDDir::open(&config).unwrap();
+    assert_eq!(migrate::layout_version(&config).unwrap(), LAYOUT_VERSION);
+}
+
+#[test]
+fn migrate_interrupted() {
+    let (_dir, config) = unversioned();
+    let backup = migrate::migrate(&config).unwrap().unwrap();
+
+    // as if the process died part way through a migration from that copy
+    write(&config.root_dir().join(".migrating"), backup.file_name().unwrap().to_str().unwrap());
+    write(&config.root_dir().join("stray"), "{}");
+    write(&config.aliases_dir().join("bar"), "stray");
+    assert_eq!(FsStorage::open(&config).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    let diddir = DIDDir::open(&config).unwrap();
+    assert_eq!(migrate::layout_version(&config).unwrap(), LAYOUT_VERSION);
+    assert!(diddir.get_identity("stray").is_err());
+    assert_eq!(diddir.get_pkid_from_alias("bar").unwrap(), "foo");
+    assert!(!config.root_dir().join(".migrating").exists());
+}
+
+#[test]
+fn migrate_newer_layout() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    DIDDir::init(&config).unwrap();
+    write(&config.root_dir().join(".version"), &format!("{}\n", LAYOUT_VERSION + 1));
+
+    assert_eq!(DIDDir::open(&config).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    assert_eq!(migrate::migrate(&config).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    assert!(!config.root_dir().join(".backups").exists());
+
+    write(&config.root_dir().join(".version"), "one");
+    assert_eq!(DIDDir::open(&config).unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
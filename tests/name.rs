@@ -0,0 +1,112 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Alias, Config, DIDDir, NameError, Pkid};
+use diddir::name::MAX_NAME_LEN;
+use std::io;
+use tempfile::tempdir;
+
+#[test]
+fn name_valid() {
+    let pkid = "c506310b2c1ceb27212c4478055a44ac6b26969af73da0828bf28fc4867f09bb";
+    assert_eq!(Pkid::new(pkid).unwrap().as_str(), pkid);
+    assert_eq!(Alias::new("chad.smith@no.email").unwrap().as_str(), "chad.smith@no.email");
+    assert_eq!(Alias::new("bob+work_2-x=").unwrap().to_string(), "bob+work_2-x=");
+    assert_eq!(Alias::new("zoë").unwrap().as_str(), "zoë");
+    assert_eq!(Alias::new(&"a".repeat(MAX_NAME_LEN)).unwrap().as_str().len(), MAX_NAME_LEN);
+
+    let alias = Alias::new("work/alice").unwrap();
+    assert_eq!(alias.namespace(), Some("work"));
+    assert_eq!(alias.name(), "alice");
+    let alias = Alias::new("/alice").unwrap();
+    assert_eq!(alias.as_str(), "alice");
+    assert_eq!(alias.namespace(), None);
+}
+
+#[test]
+fn name_invalid() {
+    assert_eq!(Alias::new(""), Err(NameError::Empty));
+    assert_eq!(Alias::new(&"a".repeat(MAX_NAME_LEN + 1)), Err(NameError::TooLong(MAX_NAME_LEN + 1)));
+    assert_eq!(Pkid::new("../../.bashrc"), Err(NameError::InvalidChar('/')));
+    assert_eq!(Pkid::new("tmp/x"), Err(NameError::InvalidChar('/')));
+    // aliases take a namespace, which has to be a valid name too
+    assert_eq!(Alias::new("../../.bashrc"), Err(NameError::Reserved("..".to_string())));
+    assert_eq!(Alias::new("tmp/x"), Err(NameError::Reserved("tmp".to_string())));
+    assert_eq!(Alias::new("a/b/c"), Err(NameError::InvalidChar('/')));
+    assert_eq!(Alias::new("a/"), Err(NameError::Empty));
+    assert_eq!(Alias::new("..\\x"), Err(NameError::InvalidChar('\\')));
+    assert_eq!(Alias::new("a b"), Err(NameError::InvalidChar(' ')));
+    assert_eq!(Alias::new("a\0b"), Err(NameError::InvalidChar('\0')));
+    assert_eq!(Pkid::new("did:key:z6Mk"), Err(NameError::InvalidChar(':')));
+}
+
+#[test]
+fn name_reserved() {
+    for name in &[".", "..", ".lock", ".bashrc", "aliases", "tmp", "TMP", "con", "NUL.txt", "com1"] {
+        assert_eq!(Pkid::new(name), Err(NameError::Reserved(name.to_string())));
+    }
+}
+
+#[test]
+fn name_normalized() {
+    // "e" followed by a combining diaeresis composes to "ë"
+    let decomposed = Alias::new("zoe\u{308}").unwrap();
+    let composed = Alias::new("zo\u{eb}").unwrap();
+    assert_eq!(decomposed, composed);
+    assert_eq!(decomposed.as_str(), "zo\u{eb}");
+}
+
+#[test]
+fn name_diddir() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(&dir.path().join("diddir"));
+    let mut diddir = DIDDir::init(&config).unwrap();
+
+    let bad = ["../../.bashrc", "tmp/x", "aliases", ".lock", ""];
+    for name in bad.iter() {
+        assert_invalid(diddir.save_identity(name, "{}"));
+        assert_invalid(diddir.get_identity(name));
+        assert_invalid(diddir.remove_identity(name));
+        assert_invalid(diddir.save_alias(name, "foo"));
+        assert_invalid(diddir.save_alias("foo", name));
+        assert_invalid(diddir.get_pkid_from_alias(name));
+        assert_invalid(diddir.remove_alias(name));
+        assert_eq!(diddir.get_aliases(name), None);
+    }
+
+    // nothing escaped the keyring
+    assert!(!dir.path().join(".bashrc").exists());
+    assert!(!config.tmp_dir().join("x").exists());
+    assert_eq!(diddir.get_identities(), None);
+
+    // names are stored normalized, so either spelling finds them
+    diddir.save_identity("zoe\u{308}", "{}").unwrap();
+    diddir.save_alias("zo\u{eb}", "zoe\u{308}").unwrap();
+    assert_eq!(diddir.get_identity("zo\u{eb}").unwrap(), "{}");
+    assert_eq!(diddir.get_pkid_from_alias("zoe\u{308}").unwrap(), "zo\u{eb}");
+    assert!(config.root_dir().join("zo\u{eb}").is_file());
+}
+
+#[test]
+fn name_custom_subdirs() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    config.set_aliases_subdir("contacts").unwrap();
+    config.set_tmp_subdir("scratch").unwrap();
+    let mut diddir = DIDDir::init(&config).unwrap();
+
+    // a pkid can't take the place of the custom dirs
+    for name in ["contacts", "Contacts", "scratch"].iter() {
+        let err = diddir.save_identity(name, "{}").unwrap_err();
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<NameError>(), Some(&NameError::Reserved(name.to_string())));
+        assert_invalid(diddir.remove_identity(name));
+    }
+    assert!(config.aliases_dir().is_dir());
+    diddir.save_identity("contacts2", "{}").unwrap();
+}
+
+fn assert_invalid<T: std::fmt::Debug>(result: io::Result<T>) {
+    let err = result.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(err.get_ref().unwrap().downcast_ref::<NameError>().is_some());
+}
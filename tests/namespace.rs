@@ -0,0 +1,121 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir, Replica};
+use diddir::sync::{self, PreferLeft};
+use std::io;
+use tempfile::tempdir;
+
+#[test]
+fn namespace_aliases() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.save_identity("alice-work", "{}").unwrap();
+    diddir.save_identity("alice-conf", "{}").unwrap();
+
+    // the same name in different namespaces doesn't collide
+    diddir.save_alias("work/alice", "alice-work").unwrap();
+    diddir.save_alias("conference/alice", "alice-conf").unwrap();
+    diddir.save_alias("alice", "alice-work").unwrap();
+    assert_eq!(diddir.get_pkid_from_alias("work/alice").unwrap(), "alice-work");
+    assert_eq!(diddir.get_pkid_from_alias("conference/alice").unwrap(), "alice-conf");
+    assert_eq!(diddir.get_pkid_from_alias("alice").unwrap(), "alice-work");
+    assert!(config.aliases_dir().join("work").join("alice").is_file());
+
+    // other processes see them too
+    let other = DIDDir::open(&config).unwrap();
+    let mut aliases = other.get_aliases("alice-work").unwrap();
+    aliases.sort();
+    assert_eq!(aliases, vec!["alice", "work/alice"]);
+    assert_eq!(other.namespaces().unwrap(), vec!["conference", "work"]);
+    assert_eq!(other.aliases_in(Some("work")).unwrap(),
+        vec![("work/alice".to_string(), "alice-work".to_string())]);
+    assert_eq!(other.aliases_in(None).unwrap(), vec![("alice".to_string(), "alice-work".to_string())]);
+
+    // removing a namespace leaves the others alone
+    assert_eq!(diddir.remove_namespace("conference").unwrap(), 1);
+    assert!(diddir.get_pkid_from_alias("conference/alice").is_err());
+    assert!(!config.aliases_dir().join("conference").exists());
+    assert_eq!(diddir.namespaces().unwrap(), vec!["work"]);
+    assert_eq!(diddir.get_identity("alice-conf").unwrap(), "{}");
+
+    // and so does removing an identity's aliases
+    diddir.remove_identity("alice-work").unwrap();
+    assert!(diddir.namespaces().unwrap().is_empty());
+    assert!(diddir.aliases_in(None).unwrap().is_empty());
+}
+
+#[test]
+fn namespace_alias_collisions() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.save_identity("alice", "{}").unwrap();
+
+    // an alias in the way of a namespace
+    diddir.save_alias("work", "alice").unwrap();
+    let err = diddir.save_alias("work/alice", "alice").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert!(err.to_string().contains("work/alice"));
+
+    // and a namespace in the way of an alias
+    diddir.save_alias("home/alice", "alice").unwrap();
+    let err = diddir.save_alias("/home", "alice").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert!(err.to_string().contains("home"));
+
+    // neither touched what was there
+    assert_eq!(diddir.get_pkid_from_alias("work").unwrap(), "alice");
+    assert_eq!(diddir.get_pkid_from_alias("home/alice").unwrap(), "alice");
+    assert!(diddir.get_pkid_from_alias("work/alice").is_err());
+}
+
+#[test]
+fn namespace_default() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    config.set_default_namespace(Some("work"));
+    let mut diddir = DIDDir::init(&config).unwrap();
+    assert_eq!(diddir.default_namespace(), Some("work"));
+    diddir.save_identity("foo", "{}").unwrap();
+
+    diddir.save_alias("alice", "foo").unwrap();
+    diddir.save_alias("/bob", "foo").unwrap();
+    diddir.save_alias("personal/carol", "foo").unwrap();
+    assert_eq!(diddir.get_pkid_from_alias("work/alice").unwrap(), "foo");
+    assert_eq!(diddir.get_pkid_from_alias("/bob").unwrap(), "foo");
+    assert!(diddir.get_pkid_from_alias("bob").is_err());
+
+    diddir.set_default_namespace(None).unwrap();
+    assert_eq!(diddir.get_pkid_from_alias("bob").unwrap(), "foo");
+    assert!(diddir.get_pkid_from_alias("alice").is_err());
+
+    diddir.set_default_namespace(Some("personal")).unwrap();
+    diddir.transaction(|tx| {
+        assert_eq!(tx.get_pkid_from_alias("carol")?, "foo");
+        tx.remove_alias("carol")?;
+        tx.remove_alias("/bob")
+    }).unwrap();
+    assert_eq!(diddir.aliases_in(None).unwrap(), vec![]);
+    assert_eq!(diddir.namespaces().unwrap(), vec!["work"]);
+
+    let err = diddir.set_default_namespace(Some("a/b")).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(diddir.remove_namespace("..").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn namespace_sync() {
+    // aliases keep their namespace, whatever the default on either side
+    let mut left = DIDDir::in_memory();
+    left.save_identity("foo", "{}").unwrap();
+    left.save_alias("alice", "foo").unwrap();
+    left.save_alias("work/alice", "foo").unwrap();
+
+    let mut right = DIDDir::in_memory();
+    right.set_default_namespace(Some("personal")).unwrap();
+    sync::sync(&mut left, &mut right, None, &mut PreferLeft).unwrap();
+    assert_eq!(right.entries().unwrap(), left.entries().unwrap());
+    assert_eq!(right.namespaces().unwrap(), vec!["work"]);
+}
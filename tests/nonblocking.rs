@@ -0,0 +1,196 @@
+#![cfg(feature = "async")]
+
+extern crate diddir;
+extern crate tempfile;
+extern crate tokio;
+
+use diddir::{AsyncDIDDir, AuditOp, AuditQuery, Config, DIDDir};
+use std::io;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn nonblocking_basics() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    assert_eq!(AsyncDIDDir::open(&config).await.unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    let diddir = AsyncDIDDir::init(&config).await.unwrap();
+    diddir.save_identity("foo", "{}").await.unwrap();
+    diddir.save_alias("bar", "foo").await.unwrap();
+    diddir.save_alias("work/baz", "foo").await.unwrap();
+    assert_eq!(diddir.get_pkid_from_alias("bar").await.unwrap(), "foo");
+    assert_eq!(diddir.get_identity("foo").await.unwrap(), "{}");
+    assert_eq!(diddir.get_identities().await.unwrap(), Some(vec!["foo".to_string()]));
+    assert_eq!(diddir.with(|d| d.namespaces()).await.unwrap(), vec!["work"]);
+
+    assert_eq!(diddir.remove_namespace("work").await.unwrap(), 1);
+    diddir.remove_alias("bar").await.unwrap();
+    assert_eq!(diddir.get_aliases("foo").await.unwrap(), None);
+    diddir.remove_identity("foo").await.unwrap();
+    assert_eq!(diddir.get_identity("foo").await.unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(diddir.save_identity("../foo", "{}").await.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    // what the sync API sees is the same keyring
+    diddir.save_identity("qux", "{}").await.unwrap();
+    assert_eq!(DIDDir::open(&config).unwrap().get_identity("qux").unwrap(), "{}");
+    let reopened = AsyncDIDDir::open_or_init(&config).await.unwrap();
+    assert_eq!(reopened.get_identity("qux").await.unwrap(), "{}");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn nonblocking_concurrent() {
+    const TASKS: usize = 8;
+    const SAVES: usize = 10;
+
+    let dir = tempdir().unwrap();
+    let diddir = AsyncDIDDir::init(&Config::with_path(dir.path())).await.unwrap();
+    diddir.save_identity("counter", "0").await.unwrap();
+
+    let tasks: Vec<_> = (0..TASKS).map(|t| {
+        let diddir = diddir.clone();
+        tokio::spawn(async move {
+            for i in 0..SAVES {
+                let pkid = format!("id-{}-{}", t, i);
+                diddir.save_identity(&pkid, "{}").await.unwrap();
+                diddir.save_alias(&format!("alias-{}-{}", t, i), &pkid).await.unwrap();
+                diddir.transaction(|tx| {
+                    let n: usize = tx.get_identity("counter")?.parse().unwrap();
+                    tx.save_identity("counter", &(n + 1).to_string())
+                }).await.unwrap();
+            }
+        })
+    }).collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    assert_eq!(diddir.get_identity("counter").await.unwrap(), (TASKS * SAVES).to_string());
+    assert_eq!(diddir.get_identities().await.unwrap().unwrap().len(), TASKS * SAVES + 1);
+    assert_eq!(diddir.get_pkid_from_alias("alias-7-9").await.unwrap(), "id-7-9");
+}
+
+#[tokio::test]
+async fn nonblocking_lock_wait() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let diddir = AsyncDIDDir::init(&config).await.unwrap();
+
+    // another process holds the keyring for a while
+    let (locked, wait) = mpsc::channel();
+    let holder = {
+        let config = config.clone();
+        thread::spawn(move || {
+            let mut other = DIDDir::open(&config).unwrap();
+            other.transaction(|tx| {
+                locked.send(()).unwrap();
+                thread::sleep(Duration::from_millis(300));
+                tx.save_identity("first", "{}")
+            }).unwrap();
+        })
+    };
+    wait.recv().unwrap();
+
+    // waiting for the lock doesn't stop this single threaded runtime
+    let save = {
+        let diddir = diddir.clone();
+        tokio::spawn(async move { diddir.save_identity("second", "{}").await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!save.is_finished());
+
+    save.await.unwrap().unwrap();
+    holder.join().unwrap();
+    diddir.refresh().await.unwrap();
+    let mut ids = diddir.get_identities().await.unwrap().unwrap();
+    ids.sort();
+    assert_eq!(ids, vec!["first", "second"]);
+}
+
+#[tokio::test]
+async fn nonblocking_bookkeeping() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    config.set_audit_log(true);
+    config.set_default_namespace(Some("team"));
+    let diddir = AsyncDIDDir::init(&config).await.unwrap();
+
+    // written by the async path, kept track of as by DIDDir
+    diddir.save_identity("foo", "{}").await.unwrap();
+    diddir.save_identity("foo", "{\"v\": 2}").await.unwrap();
+    diddir.save_alias("bar", "foo").await.unwrap();
+    assert_eq!(diddir.save_alias("baz", "../foo").await.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+    let sync = DIDDir::open(&config).unwrap();
+    assert_eq!(sync.get_identity("foo").unwrap(), "{\"v\": 2}");
+    assert_eq!(sync.get_pkid_from_alias("team/bar").unwrap(), "foo");
+    assert_eq!(sync.history("foo").unwrap().len(), 2);
+    let ops: Vec<_> = sync.audit_entries(&AuditQuery::default()).unwrap().iter().map(|e| e.op).collect();
+    assert_eq!(ops, vec![AuditOp::SaveIdentity, AuditOp::SaveIdentity, AuditOp::SaveAlias]);
+    assert_eq!(sync.verify_audit_log().unwrap().unwrap().seq, 3);
+
+    // nothing is left in tmp, even by a save that failed
+    std::fs::create_dir_all(config.root_dir().join("qux").join("in-the-way")).unwrap();
+    assert!(diddir.save_identity("qux", "{}").await.is_err());
+    assert_eq!(std::fs::read_dir(config.tmp_dir()).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn nonblocking_lock_timeout() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    config.set_lock_timeout(Duration::from_millis(100));
+    let diddir = AsyncDIDDir::init(&config).await.unwrap();
+
+    let (locked, wait) = mpsc::channel();
+    let (done, release) = mpsc::channel::<()>();
+    let holder = {
+        let config = config.clone();
+        thread::spawn(move || {
+            let mut other = DIDDir::open(&config).unwrap();
+            other.transaction(|tx| {
+                locked.send(()).unwrap();
+                release.recv().unwrap();
+                tx.save_identity("first", "{}")
+            }).unwrap();
+        })
+    };
+    wait.recv().unwrap();
+
+    let err = diddir.save_identity("second", "{}").await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(err.to_string().contains(&format!("held by pid {}", std::process::id())));
+    assert_eq!(diddir.with(|d| d.namespaces()).await.unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+    done.send(()).unwrap();
+    holder.join().unwrap();
+    diddir.save_identity("second", "{}").await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn nonblocking_with_shared() {
+    const SAVES: usize = 30;
+
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    config.set_lock_timeout(Duration::from_millis(500));
+    let diddir = AsyncDIDDir::init(&config).await.unwrap();
+
+    // blocking code saving through the same DIDDir doesn't trip up async saves
+    let shared = diddir.shared().clone();
+    let writer = thread::spawn(move || {
+        for i in 0..SAVES {
+            shared.save_identity(&format!("sync-{}", i), "{}").unwrap();
+            shared.get_identity(&format!("sync-{}", i)).unwrap();
+        }
+    });
+    for i in 0..SAVES {
+        diddir.save_identity(&format!("async-{}", i), "{}").await.unwrap();
+        diddir.get_identity(&format!("async-{}", i)).await.unwrap();
+    }
+    writer.join().unwrap();
+
+    assert_eq!(diddir.get_identities().await.unwrap().unwrap().len(), 2 * SAVES);
+}
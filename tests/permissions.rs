@@ -0,0 +1,95 @@
+#![cfg(unix)]
+
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir};
+use diddir::dir::unix::DIDDirSys;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{chown, symlink, PermissionsExt};
+use std::path::Path;
+use tempfile::{tempdir, TempDir};
+
+#[test]
+fn permissions_symlink() {
+    let (dir, config) = create_diddir();
+
+    // an identity that is really a link to somewhere else
+    let target = dir.path().join("elsewhere");
+    fs::write(&target, "{}").unwrap();
+    fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+    let link = config.root_dir().join("foo");
+    symlink(&target, &link).unwrap();
+
+    assert_unsafe(DIDDir::open(&config), &link, "is a symlink");
+    assert_unsafe(DIDDirSys::check_permission(&link), &link, "is a symlink");
+    assert_unsafe(DIDDirSys::set_permission(&link), &link, "is a symlink");
+}
+
+#[test]
+fn permissions_hardlink() {
+    let (dir, config) = create_diddir();
+    {
+        let mut diddir = DIDDir::open(&config).unwrap();
+        diddir.save_identity("foo", "{}").unwrap();
+    }
+
+    let path = config.root_dir().join("foo");
+    fs::hard_link(&path, dir.path().join("copy")).unwrap();
+    assert_unsafe(DIDDir::open(&config), &path, "has 2 hard links");
+}
+
+#[test]
+fn permissions_mode() {
+    let (_dir, config) = create_diddir();
+    fs::set_permissions(config.aliases_dir(), fs::Permissions::from_mode(0o755)).unwrap();
+    assert_unsafe(DIDDir::open(&config), config.aliases_dir(), "has permissions 755, expected 700");
+}
+
+#[test]
+fn permissions_parents() {
+    let (dir, config) = create_diddir();
+
+    // a group writable dir anywhere above the DIDDir is unsafe...
+    fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o770)).unwrap();
+    assert_unsafe(DIDDir::open(&config), dir.path(), "is group or world writable (770)");
+
+    // ...unless it is sticky, like /tmp
+    fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o1777)).unwrap();
+    DIDDir::open(&config).unwrap();
+}
+
+#[test]
+fn permissions_owner() {
+    let (_dir, config) = create_diddir();
+
+    // handing files to someone else needs root
+    let path = config.root_dir().join("foo");
+    fs::write(&path, "{}").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+    if chown(&path, Some(1), None).is_err() {
+        return;
+    }
+
+    let reason = format!("is owned by uid 1, not {}", current_uid(&config));
+    assert_unsafe(DIDDir::open(&config), &path, &reason);
+}
+
+fn create_diddir() -> (TempDir, Config) {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(&dir.path().join("diddir"));
+    DIDDir::init(&config).unwrap();
+    (dir, config)
+}
+
+fn current_uid(config: &Config) -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(config.root_dir()).unwrap().uid()
+}
+
+fn assert_unsafe<T: std::fmt::Debug>(result: io::Result<T>, path: &Path, reason: &str) {
+    let err = result.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(err.to_string(), format!("Unsafe DIDDir path {}: {}", path.display(), reason));
+}
@@ -0,0 +1,138 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir, Document, PkidDerivation};
+use std::io;
+use tempfile::tempdir;
+
+static ALICE: &str = r#"{
+    "@context": "https://w3id.org/did/v1",
+    "id": "did:example:alice",
+    "publicKey": [{
+        "id": "did:example:alice#keys-1",
+        "type": "Ed25519VerificationKey2018",
+        "controller": "did:example:alice",
+        "publicKeyBase58": "H3C2AVvLMv6gmMNam3uVAjZpfkcJCwDwnZn6z3wXmqPV"
+    }]
+}"#;
+
+static MALLORY: &str = r#"{
+    "@context": "https://w3id.org/did/v1",
+    "id": "did:example:mallory",
+    "publicKey": [{
+        "id": "did:example:mallory#keys-1",
+        "type": "Ed25519VerificationKey2018",
+        "controller": "did:example:mallory",
+        "publicKeyBase58": "8Ee4FWRUZbAvUeBJRhjqSX7iSFJUgDNdsNnDQdHyKWL6"
+    }]
+}"#;
+
+fn pkid_of(derivation: PkidDerivation, data: &str) -> String {
+    let doc: Document = serde_json::from_str(data).unwrap();
+    derivation.derive(&doc).unwrap()
+}
+
+#[test]
+fn pkid_derive() {
+    let doc: Document = serde_json::from_str(ALICE).unwrap();
+    assert_eq!(PkidDerivation::None.derive(&doc), None);
+    assert_eq!(PkidDerivation::KeyFingerprint.derive(&doc), Some(doc.public_key[0].fingerprint()));
+
+    let did = PkidDerivation::Did.derive(&doc).unwrap();
+    assert_eq!(did.len(), 64);
+    assert_ne!(did, pkid_of(PkidDerivation::Did, MALLORY));
+}
+
+#[test]
+fn pkid_primary_key() {
+    static TWO_KEYS: &str = r#"{
+        "@context": "https://w3id.org/did/v1",
+        "id": "did:example:alice",
+        "publicKey": [{
+            "id": "did:example:alice#keys-1",
+            "type": "Ed25519VerificationKey2018",
+            "controller": "did:example:alice",
+            "publicKeyBase58": "H3C2AVvLMv6gmMNam3uVAjZpfkcJCwDwnZn6z3wXmqPV"
+        }, {
+            "id": "did:example:alice#keys-2",
+            "type": "Ed25519VerificationKey2018",
+            "controller": "did:example:alice",
+            "publicKeyBase58": "8Ee4FWRUZbAvUeBJRhjqSX7iSFJUgDNdsNnDQdHyKWL6"
+        }]AUTHENTICATION
+    }"#;
+
+    // two keys of its own and nothing saying which one counts
+    let doc: Document = serde_json::from_str(&TWO_KEYS.replace("AUTHENTICATION", "")).unwrap();
+    assert_eq!(PkidDerivation::KeyFingerprint.derive(&doc), None);
+
+    // authentication picks one, wherever it is in the list
+    let doc: Document = serde_json::from_str(&TWO_KEYS.replace("AUTHENTICATION",
+        r#", "authentication": ["did:example:alice#keys-2"]"#)).unwrap();
+    assert_eq!(doc.primary_key().unwrap().id.as_str(), "did:example:alice#keys-2");
+    assert_eq!(PkidDerivation::KeyFingerprint.derive(&doc), Some(doc.public_key[1].fingerprint()));
+}
+
+#[test]
+fn pkid_check_on_save() {
+    for derivation in &[PkidDerivation::Did, PkidDerivation::KeyFingerprint] {
+        let dir = tempdir().unwrap();
+        let mut config = Config::with_path(dir.path());
+        config.set_pkid_derivation(*derivation);
+        let mut diddir = DIDDir::init(&config).unwrap();
+
+        let alice = pkid_of(*derivation, ALICE);
+        diddir.save_identity(&alice, ALICE).unwrap();
+        diddir.save_alias("alice", &alice).unwrap();
+
+        // mallory can't take over alice's pkid and with it her alias
+        let err = diddir.save_identity(&alice, MALLORY).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(diddir.get_identity(&alice).unwrap(), ALICE);
+
+        // nor store something that isn't a document at all
+        let err = diddir.save_identity(&alice, "{}").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn pkid_check_on_open() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+
+    // written before there was a rule
+    let alice = pkid_of(PkidDerivation::Did, ALICE);
+    {
+        let mut diddir = DIDDir::init(&config).unwrap();
+        diddir.save_identity(&alice, MALLORY).unwrap();
+    }
+
+    config.set_pkid_derivation(PkidDerivation::Did);
+    let err = DIDDir::open(&config).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains(&alice));
+
+    // fixing it up makes the DIDDir usable again
+    config.set_pkid_derivation(PkidDerivation::None);
+    {
+        let mut diddir = DIDDir::open(&config).unwrap();
+        diddir.save_identity(&alice, ALICE).unwrap();
+    }
+    config.set_pkid_derivation(PkidDerivation::Did);
+    let diddir = DIDDir::open(&config).unwrap();
+    assert_eq!(diddir.pkid_derivation(), PkidDerivation::Did);
+}
+
+#[test]
+fn pkid_check_other_storage() {
+    let mut diddir = DIDDir::in_memory();
+    diddir.save_identity("foo", ALICE).unwrap();
+
+    let err = diddir.set_pkid_derivation(PkidDerivation::KeyFingerprint).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(diddir.pkid_derivation(), PkidDerivation::None);
+
+    diddir.remove_identity("foo").unwrap();
+    diddir.set_pkid_derivation(PkidDerivation::KeyFingerprint).unwrap();
+    diddir.save_identity(&pkid_of(PkidDerivation::KeyFingerprint, ALICE), ALICE).unwrap();
+}
@@ -0,0 +1,134 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{AuditOp, AuditQuery, Config, DIDDir, TrustLevel};
+use std::io;
+use tempfile::tempdir;
+
+#[test]
+fn profile_config() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    assert_eq!(config.profile(), None);
+    assert!(config.profiles().unwrap().is_empty());
+
+    let work = config.with_profile("work").unwrap();
+    assert_eq!(work.profile(), Some("work"));
+    assert_eq!(work.base_dir(), dir.path());
+    assert_eq!(work.root_dir(), dir.path().join(".profiles").join("work"));
+    assert_eq!(work.aliases_dir(), work.root_dir().join("aliases"));
+    assert_eq!(work.with_profile("test").unwrap().root_dir(), dir.path().join(".profiles").join("test"));
+    assert_eq!(work.without_profile(), config);
+
+    // profiles only show up once they are initialized
+    assert!(config.profiles().unwrap().is_empty());
+    DIDDir::init(&work).unwrap();
+    let test = config.with_profile("test").unwrap();
+    DIDDir::init(&test).unwrap();
+    assert_eq!(config.profiles().unwrap(), vec!["test", "work"]);
+
+    // the keyring without a profile doesn't see the profiles' identities
+    let mut diddir = DIDDir::init(&config).unwrap();
+    let mut in_work = DIDDir::open(&work).unwrap();
+    in_work.save_identity("foo", "{}").unwrap();
+    diddir.refresh().unwrap();
+    assert_eq!(diddir.get_identities(), None);
+
+    assert_eq!(config.with_profile("../x").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(config.with_profile("aliases").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn profile_default() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    assert_eq!(config.default_profile().unwrap(), None);
+
+    let err = config.set_default_profile(Some("work")).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    DIDDir::init(&config.with_profile("work").unwrap()).unwrap();
+    config.set_default_profile(Some("work")).unwrap();
+    assert_eq!(config.default_profile().unwrap(), Some("work".to_string()));
+    assert_eq!(config.with_profile("test").unwrap().default_profile().unwrap(), Some("work".to_string()));
+
+    config.set_default_profile(None).unwrap();
+    assert_eq!(config.default_profile().unwrap(), None);
+    config.set_default_profile(None).unwrap();
+}
+
+#[test]
+fn profile_copy_move() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let personal = config.with_profile("personal").unwrap();
+    let work = config.with_profile("work").unwrap();
+    let mut from = DIDDir::init(&personal).unwrap();
+    let mut to = DIDDir::init(&work).unwrap();
+
+    from.save_identity("alice", "{}").unwrap();
+    from.save_alias("alice", "alice").unwrap();
+    from.save_alias("friends/ally", "alice").unwrap();
+    from.update_metadata("alice", |m| m.trust = TrustLevel::Full).unwrap();
+    let created = from.metadata("alice").unwrap().created;
+
+    // aliases land where they were, whatever the default namespace there
+    to.set_default_namespace(Some("team")).unwrap();
+    from.copy_identity_to("alice", &mut to).unwrap();
+    assert_eq!(to.get_identity("alice").unwrap(), "{}");
+    assert_eq!(to.get_pkid_from_alias("/alice").unwrap(), "alice");
+    assert_eq!(to.get_pkid_from_alias("friends/ally").unwrap(), "alice");
+    assert_eq!(to.metadata("alice").unwrap().trust, TrustLevel::Full);
+    assert_eq!(to.metadata("alice").unwrap().created, created);
+    assert_eq!(from.get_identity("alice").unwrap(), "{}");
+
+    // an alias taken by someone else stops the move before anything changes
+    from.save_identity("bob", "{}").unwrap();
+    from.save_alias("bob", "bob").unwrap();
+    to.save_identity("robert", "{}").unwrap();
+    to.save_alias("/bob", "robert").unwrap();
+    let err = from.move_identity_to("bob", &mut to).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert!(to.get_identity("bob").is_err());
+    assert_eq!(from.get_identity("bob").unwrap(), "{}");
+
+    to.remove_alias("/bob").unwrap();
+    from.move_identity_to("bob", &mut to).unwrap();
+    assert_eq!(to.get_pkid_from_alias("/bob").unwrap(), "bob");
+    assert!(from.get_identity("bob").is_err());
+    assert!(from.get_pkid_from_alias("bob").is_err());
+
+    let err = from.move_identity_to("nobody", &mut to).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    // the same document under the pkid is fine, a different one isn't
+    from.save_identity("carol", "{}").unwrap();
+    to.save_identity("carol", "{}").unwrap();
+    from.copy_identity_to("carol", &mut to).unwrap();
+    from.save_identity("carol", "{\"v\": 2}").unwrap();
+    let err = from.move_identity_to("carol", &mut to).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(to.get_identity("carol").unwrap(), "{}");
+    assert_eq!(from.get_identity("carol").unwrap(), "{\"v\": 2}");
+}
+
+#[test]
+fn profile_copy_is_one_transaction() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let personal = config.with_profile("personal").unwrap();
+    let work = config.with_profile("work").unwrap();
+    let mut from = DIDDir::init(&personal).unwrap();
+    let mut to = DIDDir::init(&work).unwrap();
+    to.set_audit_log(true);
+
+    from.save_identity("alice", "{}").unwrap();
+    from.save_alias("alice", "alice").unwrap();
+    from.update_metadata("alice", |m| m.notes = Some("from home".to_string())).unwrap();
+    from.copy_identity_to("alice", &mut to).unwrap();
+
+    // identity, alias and metadata all went in together
+    assert_eq!(to.metadata("alice").unwrap().notes.as_deref(), Some("from home"));
+    let ops: Vec<_> = to.audit_entries(&AuditQuery::default()).unwrap().iter().map(|e| e.op).collect();
+    assert_eq!(ops, vec![AuditOp::SaveIdentity, AuditOp::SaveAlias, AuditOp::UpdateMetadata]);
+}
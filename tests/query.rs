@@ -0,0 +1,130 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir, Document, PublicKeyType, Query};
+use std::fs;
+use tempfile::tempdir;
+
+static ALICE: &str = r#"{
+    "@context": "https://w3id.org/did/v1",
+    "id": "did:example:alice",
+    "publicKey": [{
+        "id": "did:example:alice#keys-1",
+        "type": "Ed25519VerificationKey2018",
+        "controller": "did:example:alice",
+        "publicKeyBase58": "H3C2AVvLMv6gmMNam3uVAjZpfkcJCwDwnZn6z3wXmqPV"
+    }],
+    "service": [{
+        "id": "did:example:alice#inbox",
+        "type": "MessagingService",
+        "serviceEndpoint": "https://alice.example/inbox"
+    }]
+}"#;
+
+static BOB: &str = r#"{
+    "@context": "https://w3id.org/did/v1",
+    "id": "did:web:bob.example",
+    "publicKey": [{
+        "id": "did:web:bob.example#keys-1",
+        "type": "RsaVerificationKey2018",
+        "controller": "did:example:alice",
+        "publicKeyPem": "-----BEGIN PUBLIC KEY...END PUBLIC KEY-----"
+    }]
+}"#;
+
+fn keyring(diddir: &mut DIDDir) {
+    diddir.save_identity("alice", ALICE).unwrap();
+    diddir.save_identity("bob", BOB).unwrap();
+    diddir.save_identity("carol", "{}").unwrap();
+    diddir.save_alias("alice@work", "alice").unwrap();
+    diddir.save_alias("bob@work", "bob").unwrap();
+    diddir.save_alias("carol@home", "carol").unwrap();
+}
+
+#[test]
+fn query_fields() {
+    let mut diddir = DIDDir::in_memory();
+    keyring(&mut diddir);
+
+    let find = |query: Query| diddir.query(&query).unwrap();
+    assert_eq!(find(Query::default()), vec!["alice", "bob", "carol"]);
+    assert_eq!(find(Query { method: Some("example".to_string()), ..Default::default() }), vec!["alice"]);
+    assert_eq!(find(Query { method: Some("web".to_string()), ..Default::default() }), vec!["bob"]);
+    assert_eq!(find(Query { controller: Some("did:example:alice".to_string()), ..Default::default() }),
+        vec!["alice", "bob"]);
+    assert_eq!(find(Query { key_type: Some(PublicKeyType::RsaVerificationKey2018), ..Default::default() }),
+        vec!["bob"]);
+    assert_eq!(find(Query { service_type: Some("MessagingService".to_string()), ..Default::default() }),
+        vec!["alice"]);
+    assert_eq!(find(Query { alias: Some("@work".to_string()), ..Default::default() }), vec!["alice", "bob"]);
+
+    // which identity owns this key?
+    let key = "H3C2AVvLMv6gmMNam3uVAjZpfkcJCwDwnZn6z3wXmqPV";
+    assert_eq!(find(Query { public_key: Some(key.to_string()), ..Default::default() }), vec!["alice"]);
+    let doc: Document = serde_json::from_str(ALICE).unwrap();
+    let fingerprint = doc.public_key[0].fingerprint();
+    assert_eq!(find(Query { fingerprint: Some(fingerprint.clone()), ..Default::default() }), vec!["alice"]);
+
+    // the same key however it is encoded
+    let bytes = bs58::decode(key).into_vec().unwrap();
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(find(Query { public_key: Some(hex.clone()), ..Default::default() }), vec!["alice"]);
+    let doc: Document = serde_json::from_str(&ALICE.replace("publicKeyBase58", "publicKeyHex")
+        .replace(key, &hex)).unwrap();
+    assert_eq!(doc.public_key[0].fingerprint(), fingerprint);
+    let doc: Document = serde_json::from_str(&ALICE.replace("publicKeyBase58", "publicKeyMultibase")
+        .replace(key, &format!("z{}", key))).unwrap();
+    assert_eq!(doc.public_key[0].fingerprint(), fingerprint);
+
+    // every field has to match
+    let query = Query {
+        controller: Some("did:example:alice".to_string()),
+        alias: Some("bob".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(find(query), vec!["bob"]);
+    let query = Query {
+        method: Some("web".to_string()),
+        service_type: Some("MessagingService".to_string()),
+        ..Default::default()
+    };
+    assert!(find(query).is_empty());
+}
+
+#[test]
+fn query_follows_writes() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+    keyring(&mut diddir);
+    let rsa = Query { key_type: Some(PublicKeyType::RsaVerificationKey2018), ..Default::default() };
+
+    diddir.save_identity("bob", "{}").unwrap();
+    assert!(diddir.query(&rsa).unwrap().is_empty());
+    diddir.save_identity("carol", BOB).unwrap();
+    assert_eq!(diddir.query(&rsa).unwrap(), vec!["carol"]);
+    diddir.remove_identity("carol").unwrap();
+    assert!(diddir.query(&rsa).unwrap().is_empty());
+    assert!(!config.root_dir().join(".index").join("carol").exists());
+
+    // a document changed behind the index's back is still found
+    fs::write(config.root_dir().join("bob"), BOB).unwrap();
+    let mut diddir = DIDDir::open(&config).unwrap();
+    assert_eq!(diddir.query(&rsa).unwrap(), vec!["bob"]);
+    diddir.reindex().unwrap();
+    assert_eq!(diddir.query(&rsa).unwrap(), vec!["bob"]);
+}
+
+#[test]
+fn document_service() {
+    let doc: Document = serde_json::from_str(ALICE).unwrap();
+    assert_eq!(doc.id.method(), Some("example"));
+    assert_eq!(doc.service.len(), 1);
+    assert_eq!(doc.service[0].id.as_str(), "did:example:alice#inbox");
+    assert_eq!(doc.service[0].service_type, "MessagingService");
+    assert_eq!(doc.service[0].endpoint, "https://alice.example/inbox");
+
+    // documents without services serialize as before
+    let doc: Document = serde_json::from_str(BOB).unwrap();
+    assert!(!serde_json::to_string(&doc).unwrap().contains("service"));
+}
@@ -0,0 +1,140 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Backend, Config, DIDDir, ReadOnlyDIDDir};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use tempfile::tempdir;
+
+fn keyring(path: &Path) -> Config {
+    let config = Config::with_path(path);
+    {
+        let mut diddir = DIDDir::init(&config).unwrap();
+        diddir.save_identity("foo", "{\"name\": \"Foo\"}").unwrap();
+        diddir.save_alias("bar", "foo").unwrap();
+        diddir.save_alias("work/baz", "foo").unwrap();
+    }
+    config
+}
+
+/// Everything under `path` with its contents, to show nothing was touched.
+fn snapshot(path: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(path).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            entries.push((path.display().to_string(), Vec::new()));
+            entries.extend(snapshot(&path));
+        } else {
+            entries.push((path.display().to_string(), fs::read(&path).unwrap()));
+        }
+    }
+    entries.sort();
+    entries
+}
+
+#[test]
+fn read_only_lookups() {
+    let dir = tempdir().unwrap();
+    let config = keyring(dir.path());
+    let before = snapshot(dir.path());
+
+    let diddir = DIDDir::open_read_only(&config).unwrap();
+    assert!(diddir.storage().root_dir().is_some());
+    assert_eq!(diddir.get_identities(), Some(vec!["foo".to_string()]));
+    assert_eq!(diddir.get_identity("foo").unwrap(), "{\"name\": \"Foo\"}");
+    assert_eq!(diddir.get_pkid_from_alias("bar").unwrap(), "foo");
+    assert_eq!(diddir.get_pkid_from_alias("work/baz").unwrap(), "foo");
+    assert_eq!(diddir.namespaces().unwrap(), vec!["work"]);
+    assert_eq!(diddir.history("foo").unwrap().len(), 1);
+    assert!(diddir.metadata("foo").is_ok());
+    assert_eq!(diddir.get_identity("nope").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(snapshot(dir.path()), before);
+
+    // a writer's changes show up after a refresh
+    let mut writer = DIDDir::open(&config).unwrap();
+    writer.save_identity("qux", "{}").unwrap();
+    diddir.refresh().unwrap();
+    assert_eq!(diddir.get_identity("qux").unwrap(), "{}");
+}
+
+#[test]
+fn read_only_threads() {
+    fn shareable<T: Send + Sync>() {}
+    shareable::<ReadOnlyDIDDir>();
+
+    let dir = tempdir().unwrap();
+    let config = keyring(dir.path());
+    let diddir = Arc::new(DIDDir::open_read_only_owned(&config).unwrap());
+    drop(config);
+
+    let readers: Vec<_> = (0..8).map(|_| {
+        let diddir = Arc::clone(&diddir);
+        thread::spawn(move || {
+            for _ in 0..50 {
+                let pkid = diddir.get_pkid_from_alias("bar").unwrap();
+                assert_eq!(diddir.get_identity(&pkid).unwrap(), "{\"name\": \"Foo\"}");
+            }
+        })
+    }).collect();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn read_only_mount() {
+    use std::os::unix::fs::PermissionsExt;
+
+    fn set_mode(path: &Path, dir: u32, file: u32) {
+        let mode = if path.is_dir() { dir } else { file };
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+        if path.is_dir() {
+            for entry in fs::read_dir(path).unwrap() {
+                set_mode(&entry.unwrap().path(), dir, file);
+            }
+        }
+    }
+
+    // laid out like a read-only copy: no lock file, readable by all
+    let dir = tempdir().unwrap();
+    let config = keyring(dir.path());
+    fs::remove_file(config.lock_file()).unwrap();
+    set_mode(config.root_dir(), 0o555, 0o444);
+    let before = snapshot(dir.path());
+
+    let diddir = DIDDir::open_read_only(&config).unwrap();
+    assert_eq!(diddir.get_pkid_from_alias("bar").unwrap(), "foo");
+    assert!(diddir.query(&Default::default()).is_ok());
+    diddir.refresh().unwrap();
+    assert!(!config.lock_file().exists());
+    assert_eq!(snapshot(dir.path()), before);
+    assert_eq!(DIDDir::open(&config).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+
+    // but nothing others could have changed
+    set_mode(&config.aliases_dir().join("bar"), 0o555, 0o666);
+    assert_eq!(DIDDir::open_read_only(&config).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    set_mode(config.root_dir(), 0o700, 0o600);
+}
+
+#[test]
+fn read_only_unsupported() {
+    // an old layout needs migrating, which a read-only open won't do
+    let dir = tempdir().unwrap();
+    let config = keyring(dir.path());
+    fs::remove_file(config.root_dir().join(".version")).unwrap();
+    assert_eq!(DIDDir::open_read_only(&config).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    assert!(!config.root_dir().join(".version").exists());
+
+    let empty = tempdir().unwrap();
+    let config = Config::with_path(empty.path());
+    assert_eq!(DIDDir::open_read_only(&config).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+    let mut config = Config::with_path(dir.path());
+    config.set_backend(Backend::Sqlite);
+    assert_eq!(DIDDir::open_read_only(&config).unwrap_err().kind(), io::ErrorKind::Unsupported);
+}
@@ -0,0 +1,139 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir, PkidDerivation, Sharding};
+use diddir::dir::watch::{Event, WatchMode};
+use diddir::migrate::{self, LAYOUT_VERSION};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+
+fn config(path: &Path, sharding: Sharding) -> Config {
+    let mut config = Config::with_path(path);
+    config.set_pkid_derivation(PkidDerivation::None);
+    config.set_sharding(sharding);
+    config
+}
+
+/// The shard dirs in the root, and where in them `pkid` is.
+fn shards(config: &Config, pkid: &str) -> (usize, Vec<PathBuf>) {
+    let mut dirs = 0;
+    let mut found = Vec::new();
+    for entry in fs::read_dir(config.root_dir()).unwrap() {
+        let entry = entry.unwrap();
+        let name = entry.file_name().into_string().unwrap();
+        if entry.file_type().unwrap().is_dir() && name.len() == 2 {
+            dirs += 1;
+            if entry.path().join(pkid).is_file() {
+                found.push(entry.path().join(pkid));
+            }
+        }
+    }
+    (dirs, found)
+}
+
+#[test]
+fn shard_init() {
+    let dir = tempdir().unwrap();
+    let config = config(dir.path(), Sharding::Sharded);
+    let mut diddir = DIDDir::init(&config).unwrap();
+    assert_eq!(migrate::sharding(&config).unwrap(), Sharding::Sharded);
+    assert_eq!(migrate::layout_version(&config).unwrap(), LAYOUT_VERSION);
+
+    diddir.save_identity("foo", "{}").unwrap();
+    diddir.save_alias("bar", "foo").unwrap();
+    assert!(!config.root_dir().join("foo").exists());
+    let (dirs, found) = shards(&config, "foo");
+    assert_eq!(dirs, 256);
+    assert_eq!(found.len(), 1);
+
+    // another handle reads the same layout
+    let mut other = DIDDir::open(&config).unwrap();
+    assert_eq!(other.get_identities(), Some(vec!["foo".to_string()]));
+    assert_eq!(other.get_identity("foo").unwrap(), "{}");
+    assert_eq!(other.get_pkid_from_alias("bar").unwrap(), "foo");
+
+    diddir.save_identity("baz", "{\"x\": 1}").unwrap();
+    other.refresh().unwrap();
+    assert_eq!(other.get_identity("baz").unwrap(), "{\"x\": 1}");
+
+    other.remove_identity("foo").unwrap();
+    assert_eq!(shards(&config, "foo"), (256, vec![]));
+    diddir.refresh().unwrap();
+    assert_eq!(diddir.get_identities(), Some(vec!["baz".to_string()]));
+    assert!(diddir.get_pkid_from_alias("bar").is_err());
+
+    // the setting only applies to new keyrings
+    let flat = tempdir().unwrap();
+    DIDDir::init(&self::config(flat.path(), Sharding::Flat)).unwrap();
+    let sharded = self::config(flat.path(), Sharding::Sharded);
+    assert_eq!(migrate::sharding(&sharded).unwrap(), Sharding::Flat);
+    assert_eq!(DIDDir::open(&sharded).unwrap().get_identities(), None);
+}
+
+#[test]
+fn shard_reshard() {
+    let dir = tempdir().unwrap();
+    let config = config(dir.path(), Sharding::Flat);
+    let mut diddir = DIDDir::init(&config).unwrap();
+    // "ab" is also the name of a shard
+    for pkid in ["foo", "ab", "ff"].iter() {
+        diddir.save_identity(pkid, &format!("{{\"pkid\": \"{}\"}}", pkid)).unwrap();
+    }
+    diddir.save_alias("bar", "ab").unwrap();
+    assert_eq!(migrate::reshard(&config, Sharding::Flat).unwrap(), None);
+
+    let backup = migrate::reshard(&config, Sharding::Sharded).unwrap().unwrap();
+    assert_eq!(migrate::sharding(&config).unwrap(), Sharding::Sharded);
+    assert!(backup.join("ab").is_file());
+    assert!(!config.root_dir().join(".migrating").exists());
+    for pkid in ["foo", "ab", "ff"].iter() {
+        assert_eq!(shards(&config, pkid).1.len(), 1);
+    }
+
+    let diddir = DIDDir::open(&config).unwrap();
+    let mut ids = diddir.get_identities().unwrap();
+    ids.sort();
+    assert_eq!(ids, vec!["ab", "ff", "foo"]);
+    assert_eq!(diddir.get_identity("ab").unwrap(), "{\"pkid\": \"ab\"}");
+    assert_eq!(diddir.get_pkid_from_alias("bar").unwrap(), "ab");
+    assert_eq!(migrate::reshard(&config, Sharding::Sharded).unwrap(), None);
+
+    // and back
+    migrate::reshard(&config, Sharding::Flat).unwrap().unwrap();
+    assert_eq!(migrate::sharding(&config).unwrap(), Sharding::Flat);
+    assert!(config.root_dir().join("ab").is_file());
+    assert!(config.root_dir().join("foo").is_file());
+    assert_eq!(shards(&config, "foo"), (0, vec![]));
+    let diddir = DIDDir::open(&config).unwrap();
+    assert_eq!(diddir.get_identities().unwrap().len(), 3);
+    assert_eq!(diddir.get_identity("ff").unwrap(), "{\"pkid\": \"ff\"}");
+}
+
+#[test]
+fn shard_watch() {
+    let dir = tempdir().unwrap();
+    let config = config(dir.path(), Sharding::Sharded);
+    let mut watcher = DIDDir::init(&config).unwrap();
+    watcher.watch(WatchMode::Poll(Duration::from_millis(20))).unwrap();
+
+    let mut writer = DIDDir::open(&config).unwrap();
+    writer.save_identity("foo", "{}").unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut events = Vec::new();
+    while events.is_empty() && Instant::now() < deadline {
+        events.extend(watcher.wait_events(Duration::from_millis(100)).unwrap());
+    }
+    assert_eq!(events, vec![Event::IdentityAdded("foo".to_string())]);
+    assert_eq!(watcher.get_identity("foo").unwrap(), "{}");
+}
+
+#[test]
+fn shard_names() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    assert_eq!(config.set_aliases_subdir("a0").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    config.set_aliases_subdir("a0x").unwrap();
+}
@@ -0,0 +1,100 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir, SharedDIDDir};
+use std::path::Path;
+use std::thread;
+use tempfile::tempdir;
+
+/// Nothing borrowed: the config is gone by the time the DIDDir is used.
+fn open(path: &Path) -> SharedDIDDir {
+    let config = Config::with_path(path);
+    SharedDIDDir::open_or_init(&config).unwrap()
+}
+
+#[test]
+fn shared_owned() {
+    fn shareable<T: Send + Sync + 'static>() {}
+    shareable::<SharedDIDDir>();
+    shareable::<DIDDir<'static>>();
+
+    let dir = tempdir().unwrap();
+    let diddir = open(dir.path());
+    diddir.save_identity("foo", "{}").unwrap();
+    diddir.save_alias("bar", "foo").unwrap();
+
+    let other = diddir.clone();
+    thread::spawn(move || {
+        assert_eq!(other.get_pkid_from_alias("bar").unwrap(), "foo");
+        other.remove_alias("bar").unwrap();
+    }).join().unwrap();
+    assert!(diddir.get_pkid_from_alias("bar").is_err());
+    assert_eq!(diddir.read().history("foo").unwrap().len(), 1);
+
+    let config = Config::with_path(dir.path());
+    assert_eq!(DIDDir::open(&config).unwrap().get_identity("foo").unwrap(), "{}");
+
+    let memory = SharedDIDDir::in_memory();
+    memory.save_identity("foo", "{}").unwrap();
+    assert_eq!(memory.get_identities(), Some(vec!["foo".to_string()]));
+}
+
+#[test]
+fn shared_stress() {
+    const WRITERS: usize = 4;
+    const READERS: usize = 4;
+    const SAVES: usize = 25;
+
+    let dir = tempdir().unwrap();
+    let diddir = open(dir.path());
+    // a second handle on the same keyring stands in for another process
+    let elsewhere = open(dir.path());
+    diddir.save_identity("counter", "0").unwrap();
+
+    let mut threads = Vec::new();
+    for w in 0..WRITERS {
+        let diddir = if w % 2 == 0 { diddir.clone() } else { elsewhere.clone() };
+        threads.push(thread::spawn(move || {
+            for i in 0..SAVES {
+                let pkid = format!("id-{}-{}", w, i);
+                diddir.save_identity(&pkid, &format!("{{\"n\": {}}}", i)).unwrap();
+                diddir.save_alias(&format!("alias-{}-{}", w, i), &pkid).unwrap();
+
+                // read, modify, write without losing anybody's update
+                diddir.transaction(|tx| {
+                    tx.get_identity("counter").map(|n| n.parse::<usize>().unwrap())
+                        .and_then(|n| tx.save_identity("counter", &(n + 1).to_string()))
+                }).unwrap();
+            }
+        }));
+    }
+    for r in 0..READERS {
+        let diddir = diddir.clone();
+        threads.push(thread::spawn(move || {
+            for i in 0..SAVES * 4 {
+                // whatever an alias points at is there in full
+                let alias = format!("alias-{}-{}", r % WRITERS, i % SAVES);
+                if let Ok(pkid) = diddir.get_pkid_from_alias(&alias) {
+                    let data = diddir.get_identity(&pkid).unwrap();
+                    assert_eq!(data, format!("{{\"n\": {}}}", i % SAVES));
+                }
+                diddir.get_identities().unwrap();
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    for diddir in [diddir, elsewhere].iter() {
+        diddir.refresh().unwrap();
+        assert_eq!(diddir.get_identity("counter").unwrap(), (WRITERS * SAVES).to_string());
+        assert_eq!(diddir.get_identities().unwrap().len(), WRITERS * SAVES + 1);
+        for w in 0..WRITERS {
+            for i in 0..SAVES {
+                assert_eq!(diddir.get_pkid_from_alias(&format!("alias-{}-{}", w, i)).unwrap(),
+                    format!("id-{}-{}", w, i));
+            }
+        }
+    }
+}
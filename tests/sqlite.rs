@@ -0,0 +1,99 @@
+#![cfg(feature = "sqlite")]
+
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir, Document, Storage};
+use diddir::storage::SqliteStorage;
+use tempfile::tempdir;
+
+static DOC: &str = r#"{
+    "@context": "https://w3id.org/did/v1",
+    "id": "did:example:123456789abcdefghi",
+    "publicKey": [{
+        "id": "did:example:123456789abcdefghi#keys-1",
+        "type": "Ed25519VerificationKey2018",
+        "controller": "did:example:pqrstuvwxyz0987654321",
+        "publicKeyBase58": "H3C2AVvLMv6gmMNam3uVAjZpfkcJCwDwnZn6z3wXmqPV"
+    }]
+}"#;
+
+#[test]
+fn sqlite_diddir() {
+    let dir = tempdir().unwrap();
+    let db = dir.path().join("diddir.sqlite");
+
+    {
+        let mut diddir = DIDDir::open_sqlite(&db).unwrap();
+        diddir.save_identity("foo", "{}").unwrap();
+        diddir.save_identity("bar", "{}").unwrap();
+        diddir.save_alias("default", "foo").unwrap();
+        diddir.save_alias("foo@no.email", "foo").unwrap();
+        diddir.save_alias("bar@no.email", "bar").unwrap();
+        diddir.remove_identity("bar").unwrap();
+    }
+
+    // everything survives reopening
+    let diddir = DIDDir::open_sqlite(&db).unwrap();
+    assert_eq!(diddir.get_identities().unwrap(), vec!["foo".to_string()]);
+    assert_eq!(diddir.get_identity("foo").unwrap(), "{}");
+    assert_eq!(diddir.get_pkid_from_alias("default").unwrap(), "foo");
+    assert_eq!(diddir.get_aliases("foo").unwrap().len(), 2);
+    assert_eq!(diddir.get_aliases("bar"), None);
+    assert!(diddir.get_pkid_from_alias("bar@no.email").is_err());
+}
+
+#[test]
+fn sqlite_indexes() {
+    let mut storage = SqliteStorage::open_in_memory().unwrap();
+    storage.write_identity("foo", DOC).unwrap();
+    storage.write_identity("bar", "not a DID document").unwrap();
+
+    let doc: Document = serde_json::from_str(DOC).unwrap();
+    let fingerprint = doc.public_key[0].fingerprint();
+
+    assert_eq!(storage.find_by_did("did:example:123456789abcdefghi").unwrap(), vec!["foo".to_string()]);
+    assert_eq!(storage.find_by_controller("did:example:pqrstuvwxyz0987654321").unwrap(), vec!["foo".to_string()]);
+    assert_eq!(storage.find_by_fingerprint(&fingerprint).unwrap(), vec!["foo".to_string()]);
+
+    // rewriting replaces the index entries
+    let stamp = storage.identity_stamp("foo").unwrap();
+    storage.write_identity("foo", "{}").unwrap();
+    assert!(storage.find_by_fingerprint(&fingerprint).unwrap().is_empty());
+    assert_ne!(storage.identity_stamp("foo").unwrap(), stamp);
+}
+
+#[test]
+fn sqlite_migrate() {
+    let dir = tempdir().unwrap();
+    let from = Config::with_path(&dir.path().join("from"));
+    let to = Config::with_path(&dir.path().join("to"));
+    let db = dir.path().join("diddir.sqlite");
+
+    {
+        let mut diddir = DIDDir::init(&from).unwrap();
+        diddir.save_identity("foo", "{}").unwrap();
+        diddir.save_identity("foo", DOC).unwrap();
+        diddir.save_alias("default", "foo").unwrap();
+        diddir.update_metadata("foo", |m| { m.tags.insert("work".to_string()); }).unwrap();
+    }
+
+    // directory layout into sqlite...
+    let storage = SqliteStorage::import_dir(&db, &from).unwrap();
+    assert_eq!(storage.read_identity("foo").unwrap(), DOC);
+    assert_eq!(storage.read_alias("default").unwrap(), "foo");
+
+    // ...and back out again
+    storage.export_dir(&to).unwrap();
+    let diddir = DIDDir::open(&to).unwrap();
+    assert_eq!(diddir.get_identity("foo").unwrap(), DOC);
+    assert_eq!(diddir.get_pkid_from_alias("default").unwrap(), "foo");
+
+    // with everything kept about them
+    assert_eq!(diddir.history("foo").unwrap().len(), 2);
+    assert!(diddir.metadata("foo").unwrap().tags.contains("work"));
+
+    // neither way overwrites an existing keyring
+    assert_eq!(SqliteStorage::import_dir(&db, &from).unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+    assert_eq!(storage.export_dir(&to).unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+}
@@ -0,0 +1,142 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir, Storage};
+use diddir::dir::watch::WatchMode;
+use diddir::storage::{FsStorage, MemoryStorage};
+use tempfile::tempdir;
+use std::io;
+
+#[test]
+fn storage_memory() {
+    let mut storage = MemoryStorage::new();
+    assert!(storage.identities().unwrap().is_empty());
+
+    storage.write_identity("foo", "{}").unwrap();
+    storage.write_alias("bar", "foo").unwrap();
+    storage.write_alias("baz", "foo").unwrap();
+    assert!(storage.has_identity("foo").unwrap());
+    assert_eq!(storage.read_identity("foo").unwrap(), "{}");
+    assert_eq!(storage.read_alias("bar").unwrap(), "foo");
+
+    let mut aliases = storage.aliases_of("foo").unwrap();
+    aliases.sort();
+    assert_eq!(aliases, vec!["bar".to_string(), "baz".to_string()]);
+
+    // rewriting changes the stamp
+    let stamp = storage.identity_stamp("foo").unwrap();
+    storage.write_identity("foo", "{ }").unwrap();
+    assert_ne!(storage.identity_stamp("foo").unwrap(), stamp);
+
+    storage.delete_identity("foo").unwrap();
+    assert_eq!(storage.read_identity("foo").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert!(storage.delete_identity("foo").is_err());
+}
+
+#[test]
+fn storage_fs() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut storage = FsStorage::init(&config).unwrap();
+
+    storage.write_identity("foo", "{}").unwrap();
+    storage.write_alias("bar", "foo").unwrap();
+    assert!(config.root_dir().join("foo").is_file());
+    assert!(config.aliases_dir().join("bar").is_file());
+    assert_eq!(storage.aliases_of("foo").unwrap(), vec!["bar".to_string()]);
+    assert_eq!(storage.watch_dirs(), vec![config.root_dir().to_path_buf(), config.aliases_dir().to_path_buf()]);
+
+    storage.delete_alias("bar").unwrap();
+    storage.delete_identity("foo").unwrap();
+    assert!(storage.identities().unwrap().is_empty());
+}
+
+#[test]
+fn diddir_in_memory() {
+    let pkid = "c506310b2c1ceb27212c4478055a44ac6b26969af73da0828bf28fc4867f09bb";
+    let mut diddir = DIDDir::in_memory();
+    assert_eq!(diddir.get_identities(), None);
+
+    diddir.save_identity(pkid, "{\"name\": \"Chad Smith\"}").unwrap();
+    diddir.save_alias("default", pkid).unwrap();
+    diddir.save_alias("chad.smith@no.email", pkid).unwrap();
+    assert_eq!(diddir.get_identities().unwrap(), vec![pkid.to_string()]);
+    assert_eq!(diddir.get_pkid_from_alias("default").unwrap(), pkid);
+    assert_eq!(diddir.get_aliases(pkid).unwrap().len(), 2);
+
+    // removing the identity removes its aliases
+    diddir.remove_identity(pkid).unwrap();
+    assert_eq!(diddir.get_identities(), None);
+    assert_eq!(diddir.get_aliases(pkid), None);
+    assert!(diddir.get_pkid_from_alias("default").is_err());
+}
+
+#[test]
+fn diddir_with_storage() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::with_storage(Box::new(FsStorage::init(&config).unwrap()));
+
+    diddir.save_identity("foo", "{}").unwrap();
+    assert!(config.root_dir().join("foo").is_file());
+    assert!(diddir.storage().has_identity("foo").unwrap());
+}
+
+#[test]
+fn diddir_in_memory_not_watchable() {
+    let mut diddir = DIDDir::in_memory();
+    let err = diddir.watch(WatchMode::Native).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn storage_records() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut fs = FsStorage::init(&config).unwrap();
+    let mut memory = MemoryStorage::new();
+
+    for storage in [&mut fs as &mut dyn Storage, &mut memory as &mut dyn Storage].iter_mut() {
+        assert!(storage.records("tombstones/aliases").unwrap().is_empty());
+        storage.write_record("tombstones/aliases", "foo", "abc").unwrap();
+        assert_eq!(storage.records("tombstones/aliases").unwrap(), vec!["foo".to_string()]);
+        assert_eq!(storage.read_record("tombstones/aliases", "foo").unwrap(), "abc");
+        assert!(storage.records("tombstones/identities").unwrap().is_empty());
+
+        storage.delete_record("tombstones/aliases", "foo").unwrap();
+        let err = storage.read_record("tombstones/aliases", "foo").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    // records stay out of sight of identities and aliases
+    assert!(config.root_dir().join(".tombstones").join("aliases").is_dir());
+    assert!(fs.identities().unwrap().is_empty());
+    assert!(fs.write_record("../escape", "foo", "").is_err());
+    assert!(fs.write_record("tombstones", "../foo", "").is_err());
+    FsStorage::open(&config).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn storage_skips_names_that_arent_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut storage = FsStorage::init(&config).unwrap();
+    storage.write_identity("foo", "{}").unwrap();
+    storage.write_record("tombstones/identities", "bar", "x").unwrap();
+
+    // strays nobody in DIDDir could have written
+    let stray = OsStr::from_bytes(b"stray-\xff");
+    for path in [config.root_dir().join(".tombstones").join("identities").join(stray), config.root_dir().join(stray)].iter() {
+        std::fs::write(path, "x").unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    assert_eq!(storage.records("tombstones/identities").unwrap(), vec!["bar"]);
+    let storage = FsStorage::open(&config).unwrap();
+    assert_eq!(storage.identities().unwrap(), vec!["foo"]);
+}
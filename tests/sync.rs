@@ -0,0 +1,179 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Archive, Config, DIDDir, ExportOptions, Replica};
+use diddir::sync::{self, Change, Entry, PreferLeft, PreferRight, Resolution, SyncConflict, SyncState};
+use tempfile::tempdir;
+
+fn identity(pkid: &str) -> Entry {
+    Entry::Identity(pkid.to_string())
+}
+
+fn alias(alias: &str) -> Entry {
+    Entry::Alias(alias.to_string())
+}
+
+/// Two DIDDirs that start out in sync.
+fn replicas() -> (DIDDir<'static>, DIDDir<'static>, SyncState) {
+    let mut laptop = DIDDir::in_memory();
+    laptop.save_identity("chad", "{\"v\": 1}").unwrap();
+    laptop.save_identity("stacy", "{\"v\": 1}").unwrap();
+    laptop.save_alias("default", "chad").unwrap();
+
+    let mut server = DIDDir::in_memory();
+    let report = sync::sync(&mut laptop, &mut server, None, &mut PreferLeft).unwrap();
+    assert_eq!(report.to_right.len(), 3);
+    assert!(report.conflicts.is_empty());
+    (laptop, server, report.state)
+}
+
+#[test]
+fn sync_additions_and_updates() {
+    let (mut laptop, mut server, state) = replicas();
+
+    laptop.save_identity("new", "{}").unwrap();
+    laptop.save_alias("new", "new").unwrap();
+    server.save_identity("stacy", "{\"v\": 2}").unwrap();
+
+    let plan = sync::plan(&laptop, &server, Some(&state)).unwrap();
+    assert_eq!(plan.to_left, vec![Change::Put(identity("stacy"), "{\"v\": 2}".to_string())]);
+    assert_eq!(plan.to_right, vec![
+        Change::Put(identity("new"), "{}".to_string()),
+        Change::Put(alias("new"), "new".to_string())
+    ]);
+    assert!(plan.conflicts.is_empty());
+
+    sync::sync(&mut laptop, &mut server, Some(&state), &mut PreferLeft).unwrap();
+    assert_eq!(laptop.entries().unwrap(), server.entries().unwrap());
+    assert_eq!(laptop.get_identity("stacy").unwrap(), "{\"v\": 2}");
+}
+
+#[test]
+fn sync_removals() {
+    let (mut laptop, mut server, _) = replicas();
+
+    // tombstones tell a removal apart from an addition, even without a base
+    server.remove_identity("chad").unwrap();
+    let plan = sync::plan(&laptop, &server, None).unwrap();
+    assert_eq!(plan.to_left, vec![Change::Remove(identity("chad")), Change::Remove(alias("default"))]);
+    assert!(plan.to_right.is_empty() && plan.conflicts.is_empty());
+
+    sync::sync(&mut laptop, &mut server, None, &mut PreferLeft).unwrap();
+    assert!(laptop.get_identity("chad").is_err());
+    assert!(laptop.get_pkid_from_alias("default").is_err());
+    assert_eq!(laptop.entries().unwrap(), server.entries().unwrap());
+}
+
+#[test]
+fn sync_conflicts() {
+    let (mut laptop, mut server, state) = replicas();
+
+    laptop.save_identity("chad", "{\"v\": \"laptop\"}").unwrap();
+    server.save_identity("chad", "{\"v\": \"server\"}").unwrap();
+    laptop.save_identity("stacy", "{\"v\": 2}").unwrap();
+    server.remove_identity("stacy").unwrap();
+
+    let plan = sync::plan(&laptop, &server, Some(&state)).unwrap();
+    assert!(plan.to_left.is_empty() && plan.to_right.is_empty());
+    assert_eq!(plan.conflicts, vec![
+        SyncConflict {
+            entry: identity("chad"),
+            left: Some("{\"v\": \"laptop\"}".to_string()),
+            right: Some("{\"v\": \"server\"}".to_string())
+        },
+        SyncConflict {
+            entry: identity("stacy"),
+            left: Some("{\"v\": 2}".to_string()),
+            right: None
+        }
+    ]);
+
+    // skipped conflicts stay conflicts next time
+    let mut skip = |_: &SyncConflict| Resolution::Skip;
+    let report = sync::sync(&mut laptop, &mut server, Some(&state), &mut skip).unwrap();
+    assert_eq!(report.conflicts.len(), 2);
+    assert_eq!(sync::plan(&laptop, &server, Some(&report.state)).unwrap().conflicts.len(), 2);
+
+    // the resolver decides, not who wrote last
+    let mut resolver = |c: &SyncConflict| {
+        if c.entry == identity("chad") { Resolution::Right } else { Resolution::Left }
+    };
+    let report = sync::sync(&mut laptop, &mut server, Some(&report.state), &mut resolver).unwrap();
+    assert_eq!(report.conflicts.len(), 2);
+    assert_eq!(laptop.get_identity("chad").unwrap(), "{\"v\": \"server\"}");
+    assert_eq!(server.get_identity("stacy").unwrap(), "{\"v\": 2}");
+    assert_eq!(laptop.entries().unwrap(), server.entries().unwrap());
+    assert!(sync::plan(&laptop, &server, Some(&report.state)).unwrap().conflicts.is_empty());
+}
+
+#[test]
+fn sync_archive() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.save_identity("chad", "{}").unwrap();
+    diddir.save_identity("stacy", "{}").unwrap();
+    diddir.save_alias("default", "chad").unwrap();
+
+    let mut buf = Vec::new();
+    diddir.export(&mut buf, &ExportOptions::default()).unwrap();
+    let mut archive = Archive::read(&buf[..]).unwrap();
+
+    // removals on disk are tombstoned and travel in archives
+    diddir.remove_identity("stacy").unwrap();
+    archive.identities.insert("bob".to_string(), "{}".to_string());
+    archive.manifest.insert("identities/bob".to_string(),
+        "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a".to_string());
+
+    sync::sync(&mut diddir, &mut archive, None, &mut PreferRight).unwrap();
+    assert_eq!(diddir.get_identity("bob").unwrap(), "{}");
+    assert!(!archive.identities.contains_key("stacy"));
+    assert!(archive.tombstones.contains_key("identities/stacy"));
+    assert_eq!(diddir.entries().unwrap(), archive.entries().unwrap());
+    archive.validate().unwrap();
+
+    // the tombstones survive a round trip through an archive
+    let mut buf = Vec::new();
+    diddir.export(&mut buf, &ExportOptions::default()).unwrap();
+    let archive = Archive::read(&buf[..]).unwrap();
+    assert!(archive.tombstones().unwrap().contains_key(&identity("stacy")));
+
+    // and re-adding forgets the removal
+    diddir.save_identity("stacy", "{}").unwrap();
+    assert!(!diddir.tombstones().unwrap().contains_key(&identity("stacy")));
+}
+
+#[test]
+fn sync_concurrent_write() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut laptop = DIDDir::init(&config).unwrap();
+    laptop.save_identity("chad", "{\"v\": 1}").unwrap();
+    laptop.save_identity("stacy", "{\"v\": 1}").unwrap();
+    let mut server = DIDDir::in_memory();
+    let state = sync::sync(&mut laptop, &mut server, None, &mut PreferLeft).unwrap().state;
+
+    server.save_identity("chad", "{\"v\": 2}").unwrap();
+    server.save_identity("stacy", "{\"v\": 2}").unwrap();
+    laptop.save_identity("chad", "{\"v\": 3}").unwrap();
+
+    // another process writes to the laptop while the conflict is resolved,
+    // after the sync was planned
+    let mut other = DIDDir::open(&config).unwrap();
+    let mut resolver = |_: &SyncConflict| {
+        other.save_identity("stacy", "{\"v\": 4}").unwrap();
+        Resolution::Right
+    };
+    let err = sync::sync(&mut laptop, &mut server, Some(&state), &mut resolver).unwrap_err();
+    assert!(err.to_string().contains("identities/stacy"));
+
+    // nothing of the planned changes went through on that side
+    laptop.refresh().unwrap();
+    assert_eq!(laptop.get_identity("stacy").unwrap(), "{\"v\": 4}");
+    assert_eq!(laptop.get_identity("chad").unwrap(), "{\"v\": 3}");
+
+    // and the next sync sorts it out
+    sync::sync(&mut laptop, &mut server, Some(&state), &mut PreferLeft).unwrap();
+    assert_eq!(laptop.entries().unwrap(), server.entries().unwrap());
+    assert_eq!(server.get_identity("stacy").unwrap(), "{\"v\": 4}");
+}
@@ -0,0 +1,201 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{AuditOp, AuditQuery, Config, DIDDir};
+use std::fs;
+use std::io;
+use std::process::Command;
+use tempfile::tempdir;
+
+#[test]
+fn transaction_commits() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    config.set_audit_log(true);
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.save_identity("old", "{}").unwrap();
+    diddir.save_alias("old-alias", "old").unwrap();
+
+    let n = diddir.transaction(|tx| {
+        tx.save_identity("foo", "{}")?;
+        tx.save_alias("work", "foo")?;
+        tx.save_alias("home", "foo")?;
+        // reads see what is staged
+        assert_eq!(tx.get_pkid_from_alias("work")?, "foo");
+        assert_eq!(tx.get_identity("foo")?, "{}");
+
+        tx.remove_identity("old")?;
+        assert!(tx.get_pkid_from_alias("old-alias").is_err());
+        Ok(3)
+    }).unwrap();
+    assert_eq!(n, 3);
+
+    assert_eq!(diddir.get_identities(), Some(vec!["foo".to_string()]));
+    let mut aliases = diddir.get_aliases("foo").unwrap();
+    aliases.sort();
+    assert_eq!(aliases, vec!["home", "work"]);
+    assert!(diddir.get_pkid_from_alias("old-alias").is_err());
+
+    // other DIDDirs see all of it too
+    let other = DIDDir::open(&config).unwrap();
+    assert_eq!(other.get_pkid_from_alias("home").unwrap(), "foo");
+
+    // and the bookkeeping was done for each change
+    assert_eq!(diddir.history("foo").unwrap().len(), 1);
+    let ops: Vec<_> = diddir.audit_entries(&AuditQuery::default()).unwrap()
+        .iter().skip(2).map(|e| e.op).collect();
+    assert_eq!(ops, vec![
+        AuditOp::RemoveAlias,
+        AuditOp::RemoveIdentity,
+        AuditOp::SaveIdentity,
+        AuditOp::SaveAlias,
+        AuditOp::SaveAlias
+    ]);
+    diddir.verify_audit_log().unwrap();
+    assert!(!config.root_dir().join(".transaction").join("journal").exists());
+}
+
+#[test]
+fn transaction_rolls_back() {
+    let mut diddir = DIDDir::in_memory();
+    diddir.save_identity("foo", "{}").unwrap();
+
+    // an error from the closure
+    let err = diddir.transaction(|tx| {
+        tx.save_identity("foo", "{\"v\": 2}")?;
+        tx.save_alias("foo", "foo")?;
+        Err::<(), _>(io::Error::other("changed my mind"))
+    }).unwrap_err();
+    assert_eq!(err.to_string(), "changed my mind");
+
+    // an invalid change
+    let err = diddir.transaction(|tx| {
+        tx.save_identity("bar", "{}")?;
+        tx.remove_alias("missing")
+    }).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    assert_eq!(diddir.get_identity("foo").unwrap(), "{}");
+    assert_eq!(diddir.get_identities(), Some(vec!["foo".to_string()]));
+    assert!(diddir.get_aliases("foo").is_none());
+    assert_eq!(diddir.history("foo").unwrap().len(), 1);
+}
+
+#[test]
+fn transaction_metadata() {
+    let mut diddir = DIDDir::in_memory();
+
+    // nothing staged is kept when the closure fails
+    let err = diddir.transaction(|tx| {
+        tx.save_identity("foo", "{}")?;
+        tx.update_metadata("foo", |m| m.notes = Some("new".to_string()))?;
+        Err::<(), _>(io::Error::other("changed my mind"))
+    }).unwrap_err();
+    assert_eq!(err.to_string(), "changed my mind");
+    assert!(diddir.metadata("foo").is_err());
+
+    diddir.transaction(|tx| {
+        tx.save_identity("foo", "{}")?;
+        tx.update_metadata("foo", |m| m.notes = Some("new".to_string()))?;
+        // and only for identities there by then
+        assert!(tx.update_metadata("bar", |_| {}).is_err());
+        Ok(())
+    }).unwrap();
+    assert_eq!(diddir.metadata("foo").unwrap().notes.as_deref(), Some("new"));
+}
+
+#[test]
+fn transaction_recovers() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    {
+        let mut diddir = DIDDir::init(&config).unwrap();
+        diddir.save_identity("foo", "{}").unwrap();
+        diddir.save_alias("default", "foo").unwrap();
+    }
+
+    // a transaction that died halfway through: foo was changed, default
+    // removed and bar added, but the journal is still there
+    {
+        let mut diddir = DIDDir::open(&config).unwrap();
+        diddir.save_identity("foo", "{\"v\": 2}").unwrap();
+        diddir.remove_alias("default").unwrap();
+        diddir.save_identity("bar", "{}").unwrap();
+    }
+    let root = config.root_dir();
+    let journal = root.join(".transaction").join("journal");
+    fs::create_dir(root.join(".transaction")).unwrap();
+    fs::write(&journal,
+        "{\"before\":{\"identities/foo\":\"{}\",\"aliases/default\":\"foo\",\"identities/bar\":null}}").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(root.join(".transaction"), fs::Permissions::from_mode(0o700)).unwrap();
+        fs::set_permissions(&journal, fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    let diddir = DIDDir::open(&config).unwrap();
+    assert_eq!(diddir.get_identity("foo").unwrap(), "{}");
+    assert_eq!(diddir.get_pkid_from_alias("default").unwrap(), "foo");
+    assert!(diddir.get_identity("bar").is_err());
+    assert!(!journal.exists());
+}
+
+#[test]
+fn transaction_recovers_bookkeeping() {
+    let dir = tempdir().unwrap();
+    let mut config = Config::with_path(dir.path());
+    config.set_audit_log(true);
+    {
+        let mut diddir = DIDDir::init(&config).unwrap();
+        diddir.save_identity("foo", "{}").unwrap();
+    }
+
+    // a transaction that died after bar was added, before it was noted
+    let root = config.root_dir();
+    let journal = root.join(".transaction").join("journal");
+    fs::create_dir(root.join(".transaction")).unwrap();
+    fs::write(root.join("bar"), "{}").unwrap();
+    fs::write(&journal,
+        "{\"before\":{\"identities/bar\":null},\"unrecorded\":{\"identities/bar\":\"{}\"}}").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(root.join(".transaction"), fs::Permissions::from_mode(0o700)).unwrap();
+        fs::set_permissions(&journal, fs::Permissions::from_mode(0o600)).unwrap();
+        fs::set_permissions(root.join("bar"), fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    // the change stays and gets its bookkeeping
+    let diddir = DIDDir::open(&config).unwrap();
+    assert_eq!(diddir.get_identity("bar").unwrap(), "{}");
+    assert_eq!(diddir.history("bar").unwrap().len(), 1);
+    let ops: Vec<_> = diddir.audit_entries(&AuditQuery::default()).unwrap()
+        .iter().map(|e| e.op).collect();
+    assert_eq!(ops.last(), Some(&AuditOp::SaveIdentity));
+    assert_eq!(ops.len(), 2);
+    diddir.verify_audit_log().unwrap();
+    assert!(!journal.exists());
+}
+
+#[test]
+fn transaction_git_commit() {
+    if Command::new("git").arg("--version").output().is_err() {
+        return;
+    }
+
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(&dir.path().join("diddir"));
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.init_git().unwrap();
+    diddir.transaction(|tx| {
+        tx.save_identity("foo", "{}")?;
+        tx.save_alias("work", "foo")?;
+        tx.save_alias("home", "foo")
+    }).unwrap();
+
+    let output = Command::new("git").arg("-C").arg(config.root_dir())
+        .args(["log", "--format=%s"]).output().unwrap();
+    let log = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(log.lines().collect::<Vec<_>>(), vec!["Apply 3 changes", "Start keeping DIDDir in git"]);
+}
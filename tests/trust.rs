@@ -0,0 +1,186 @@
+extern crate diddir;
+
+use diddir::{AuditOp, AuditQuery, DIDDir, Trust, TrustLevel};
+use diddir::trust::SigningKey;
+use std::io;
+
+fn key(seed: u8) -> SigningKey {
+    SigningKey::from_bytes(&[seed; 32])
+}
+
+fn doc(name: &str, key: &SigningKey) -> String {
+    format!(r#"{{
+        "@context": "https://w3id.org/did/v1",
+        "id": "did:example:{name}",
+        "publicKey": [{{
+            "id": "did:example:{name}#keys-1",
+            "type": "Ed25519VerificationKey2018",
+            "controller": "did:example:{name}",
+            "publicKeyBase58": "{key}"
+        }}]
+    }}"#, name = name, key = bs58::encode(key.verifying_key().to_bytes()).into_string())
+}
+
+fn key_id(name: &str) -> String {
+    format!("did:example:{}#keys-1", name)
+}
+
+/// Our own identity `me` plus alice, bob and carol, each with a key seeded
+/// by their position.
+fn keyring() -> DIDDir<'static> {
+    let mut diddir = DIDDir::in_memory();
+    for (seed, name) in ["me", "alice", "bob", "carol"].iter().enumerate() {
+        diddir.save_identity(name, &doc(name, &key(seed as u8))).unwrap();
+        diddir.save_alias(name, name).unwrap();
+    }
+    diddir.update_metadata("me", |m| m.trust = TrustLevel::Ultimate).unwrap();
+    diddir
+}
+
+#[test]
+fn trust_direct() {
+    let mut diddir = keyring();
+    assert_eq!(diddir.trust_of("me").unwrap(), Trust::Own);
+    assert_eq!(diddir.trust_of("alice").unwrap(), Trust::Unknown);
+
+    let attestation = diddir.attest("me", &key_id("me"), &key(0), "alice", "alice", 0).unwrap();
+    assert_eq!(attestation.name, "alice");
+    assert_eq!(diddir.attestations("alice").unwrap(), vec![attestation]);
+    assert_eq!(diddir.trust_of("alice").unwrap(), Trust::Attested { path: vec!["me".into(), "alice".into()] });
+
+    // alice can't introduce anyone at depth 0
+    diddir.attest("alice", &key_id("alice"), &key(1), "bob", "bob", 0).unwrap();
+    assert_eq!(diddir.trust_of("bob").unwrap(), Trust::Unknown);
+
+    // changing alice's document voids the attestation
+    diddir.save_identity("alice", &doc("alice", &key(9))).unwrap();
+    assert_eq!(diddir.trust_of("alice").unwrap(), Trust::Unknown);
+
+    assert_eq!(diddir.trust_of("nobody").unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn trust_introducers() {
+    let mut diddir = keyring();
+    diddir.attest("me", &key_id("me"), &key(0), "alice", "alice", 1).unwrap();
+    diddir.attest("alice", &key_id("alice"), &key(1), "bob", "bob", 5).unwrap();
+    diddir.attest("bob", &key_id("bob"), &key(2), "carol", "carol", 0).unwrap();
+
+    // alice may introduce one level, so bob is trusted but can't introduce
+    assert_eq!(diddir.trust_of("bob").unwrap(),
+        Trust::Attested { path: vec!["me".into(), "alice".into(), "bob".into()] });
+    assert_eq!(diddir.trust_of("carol").unwrap(), Trust::Unknown);
+
+    diddir.attest("me", &key_id("me"), &key(0), "alice", "alice", 2).unwrap();
+    assert_eq!(diddir.trust_of("carol").unwrap(),
+        Trust::Attested { path: vec!["me".into(), "alice".into(), "bob".into(), "carol".into()] });
+
+    // taking it back cuts off everyone alice introduced
+    diddir.revoke_attestation("me", &key_id("me"), &key(0), "alice").unwrap();
+    assert_eq!(diddir.trust_of("alice").unwrap(), Trust::Revoked);
+    assert_eq!(diddir.trust_of("bob").unwrap(), Trust::Unknown);
+    assert_eq!(diddir.trust_of("carol").unwrap(), Trust::Unknown);
+}
+
+#[test]
+fn trust_alias_binding() {
+    let mut diddir = keyring();
+
+    // the name has to be an alias of the subject
+    let err = diddir.attest("me", &key_id("me"), &key(0), "alice", "bob", 0).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(diddir.attest("me", &key_id("me"), &key(0), "alice", "nobody", 0).is_err());
+
+    diddir.attest("me", &key_id("me"), &key(0), "alice", "alice", 0).unwrap();
+    assert_eq!(diddir.trust_of("alice").unwrap(), Trust::Attested { path: vec!["me".into(), "alice".into()] });
+
+    // pointing the alias elsewhere voids it, pointing it back restores it
+    diddir.save_alias("alice", "bob").unwrap();
+    assert_eq!(diddir.trust_of("alice").unwrap(), Trust::Unknown);
+    assert_eq!(diddir.trust_of("bob").unwrap(), Trust::Unknown);
+    diddir.save_alias("alice", "alice").unwrap();
+    assert_eq!(diddir.trust_of("alice").unwrap(), Trust::Attested { path: vec!["me".into(), "alice".into()] });
+}
+
+#[test]
+fn trust_untrusted_revocations() {
+    let mut diddir = keyring();
+    diddir.attest("me", &key_id("me"), &key(0), "alice", "alice", 0).unwrap();
+
+    // bob isn't trusted, so his word about carol counts for nothing
+    diddir.attest("bob", &key_id("bob"), &key(2), "carol", "carol", 0).unwrap();
+    diddir.revoke_attestation("bob", &key_id("bob"), &key(2), "carol").unwrap();
+    assert_eq!(diddir.trust_of("carol").unwrap(), Trust::Unknown);
+
+    // alice is trusted, so hers does
+    diddir.revoke_attestation("alice", &key_id("alice"), &key(1), "carol").unwrap();
+    assert_eq!(diddir.trust_of("carol").unwrap(), Trust::Revoked);
+}
+
+#[test]
+fn trust_never_subject() {
+    let mut diddir = keyring();
+    diddir.attest("me", &key_id("me"), &key(0), "alice", "alice", 0).unwrap();
+    diddir.update_metadata("alice", |m| m.trust = TrustLevel::Never).unwrap();
+    assert_eq!(diddir.trust_of("alice").unwrap(), Trust::Never);
+
+    diddir.update_metadata("alice", |m| m.trust = TrustLevel::Unknown).unwrap();
+    assert_eq!(diddir.trust_of("alice").unwrap(), Trust::Attested { path: vec!["me".into(), "alice".into()] });
+}
+
+#[test]
+fn trust_never_introducer() {
+    let mut diddir = keyring();
+    diddir.attest("me", &key_id("me"), &key(0), "alice", "alice", 2).unwrap();
+    diddir.attest("alice", &key_id("alice"), &key(1), "bob", "bob", 1).unwrap();
+    diddir.attest("bob", &key_id("bob"), &key(2), "carol", "carol", 0).unwrap();
+    assert_eq!(diddir.trust_of("carol").unwrap(),
+        Trust::Attested { path: vec!["me".into(), "alice".into(), "bob".into(), "carol".into()] });
+
+    // bob can't vouch for carol, nor take anything back, once he is never trusted
+    diddir.update_metadata("bob", |m| m.trust = TrustLevel::Never).unwrap();
+    assert_eq!(diddir.trust_of("carol").unwrap(), Trust::Unknown);
+    diddir.revoke_attestation("bob", &key_id("bob"), &key(2), "carol").unwrap();
+    assert_eq!(diddir.trust_of("carol").unwrap(), Trust::Unknown);
+}
+
+#[test]
+fn trust_signatures() {
+    let mut diddir = keyring();
+
+    // the signing key has to be the one in the issuer's document
+    let err = diddir.attest("me", &key_id("me"), &key(1), "alice", "alice", 0).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = diddir.attest("me", "did:example:me#keys-2", &key(0), "alice", "alice", 0).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    // attestations made elsewhere are checked before they are stored
+    let mut other = keyring();
+    let attestation = other.attest("me", &key_id("me"), &key(0), "alice", "alice", 0).unwrap();
+    let mut forged = attestation.clone();
+    forged.depth = 9;
+    let err = diddir.add_attestation(&forged).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    forged.id = attestation.id.clone();
+    forged.depth = attestation.depth;
+    forged.signature = other.attest("me", &key_id("me"), &key(0), "bob", "bob", 0).unwrap().signature;
+    assert!(diddir.add_attestation(&forged).is_err());
+
+    diddir.add_attestation(&attestation).unwrap();
+    assert_eq!(diddir.trust_of("alice").unwrap(), Trust::Attested { path: vec!["me".into(), "alice".into()] });
+}
+
+#[test]
+fn trust_audited() {
+    let mut diddir = keyring();
+    diddir.set_audit_log(true);
+    let attestation = diddir.attest("me", &key_id("me"), &key(0), "alice", "alice", 0).unwrap();
+    let revocation = diddir.revoke_attestation("me", &key_id("me"), &key(0), "alice").unwrap();
+
+    let entries = diddir.audit_entries(&AuditQuery::default()).unwrap();
+    let ops: Vec<_> = entries.iter().map(|e| e.op).collect();
+    assert_eq!(ops, vec![AuditOp::Attest, AuditOp::Revoke]);
+    assert!(entries.iter().all(|e| e.pkid.as_deref() == Some("alice")));
+    assert_eq!(entries[0].after.as_ref(), Some(&attestation.id));
+    assert_eq!(entries[1].after.as_ref(), Some(&revocation.id));
+}
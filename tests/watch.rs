@@ -0,0 +1,112 @@
+extern crate diddir;
+extern crate tempfile;
+
+use diddir::{Config, DIDDir};
+use diddir::dir::watch::{Event, WatchMode};
+use tempfile::tempdir;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn watch_not_started() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+
+    assert_eq!(diddir.watch_mode(), None);
+    let err = diddir.poll_events().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn watch_native() {
+    watch_external_changes(WatchMode::Native);
+}
+
+#[test]
+fn watch_poll() {
+    watch_external_changes(WatchMode::Poll(Duration::from_millis(20)));
+}
+
+#[test]
+fn watch_quiet() {
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut diddir = DIDDir::init(&config).unwrap();
+    diddir.watch(WatchMode::Native).unwrap();
+
+    // nothing happened so nothing to report
+    assert_eq!(diddir.wait_events(Duration::from_millis(50)).unwrap(), vec![]);
+}
+
+fn watch_external_changes(mode: WatchMode) {
+    let pkid = "8b69351b707a187559ef7e87d898430dc016680c52b36e23d8703a2e030b30dd".to_string();
+    let alias = "stacy.jones@no.email".to_string();
+
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut watcher = DIDDir::init(&config).unwrap();
+    watcher.watch(mode).unwrap();
+    assert!(watcher.watch_mode().is_some());
+
+    // another DIDDir stands in for another process
+    let mut writer = DIDDir::open(&config).unwrap();
+
+    // add
+    writer.save_identity(&pkid, "{\"name\": \"Stacy\"}").unwrap();
+    writer.save_alias(&alias, &pkid).unwrap();
+    let events = wait_for(&mut watcher, 2);
+    assert!(events.contains(&Event::IdentityAdded(pkid.clone())));
+    assert!(events.contains(&Event::AliasAdded { alias: alias.clone(), pkid: pkid.clone() }));
+    assert_eq!(watcher.get_pkid_from_alias(&alias).unwrap(), pkid);
+    assert_eq!(watcher.get_identity(&pkid).unwrap(), "{\"name\": \"Stacy\"}");
+
+    // update, to a document of the same size
+    writer.save_identity(&pkid, "{\"name\": \"Tracy\"}").unwrap();
+    let events = wait_for(&mut watcher, 1);
+    assert_eq!(events, vec![Event::IdentityUpdated(pkid.clone())]);
+
+    // remove
+    writer.remove_identity(&pkid).unwrap();
+    let events = wait_for(&mut watcher, 2);
+    assert!(events.contains(&Event::IdentityRemoved(pkid.clone())));
+    assert!(events.contains(&Event::AliasRemoved { alias: alias.clone(), pkid: pkid.clone() }));
+    assert_eq!(watcher.get_identities(), None);
+}
+
+#[test]
+fn watch_keeps_current() {
+    let pkid = "8b69351b707a187559ef7e87d898430dc016680c52b36e23d8703a2e030b30dd";
+
+    let dir = tempdir().unwrap();
+    let config = Config::with_path(dir.path());
+    let mut watcher = DIDDir::init(&config).unwrap();
+    watcher.watch(WatchMode::Poll(Duration::from_millis(20))).unwrap();
+    let mut writer = DIDDir::open(&config).unwrap();
+
+    // lookups catch up on their own, without polling for events
+    writer.save_identity(pkid, "{}").unwrap();
+    writer.save_alias("stacy", pkid).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while watcher.get_pkid_from_alias("stacy").is_err() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(watcher.get_pkid_from_alias("stacy").unwrap(), pkid);
+    assert_eq!(watcher.get_identity(pkid).unwrap(), "{}");
+
+    // and what they picked up is still reported
+    let events = watcher.poll_events().unwrap();
+    assert!(events.contains(&Event::IdentityAdded(pkid.to_string())));
+    assert_eq!(watcher, writer);
+}
+
+fn wait_for(diddir: &mut DIDDir, count: usize) -> Vec<Event> {
+    // notifications can arrive spread out over several polls
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut events = Vec::new();
+    while events.len() < count && Instant::now() < deadline {
+        events.extend(diddir.wait_events(Duration::from_millis(100)).unwrap());
+    }
+    events
+}